@@ -0,0 +1,21 @@
+CREATE INDEX IF NOT EXISTS events_main_event_id ON events(main_event_id);
+CREATE INDEX IF NOT EXISTS events_type_time ON events(type, time);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS events_fts USING fts5(
+    args,
+    content = 'events',
+    content_rowid = 'id',
+    tokenize = 'trigram'
+);
+INSERT INTO events_fts(events_fts) VALUES ('rebuild');
+
+CREATE TRIGGER IF NOT EXISTS events_fts_insert AFTER INSERT ON events BEGIN
+    INSERT INTO events_fts(rowid, args) VALUES (new.id, new.args);
+END;
+CREATE TRIGGER IF NOT EXISTS events_fts_delete AFTER DELETE ON events BEGIN
+    INSERT INTO events_fts(events_fts, rowid, args) VALUES ('delete', old.id, old.args);
+END;
+CREATE TRIGGER IF NOT EXISTS events_fts_update AFTER UPDATE OF args ON events BEGIN
+    INSERT INTO events_fts(events_fts, rowid, args) VALUES ('delete', old.id, old.args);
+    INSERT INTO events_fts(rowid, args) VALUES (new.id, new.args);
+END;
@@ -0,0 +1 @@
+ALTER TABLE events ADD COLUMN repeat_count integer NOT NULL DEFAULT 1;
@@ -0,0 +1,30 @@
+CREATE TABLE IF NOT EXISTS mutations (
+    id integer PRIMARY KEY AUTOINCREMENT NOT NULL,
+    time datetime NOT NULL,
+    kind text NOT NULL,
+    cmd text NOT NULL,
+    open boolean NOT NULL DEFAULT TRUE
+);
+CREATE TABLE IF NOT EXISTS mutation_events (
+    id integer PRIMARY KEY AUTOINCREMENT NOT NULL,
+    mutation_id integer NOT NULL,
+    event_id integer NOT NULL,
+    ignored boolean NOT NULL,
+    humble boolean NOT NULL,
+    args text,
+    envs text
+);
+CREATE INDEX IF NOT EXISTS mutation_events_mutation_id ON mutation_events(mutation_id);
+
+CREATE TRIGGER IF NOT EXISTS events_journal AFTER UPDATE OF ignored, humble, args, envs ON events
+WHEN EXISTS (SELECT 1 FROM mutations WHERE open) AND (
+    old.ignored IS NOT new.ignored OR old.humble IS NOT new.humble
+    OR old.args IS NOT new.args OR old.envs IS NOT new.envs
+)
+BEGIN
+    INSERT INTO mutation_events (mutation_id, event_id, ignored, humble, args, envs)
+    VALUES (
+        (SELECT max(id) FROM mutations WHERE open),
+        old.id, old.ignored, old.humble, old.args, old.envs
+    );
+END;
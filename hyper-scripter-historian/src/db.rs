@@ -8,6 +8,11 @@ pub fn get_file(dir_path: impl AsRef<Path>) -> PathBuf {
     dir_path.as_ref().join(".script_history.db")
 }
 
+/// 以 JSON Lines 儲存歷史時的檔案
+pub fn get_jsonl_file(dir_path: impl AsRef<Path>) -> PathBuf {
+    dir_path.as_ref().join(".script_history.jsonl")
+}
+
 pub async fn get_pool(dir_path: impl AsRef<Path>) -> Result<SqlitePool, sqlx::error::Error> {
     let file = get_file(dir_path);
     let opt = SqliteConnectOptions::new()
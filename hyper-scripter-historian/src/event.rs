@@ -1,13 +1,23 @@
 use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
 use std::path::Path;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+/// 序列化時以類型代碼表示，同 SQLite 的 events 表
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(into = "i8", try_from = "i8")]
 pub enum EventType {
     Exec,
     PreExec,
     ExecDone,
     Read,
     Write,
+    Neglect,
+    Rename,
+    Copy,
+    Retag,
+    TypeChange,
+    Delete,
+    Restore,
 }
 
 #[derive(Debug)]
@@ -15,12 +25,22 @@ pub enum EventData<'a> {
     Exec {
         args: &'a str,
         envs: &'a str,
+        /// 執行時指定的環境檔路徑，以 JSON 陣列記錄，重播時再重新讀取
+        env_files: Option<&'a str>,
+        profile: Option<&'a str>,
         dir: Option<&'a Path>,
+        /// 在另一次執行的腳本中被呼叫時，外層的執行事件 id
+        parent: Option<i64>,
     },
     PreExec {
         args: &'a str,
         envs: &'a str,
+        /// 執行時指定的環境檔路徑，以 JSON 陣列記錄，重播時再重新讀取
+        env_files: Option<&'a str>,
+        profile: Option<&'a str>,
         dir: Option<&'a Path>,
+        /// 在另一次執行的腳本中被呼叫時，外層的執行事件 id
+        parent: Option<i64>,
     },
     ExecDone {
         code: i32,
@@ -28,6 +48,29 @@ pub enum EventData<'a> {
     },
     Read,
     Write,
+    Neglect,
+    /// 改名，記錄新舊名字
+    Rename {
+        from: &'a str,
+        to: &'a str,
+    },
+    /// 由 `from` 這支腳本複製而來，記在新腳本身上
+    Copy {
+        from: i64,
+    },
+    /// 標籤的增減，如 `+a -b`
+    Retag {
+        diff: &'a str,
+    },
+    TypeChange {
+        from: &'a str,
+        to: &'a str,
+    },
+    /// 刪除（包含以標籤隱藏），記錄刪除當下的名字
+    Delete {
+        name: &'a str,
+    },
+    Restore,
 }
 
 impl EventData<'_> {
@@ -38,6 +81,13 @@ impl EventData<'_> {
             EventData::ExecDone { .. } => EventType::ExecDone,
             EventData::Read => EventType::Read,
             EventData::Write => EventType::Write,
+            EventData::Neglect => EventType::Neglect,
+            EventData::Rename { .. } => EventType::Rename,
+            EventData::Copy { .. } => EventType::Copy,
+            EventData::Retag { .. } => EventType::Retag,
+            EventData::TypeChange { .. } => EventType::TypeChange,
+            EventData::Delete { .. } => EventType::Delete,
+            EventData::Restore => EventType::Restore,
         }
     }
 }
@@ -51,8 +101,49 @@ impl EventType {
             Exec => 3,
             ExecDone => 4,
             PreExec => 5,
+            Neglect => 6,
+            Rename => 7,
+            Copy => 8,
+            Retag => 9,
+            TypeChange => 10,
+            Delete => 11,
+            Restore => 12,
         }
     }
+    /// 改名、複製、改標籤等改變腳本本身的事件，不影響最新時間，也不會被 tidy 掉
+    pub const fn is_structural(&self) -> bool {
+        self.get_code() >= EventType::Rename.get_code()
+    }
+    pub const fn from_code(code: i8) -> Option<Self> {
+        use EventType::*;
+        Some(match code {
+            0 => Read,
+            1 => Write,
+            3 => Exec,
+            4 => ExecDone,
+            5 => PreExec,
+            6 => Neglect,
+            7 => Rename,
+            8 => Copy,
+            9 => Retag,
+            10 => TypeChange,
+            11 => Delete,
+            12 => Restore,
+            _ => return None,
+        })
+    }
+}
+
+impl From<EventType> for i8 {
+    fn from(ty: EventType) -> Self {
+        ty.get_code()
+    }
+}
+impl TryFrom<i8> for EventType {
+    type Error = String;
+    fn try_from(code: i8) -> Result<Self, Self::Error> {
+        EventType::from_code(code).ok_or_else(|| format!("unknown event type {}", code))
+    }
 }
 
 #[derive(Debug)]
@@ -1,14 +1,19 @@
 use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::error::Error as DBError;
 use sqlx::migrate::MigrateError;
-use sqlx::{error::Error as DBError, Pool, Sqlite, SqlitePool};
 use std::num::NonZeroU64;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
 
 mod db;
 mod event;
 pub mod migration;
+mod query;
+mod storage;
 pub use event::*;
+pub use query::*;
+pub use storage::StorageKind;
+use storage::{dispatch, ArgsKey, ArgsQuery, Backend, MemoryStorage, SqliteStorage, Storage};
 
 const ZERO: i64 = 0;
 const EMPTY_STR: &str = "";
@@ -16,52 +21,34 @@ const EMPTY_STR: &str = "";
 const PRE_EXEC_CODE: i8 = EventType::PreExec.get_code();
 const EXEC_CODE: i8 = EventType::Exec.get_code();
 const EXEC_DONE_CODE: i8 = EventType::ExecDone.get_code();
+const DELETE_CODE: i8 = EventType::Delete.get_code();
+const FIRST_STRUCTURAL_CODE: i8 = EventType::Rename.get_code();
+/// 最多保留幾次修改的日誌供 undo 使用
+const MAX_MUTATIONS: i64 = 50;
 
 #[derive(Debug, Clone)]
 pub struct Historian {
-    pool: Arc<RwLock<SqlitePool>>,
-    dir_path: PathBuf,
-}
-
-async fn raw_record_event(pool: &Pool<Sqlite>, event: DBEvent<'_>) -> Result<i64, DBError> {
-    let res = sqlx::query!(
-        "
-        INSERT INTO events
-        (script_id, type, cmd, args, content, time, main_event_id, dir, envs, humble)
-        VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
-        RETURNING id
-        ",
-        event.script_id,
-        event.ty,
-        event.cmd,
-        event.args,
-        event.content,
-        event.time,
-        event.main_event_id,
-        event.dir,
-        event.envs,
-        event.humble
-    )
-    .fetch_one(pool)
-    .await?;
-    Ok(res.id)
+    storage: Backend,
 }
 
 #[derive(Clone, Copy)]
 struct DBEvent<'a> {
     script_id: i64,
-    ty: i8,
+    ty: EventType,
     cmd: &'a str,
     time: NaiveDateTime,
     args: Option<&'a str>,
     dir: Option<&'a str>,
     envs: Option<&'a str>,
+    env_files: Option<&'a str>,
+    profile: Option<&'a str>,
     content: Option<&'a str>,
     humble: bool,
     main_event_id: i64,
+    parent_id: Option<i64>,
 }
 impl<'a> DBEvent<'a> {
-    fn new(script_id: i64, time: NaiveDateTime, ty: i8, cmd: &'a str, humble: bool) -> Self {
+    fn new(script_id: i64, time: NaiveDateTime, ty: EventType, cmd: &'a str, humble: bool) -> Self {
         DBEvent {
             script_id,
             time,
@@ -69,7 +56,10 @@ impl<'a> DBEvent<'a> {
             cmd,
             humble,
             main_event_id: ZERO,
+            parent_id: None,
             envs: None,
+            env_files: None,
+            profile: None,
             content: None,
             args: None,
             dir: None,
@@ -91,6 +81,14 @@ impl<'a> DBEvent<'a> {
         self.envs = Some(value);
         self
     }
+    fn env_files(mut self, value: Option<&'a str>) -> Self {
+        self.env_files = value;
+        self
+    }
+    fn profile(mut self, value: Option<&'a str>) -> Self {
+        self.profile = value;
+        self
+    }
     fn humble(mut self) -> Self {
         self.humble = true;
         self
@@ -99,92 +97,165 @@ impl<'a> DBEvent<'a> {
         self.main_event_id = value;
         self
     }
+    fn parent_id(mut self, value: Option<i64>) -> Self {
+        self.parent_id = value;
+        self
+    }
 }
 
-macro_rules! last_arg {
-    ($select:literal, $offset:expr, $limit:expr, $group_by:literal, $where:literal $(+ $more_where:literal)* , $($var:expr),*) => {{
-        sqlx::query!(
-            "
-            WITH args AS (
-                SELECT " + $select + ", max(time) as time FROM events
-                WHERE type = ? AND NOT ignored "
-                +
-                $where
-                $(+ $more_where)*
-                +
-                " GROUP BY script_id " + $group_by + " ORDER BY time DESC LIMIT ? OFFSET ?
-            ) SELECT "
-                + $select
-                + " FROM args
-            ",
-            EXEC_CODE,
-            $($var, )*
-            $limit,
-            $offset,
-        )
-    }};
+/// 前一次執行的紀錄，各欄位皆為歷史中的原始字串
+#[derive(Debug, Default)]
+pub struct PreviousArgs {
+    pub args: String,
+    pub envs: String,
+    pub env_files: Option<String>,
+    pub profile: Option<String>,
 }
-macro_rules! do_last_arg {
-    ($select:literal, $group_by:literal, $ids:expr, $limit:expr, $offset:expr, $no_humble:expr, $dir:expr, $historian:expr) => {{
-        let ids = join_id_str($ids);
-        log::info!("查詢歷史 {}", ids);
-        let limit = $limit as i64;
-        let offset = $offset as i64;
-        let no_dir = $dir.is_none();
-        let dir = $dir.map(|p| p.to_string_lossy());
-        let dir = dir.as_deref().unwrap_or(EMPTY_STR);
-        // FIXME: 一旦可以綁定陣列就換掉這個醜死人的 instr
-        last_arg!(
-            $select,
-            offset,
-            limit,
-            $group_by,
-            "
-            AND instr(?, '[' || script_id || ']') > 0 AND (? OR dir = ?)
-            AND (NOT ? OR NOT humble)
-            ",
-            ids,
-            no_dir,
-            dir,
-            $no_humble
-        )
-        .fetch_all(&*$historian.pool.read().unwrap())
-        .await
-    }};
+
+/// 依執行結果篩選歷史，沒有返回碼（如被中斷）的事件只會出現在 `All` 中
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum StatusFilter {
+    #[default]
+    All,
+    Failed,
+    Succeeded,
 }
 
-macro_rules! ignore_or_humble_arg {
-    ($ignore_or_humble:literal, $pool:expr, $cond:literal $(+ $more_cond:literal)*, $($var:expr),+) => {
-        sqlx::query!(
-            "
-            UPDATE events SET " + $ignore_or_humble + " = true
-            WHERE type = ? AND main_event_id IN (
-                SELECT id FROM events WHERE (type = ? OR type = ?) AND NOT ignored AND "
-                + $cond $(+ $more_cond)*
-                + "
-            )
-            ",
-            EXEC_DONE_CODE,
-            EXEC_CODE,
-            PRE_EXEC_CODE,
-            $($var),*
-        )
-        .execute(&*$pool)
-        .await?;
-
-        sqlx::query!(
-            "
-            UPDATE events SET " + $ignore_or_humble + " = true
-            WHERE (type = ? OR type = ?) AND NOT ignored AND
-            "
-                + $cond $(+ $more_cond)*,
-            EXEC_CODE,
-            PRE_EXEC_CODE,
-            $($var),*
-        )
-        .execute(&*$pool)
-        .await?;
-    };
+#[derive(Debug)]
+pub struct ArgsRecord {
+    pub event_id: i64,
+    pub script_id: i64,
+    pub args: String,
+    pub envs: String,
+    /// 對應的 ExecDone 事件中的返回碼，若無則代表還在執行或被中斷
+    pub code: Option<i32>,
+}
+
+/// 跨腳本的事件時間軸查詢條件
+#[derive(Debug, Default)]
+pub struct LogQuery<'a> {
+    pub ids: &'a [i64],
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+    /// 只取 id 大於此值的事件，用於持續追蹤新事件
+    pub after_id: Option<i64>,
+    pub limit: Option<u32>,
+    pub offset: u32,
+}
+
+#[derive(Debug)]
+pub struct LogRecord {
+    pub event_id: i64,
+    pub script_id: i64,
+    pub ty: EventType,
+    pub time: NaiveDateTime,
+    pub args: Option<String>,
+    pub dir: Option<String>,
+    pub code: Option<i32>,
+    pub content: Option<String>,
+    /// 被 tidy 合併的連續相同執行次數
+    pub repeat_count: i64,
+    pub humble: bool,
+}
+
+/// 完整的事件，欄位同 SQLite 的 events 表
+///
+/// 匯出、匯入及 JSON Lines 後端皆以此格式序列化
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawEvent {
+    pub id: i64,
+    /// 匯出時以腳本名稱取代，此欄位留空
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub script_id: i64,
+    #[serde(rename = "type")]
+    pub ty: EventType,
+    pub cmd: String,
+    pub time: NaiveDateTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub envs: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_files: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub main_event_id: i64,
+    /// 在另一次執行中被呼叫的執行事件，記錄外層的執行事件 id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<i64>,
+    #[serde(default)]
+    pub humble: bool,
+    #[serde(default)]
+    pub ignored: bool,
+    #[serde(default = "one")]
+    pub repeat_count: i64,
+}
+fn is_zero(n: &i64) -> bool {
+    *n == ZERO
+}
+fn one() -> i64 {
+    1
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: u64,
+    /// 因 dedupe 而略過的事件數
+    pub duplicated: u64,
+    /// 找不到主要事件而略過的事件數
+    pub orphaned: u64,
+    /// 有匯入事件的腳本，其最新時間
+    pub last_times: Vec<LastTimeRecord>,
+}
+
+/// 一次對歷史的修改，如 `history rm`
+#[derive(Debug)]
+pub struct MutationRecord {
+    pub id: i64,
+    pub time: NaiveDateTime,
+    pub kind: String,
+    pub cmd: String,
+    /// 被修改的事件數
+    pub event_count: i64,
+}
+
+/// 整理歷史時的保留規則
+///
+/// 若未設定 `keep_execs` 及 `collapse_repeats`，則沿用預設的整理方式：
+/// 每組參數及目錄只留下最新一筆執行事件，其它非結構性事件一律砍除
+#[derive(Debug, Default)]
+pub struct RetentionPolicy {
+    /// 每支腳本最多保留幾筆執行紀錄
+    pub keep_execs: Option<u32>,
+    /// 早於此時間的謙卑事件會被刪除
+    pub humble_before: Option<NaiveDateTime>,
+    /// 早於此時間的被忽略事件會被刪除
+    pub ignored_before: Option<NaiveDateTime>,
+    /// 將連續以相同參數執行的紀錄合併為一筆，並累計次數
+    pub collapse_repeats: bool,
+}
+
+impl RetentionPolicy {
+    pub fn dedup_only(&self) -> bool {
+        self.keep_execs.is_none() && !self.collapse_repeats
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TidyReport {
+    /// 刪除的事件數
+    pub rows: u64,
+    /// 刪除的事件大約佔用的位元組數
+    pub bytes: u64,
+    /// 因合併重複執行而刪除的執行事件數
+    pub collapsed: u64,
+    /// 實際整理時，整理前後的檔案大小
+    pub file_size: Option<(u64, u64)>,
 }
 
 #[derive(Debug)]
@@ -195,30 +266,100 @@ pub struct LastTimeRecord {
     pub humble_time: Option<NaiveDateTime>,
 }
 
+/// 跨腳本搜尋歷史的條件
+#[derive(Debug, Default)]
+pub struct SearchQuery<'a> {
+    pub ids: &'a [i64],
+    /// 須出現在以空白串接的參數中，區分大小寫
+    pub pattern: Option<&'a str>,
+    pub dir: Option<&'a Path>,
+    pub since: Option<NaiveDateTime>,
+    pub failed: bool,
+    /// 環境變數的鍵，或 `KEY=VAL` 的形式
+    pub env: Option<&'a str>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct SearchRecord {
+    pub event_id: i64,
+    pub script_id: i64,
+    pub time: NaiveDateTime,
+    pub dir: String,
+    pub args: String,
+    pub envs: String,
+    pub code: Option<i32>,
+}
+
 impl Historian {
     pub async fn close(self) {
-        log::info!("close the historian database");
-        if let Ok(pool) = self.pool.read() {
-            pool.close().await;
-        }
+        dispatch!(&self.storage, s => s.close())
     }
     async fn raw_record(&self, event: DBEvent<'_>) -> Result<i64, DBError> {
-        let pool = &mut *self.pool.write().unwrap();
-        let res = raw_record_event(pool, event).await;
-        if res.is_err() {
-            pool.close().await;
-            log::warn!("資料庫錯誤 {:?}，再試最後一次！", res);
-            *pool = db::get_pool(&self.dir_path).await?;
-            return raw_record_event(pool, event).await;
+        dispatch!(&self.storage, s => s.insert(event))
+    }
+    pub async fn new(dir_path: PathBuf) -> Result<Self, DBError> {
+        Self::open(dir_path, StorageKind::Sqlite).await
+    }
+    /// 以指定的方式開啟家目錄下的歷史
+    ///
+    /// 若另一種方式的歷史檔案還在，而目前的歷史是空的，會先把舊的事件搬過來
+    pub async fn open(dir_path: PathBuf, kind: StorageKind) -> Result<Self, DBError> {
+        let historian = Self::open_storage(&dir_path, kind).await?;
+        let other = match kind {
+            StorageKind::Sqlite => Some((StorageKind::Jsonl, db::get_jsonl_file(&dir_path))),
+            StorageKind::Jsonl => Some((StorageKind::Sqlite, db::get_file(&dir_path))),
+            StorageKind::Memory => None,
+        };
+        if let Some((other, file)) = other.filter(|(_, file)| file.exists()) {
+            historian.adopt(&dir_path, other, &file).await?;
+        }
+        Ok(historian)
+    }
+    async fn open_storage(dir_path: &Path, kind: StorageKind) -> Result<Self, DBError> {
+        let storage = match kind {
+            StorageKind::Sqlite => Backend::Sqlite(SqliteStorage::new(dir_path.to_owned()).await?),
+            StorageKind::Jsonl => {
+                Backend::Memory(MemoryStorage::with_log(db::get_jsonl_file(dir_path)))
+            }
+            StorageKind::Memory => Backend::Memory(MemoryStorage::default()),
+        };
+        Ok(Historian { storage })
+    }
+    /// 把另一種方式存的歷史搬進來，並將舊檔案改名以免重覆搬移。修改日誌不會被搬移
+    async fn adopt(&self, dir_path: &Path, kind: StorageKind, file: &Path) -> Result<(), DBError> {
+        let all = EventQuery::new().ignored(None);
+        let existing = dispatch!(&self.storage, s => s.query(&all.clone().limit(1)))?;
+        if !existing.is_empty() {
+            log::warn!(
+                "另一種儲存方式的歷史檔案 {:?} 仍存在，其中的事件不會被讀取",
+                file
+            );
+            return Ok(());
         }
 
-        res
+        let old = Self::open_storage(dir_path, kind).await?;
+        let res = dispatch!(&old.storage, s => s.query(&all));
+        old.close().await;
+        let mut events: Vec<_> = res?.into_iter().map(|(event, _)| event).collect();
+        events.sort_by_key(|e| e.id);
+        let report = self.import(&events, false).await?;
+
+        let mut migrated = file.as_os_str().to_owned();
+        migrated.push(".migrated");
+        std::fs::rename(file, &migrated)?;
+        log::warn!(
+            "歷史的儲存方式改變了，已搬移 {} 筆事件，舊檔案改名為 {:?}",
+            report.imported,
+            migrated
+        );
+        Ok(())
     }
-    pub async fn new(dir_path: PathBuf) -> Result<Self, DBError> {
-        db::get_pool(&dir_path).await.map(|pool| Historian {
-            pool: Arc::new(RwLock::new(pool)),
-            dir_path,
-        })
+    /// 只存在記憶體中的歷史
+    pub fn in_memory() -> Self {
+        Historian {
+            storage: Backend::Memory(MemoryStorage::default()),
+        }
     }
     pub async fn do_migrate(dir_path: &Path) -> Result<(), MigrateError> {
         migration::do_migrate(db::get_file(dir_path)).await?;
@@ -226,48 +367,76 @@ impl Historian {
     }
 
     pub async fn remove(&self, script_id: i64) -> Result<(), DBError> {
-        let pool = self.pool.read().unwrap();
-        sqlx::query!("DELETE FROM events WHERE script_id = ?", script_id,)
-            .execute(&*pool)
-            .await?;
-        Ok(())
+        dispatch!(&self.storage, s => s.remove(script_id))
+    }
+    /// 把腳本的事件移到墓碑 id（即負的腳本 id）底下，腳本刪除後仍可追溯
+    pub async fn keep_as_tombstone(&self, script_id: i64) -> Result<i64, DBError> {
+        let tombstone_id = -script_id;
+        dispatch!(&self.storage, s => s.move_events(script_id, tombstone_id))?;
+        Ok(tombstone_id)
+    }
+    /// 列出所有墓碑 id 及腳本被刪除時的名字
+    pub async fn tombstones(&self) -> Result<Vec<(i64, String)>, DBError> {
+        dispatch!(&self.storage, s => s.tombstones())
     }
 
     pub async fn record(&self, event: &Event<'_>) -> Result<i64, DBError> {
         log::debug!("記錄事件 {:?}", event);
-        let ty = event.data.get_type().get_code();
+        let ty = event.data.get_type();
         let cmd = std::env::args().collect::<Vec<_>>().join(" ");
         let mut db_event = DBEvent::new(event.script_id, event.time, ty, &cmd, event.humble);
         let id = match &event.data {
-            EventData::Write | EventData::Read => self.raw_record(db_event).await?,
-            EventData::PreExec { args, envs, dir } | EventData::Exec { args, envs, dir } => {
+            EventData::Write | EventData::Read | EventData::Neglect | EventData::Restore => {
+                self.raw_record(db_event).await?
+            }
+            EventData::Rename { from, to } | EventData::TypeChange { from, to } => {
+                let content = format!("{} -> {}", from, to);
+                self.raw_record(db_event.content(&content)).await?
+            }
+            EventData::Copy { from } => {
+                let content = from.to_string();
+                self.raw_record(db_event.content(&content)).await?
+            }
+            EventData::Retag { diff } => self.raw_record(db_event.content(diff)).await?,
+            EventData::Delete { name } => self.raw_record(db_event.content(name)).await?,
+            EventData::PreExec {
+                args,
+                envs,
+                env_files,
+                profile,
+                dir,
+                parent,
+            }
+            | EventData::Exec {
+                args,
+                envs,
+                env_files,
+                profile,
+                dir,
+                parent,
+            } => {
                 let dir = dir.map(|p| p.to_string_lossy()).unwrap_or_default();
-                self.raw_record(db_event.envs(envs).dir(dir.as_ref()).args(args))
+                let db_event = db_event.envs(envs).env_files(*env_files).profile(*profile);
+                let db_event = db_event.parent_id(*parent);
+                self.raw_record(db_event.dir(dir.as_ref()).args(args))
                     .await?
             }
             EventData::ExecDone {
                 code,
                 main_event_id,
             } => {
-                let main_event = sqlx::query!(
-                    "SELECT ignored, humble FROM events WHERE (type = ? OR type = ?) AND id = ?",
-                    EXEC_CODE,
-                    PRE_EXEC_CODE,
-                    main_event_id
-                )
-                .fetch_optional(&*self.pool.read().unwrap())
-                .await?;
-                let main_event = match main_event {
+                let main_event = dispatch!(&self.storage, s => s.main_event_flags(*main_event_id))?;
+                let (ignored, humble) = match main_event {
                     Some(e) => e,
                     None => {
                         log::warn!("找不到主要事件，可能被 tidy 掉了");
                         return Ok(ZERO);
                     }
                 };
-                if main_event.ignored {
+                if ignored {
                     log::debug!("執行事件被忽略了");
                     return Ok(ZERO);
-                } else if main_event.humble {
+                } else if humble {
                     log::debug!("謙卑地執行完畢了");
                     db_event = db_event.humble();
                 }
@@ -288,31 +457,24 @@ impl Historian {
         Ok(id)
     }
 
+    /// 回傳前一次執行的參數、環境變數、環境檔及環境組合名稱
+    /// 若 `only_success` 為真，只考慮返回碼為零的執行
     pub async fn previous_args(
         &self,
         id: i64,
         dir: Option<&Path>,
-    ) -> Result<Option<(String, String)>, DBError> {
-        let no_dir = dir.is_none();
-        let dir = dir.map(|p| p.to_string_lossy());
-        let dir = dir.as_deref().unwrap_or(EMPTY_STR);
-        let res = sqlx::query!(
-            "
-            SELECT args, envs FROM events
-            WHERE type = ? AND script_id = ? AND NOT ignored
-            AND (? OR dir = ?)
-            ORDER BY time DESC LIMIT 1
-            ",
-            EXEC_CODE,
-            id,
-            no_dir,
-            dir
-        )
-        .fetch_optional(&*self.pool.read().unwrap())
-        .await?;
-        Ok(res.map(|res| (res.args.unwrap_or_default(), res.envs.unwrap_or_default())))
+        only_success: bool,
+    ) -> Result<Option<PreviousArgs>, DBError> {
+        dispatch!(&self.storage, s => s.previous_args(id, dir, only_success))
     }
 
+    async fn do_previous_args_list(
+        &self,
+        query: ArgsQuery<'_>,
+    ) -> Result<impl ExactSizeIterator<Item = ArgsRecord>, DBError> {
+        let res = dispatch!(&self.storage, s => s.previous_args_list(&query))?;
+        Ok(res.into_iter())
+    }
     pub async fn previous_args_list(
         &self,
         ids: &[i64],
@@ -320,23 +482,18 @@ impl Historian {
         offset: u32,
         no_humble: bool,
         dir: Option<&Path>,
-    ) -> Result<impl ExactSizeIterator<Item = (i64, String)>, DBError> {
-        let res = do_last_arg!(
-            "script_id, args",
-            ", args",
+        status: StatusFilter,
+    ) -> Result<impl ExactSizeIterator<Item = ArgsRecord>, DBError> {
+        self.do_previous_args_list(ArgsQuery {
             ids,
-            limit,
-            offset,
-            no_humble,
             dir,
-            self
-        )?;
-        Ok(res.into_iter().map(|res| {
-            (
-                res.script_id.unwrap_or_default(),
-                res.args.unwrap_or_default(),
-            )
-        }))
+            no_humble,
+            status,
+            key: ArgsKey::Args,
+            limit: limit as i64,
+            offset: offset as i64,
+        })
+        .await
     }
 
     pub async fn previous_args_list_with_envs(
@@ -346,24 +503,18 @@ impl Historian {
         offset: u32,
         no_humble: bool,
         dir: Option<&Path>,
-    ) -> Result<impl ExactSizeIterator<Item = (i64, String, String)>, DBError> {
-        let res = do_last_arg!(
-            "script_id, args, envs",
-            ", args, envs",
+        status: StatusFilter,
+    ) -> Result<impl ExactSizeIterator<Item = ArgsRecord>, DBError> {
+        self.do_previous_args_list(ArgsQuery {
             ids,
-            limit,
-            offset,
-            no_humble,
             dir,
-            self
-        )?;
-        Ok(res.into_iter().map(|res| {
-            (
-                res.script_id.unwrap_or_default(),
-                res.args.unwrap_or_default(),
-                res.envs.unwrap_or_default(),
-            )
-        }))
+            no_humble,
+            status,
+            key: ArgsKey::Both,
+            limit: limit as i64,
+            offset: offset as i64,
+        })
+        .await
     }
 
     pub async fn previous_args_list_only_envs(
@@ -373,68 +524,94 @@ impl Historian {
         offset: u32,
         no_humble: bool,
         dir: Option<&Path>,
-    ) -> Result<impl ExactSizeIterator<Item = (i64, String)>, DBError> {
-        let res = do_last_arg!(
-            "script_id, envs",
-            ", envs",
+        status: StatusFilter,
+    ) -> Result<impl ExactSizeIterator<Item = ArgsRecord>, DBError> {
+        self.do_previous_args_list(ArgsQuery {
             ids,
-            limit,
-            offset,
-            no_humble,
             dir,
-            self
-        )?;
-        Ok(res.into_iter().map(|res| {
-            (
-                res.script_id.unwrap_or_default(),
-                res.envs.unwrap_or_default(),
-            )
-        }))
+            no_humble,
+            status,
+            key: ArgsKey::Envs,
+            limit: limit as i64,
+            offset: offset as i64,
+        })
+        .await
+    }
+
+    /// 直接查詢所有腳本的執行事件，由新到舊排序
+    ///
+    /// 注意：過短的詞不會經過全文檢索，回傳的結果只是候選，呼叫端需自行精確比對
+    pub async fn search(&self, query: &SearchQuery<'_>) -> Result<Vec<SearchRecord>, DBError> {
+        dispatch!(&self.storage, s => s.search(query))
+    }
+    /// 依時間由新到舊列出所有腳本的事件，被忽略的事件不會出現
+    pub async fn log(&self, query: &LogQuery<'_>) -> Result<Vec<LogRecord>, DBError> {
+        dispatch!(&self.storage, s => s.log(query))
+    }
+    /// 依條件查詢事件，參數及環境變數會解析成結構化的資料
+    pub async fn query(&self, query: &EventQuery) -> Result<Vec<EventRecord>, DBError> {
+        let res = dispatch!(&self.storage, s => s.query(query))?;
+        res.into_iter()
+            .map(|(event, code)| EventRecord::new(event, code))
+            .collect()
     }
     async fn make_last_time_record(&self, script_id: i64) -> Result<LastTimeRecord, DBError> {
-        let res = sqlx::query_as_unchecked!(
-            LastTimeRecord,
-            "
-            SELECT
-                ? as script_id,
-                (SELECT time FROM events
-                WHERE script_id = ? AND NOT ignored AND humble
-                ORDER BY time DESC LIMIT 1) as humble_time,
-                (SELECT time FROM events
-                WHERE script_id = ? AND NOT ignored AND NOT humble AND type = ?
-                ORDER BY time DESC LIMIT 1) as exec_time,
-                (SELECT time FROM events
-                WHERE script_id = ? AND NOT ignored AND NOT humble AND type = ?
-                ORDER BY time DESC LIMIT 1) as exec_done_time
-            ",
-            script_id,
-            script_id,
-            script_id,
-            EXEC_CODE,
-            script_id,
-            EXEC_DONE_CODE
-        )
-        .fetch_one(&*self.pool.read().unwrap())
-        .await?;
+        dispatch!(&self.storage, s => s.last_time(script_id))
+    }
+    /// 在日誌開啟的期間執行 `fut`，其間對事件的修改都會記下原值，之後可用 `undo` 復原
+    async fn journaled<T>(
+        &self,
+        kind: &str,
+        fut: impl std::future::Future<Output = Result<T, DBError>>,
+    ) -> Result<T, DBError> {
+        let time = chrono::Utc::now().naive_utc();
+        let cmd = std::env::args().collect::<Vec<_>>().join(" ");
+        dispatch!(&self.storage, s => s.begin_mutation(time, kind, &cmd))?;
+        let res = fut.await;
+        dispatch!(&self.storage, s => s.end_mutation())?;
+        res
+    }
+    /// 由新到舊列出可以復原的修改
+    pub async fn mutations(&self, limit: u32) -> Result<Vec<MutationRecord>, DBError> {
+        dispatch!(&self.storage, s => s.mutations(limit))
+    }
+    /// 復原最近的 `n` 次修改，回傳被復原的修改及受影響腳本的最新時間
+    pub async fn undo(
+        &self,
+        n: u32,
+    ) -> Result<(Vec<MutationRecord>, Vec<LastTimeRecord>), DBError> {
+        let mutations = self.mutations(n).await?;
+        let mut script_ids = vec![];
+        for mutation in mutations.iter() {
+            log::info!("復原修改 {:?}", mutation);
+            let ids = dispatch!(&self.storage, s => s.undo_mutation(mutation.id))?;
+            for id in ids.into_iter() {
+                if !script_ids.contains(&id) {
+                    script_ids.push(id);
+                }
+            }
+        }
 
-        Ok(LastTimeRecord {
-            script_id,
-            exec_time: res.exec_time,
-            exec_done_time: res.exec_done_time,
-            humble_time: res.humble_time,
-        })
+        let mut last_times = vec![];
+        for id in script_ids.into_iter() {
+            last_times.push(self.make_last_time_record(id).await?);
+        }
+        Ok((mutations, last_times))
     }
+
     pub async fn ignore_args_by_id(
         &self,
         event_id: NonZeroU64,
     ) -> Result<Option<LastTimeRecord>, DBError> {
-        self.process_args_by_id(false, event_id).await
+        let fut = self.process_args_by_id(false, event_id);
+        self.journaled("rm-id", fut).await
     }
     pub async fn humble_args_by_id(
         &self,
         event_id: NonZeroU64,
     ) -> Result<Option<LastTimeRecord>, DBError> {
-        self.process_args_by_id(true, event_id).await
+        let fut = self.process_args_by_id(true, event_id);
+        self.journaled("humble", fut).await
     }
     /// humble or ignore
     async fn process_args_by_id(
@@ -442,33 +619,14 @@ impl Historian {
         is_humble: bool,
         event_id: NonZeroU64,
     ) -> Result<Option<LastTimeRecord>, DBError> {
-        let pool = self.pool.read().unwrap();
-        let event_id = event_id.get() as i64;
-        let latest_record = sqlx::query!(
-            "
-            SELECT id, script_id FROM events
-            WHERE (type = ? OR type = ?) AND script_id = (SELECT script_id FROM events WHERE id = ?)
-            ORDER BY time DESC LIMIT 1
-            ",
-            EXEC_CODE,
-            PRE_EXEC_CODE,
-            event_id,
-        )
-        .fetch_one(&*pool)
-        .await?;
-        // TODO: check if this event is exec?
-
-        if is_humble {
-            ignore_or_humble_arg!("humble", pool, "id = ?", event_id);
-        } else {
-            ignore_or_humble_arg!("ignored", pool, "id = ?", event_id);
-        }
+        let (latest_id, script_id) =
+            dispatch!(&self.storage, s => s.ignore_or_humble_by_id(is_humble, event_id))?;
 
-        if latest_record.id == event_id {
+        if latest_id == event_id.get() as i64 {
             // NOTE: 若 event_id 為最新但已被 ignored/humble，仍會被抓成 last_record 並進入這裡
             // 但應該不致於有太大的效能問題
             log::info!("process last args");
-            let ret = self.make_last_time_record(latest_record.script_id).await?;
+            let ret = self.make_last_time_record(script_id).await?;
             return Ok(Some(ret));
         }
         Ok(None)
@@ -483,69 +641,34 @@ impl Historian {
         min: NonZeroU64,
         max: Option<NonZeroU64>,
     ) -> Result<Vec<LastTimeRecord>, DBError> {
-        let ids_str = join_id_str(ids);
-
-        let offset = min.get() as i64 - 1;
-        let limit = if let Some(max) = max {
-            (max.get() - min.get()) as i64
-        } else {
-            -1
-        };
-        let no_dir = dir.is_none();
-        let dir = dir.map(|p| p.to_string_lossy());
-        let dir = dir.as_deref().unwrap_or(EMPTY_STR);
-        log::info!("忽略歷史 {} {} {}", offset, limit, ids_str);
-
-        let pool = self.pool.read().unwrap();
-        macro_rules! ignore_arg {
-            ($($target:literal)*) => {{
-                // NOTE: 我們知道 script_id || args 串接起來必然是唯一的（因為 args 的格式為 [...]）
-                // FIXME: 一旦可以綁定陣列就換掉這個醜死人的 instr
-                ignore_or_humble_arg!(
-                    "ignored",
-                    pool,
-                    "
-                    (? OR dir == ?) AND
-                    (NOT ? OR NOT humble) AND
-                    (script_id " $(+ "||" + $target)* + ") IN (
-                        WITH records AS (
-                            SELECT max(time) as time, script_id " $(+ "," + $target)* +" FROM events
-                            WHERE instr(?, '[' || script_id || ']') > 0
-                            AND type = ? AND NOT ignored
-                            AND (? OR dir == ?)
-                            AND (NOT ? OR NOT humble)
-                            GROUP BY script_id " $( + "," + $target)* + " ORDER BY time DESC LIMIT ? OFFSET ?
-                        ) SELECT script_id " $(+ "||" + $target)* + " as t FROM records
-                    )
-                    ",
-                    no_dir,
-                    dir,
-                    no_humble,
-                    ids_str,
-                    EXEC_CODE,
-                    no_dir,
-                    dir,
-                    no_humble,
-                    limit,
-                    offset
-                );
-            }};
-        }
-
-        match (show_env, show_args) {
-            (true, true) => ignore_arg!("args" "envs"),
-            (true, false) => ignore_arg!("envs"),
-            (false, true) => ignore_arg!("args"),
+        let key = match (show_env, show_args) {
+            (true, true) => ArgsKey::Both,
+            (true, false) => ArgsKey::Envs,
+            (false, true) => ArgsKey::Args,
             (false, false) => unreachable!(),
-        }
-
-        log::info!("ignore last args");
-        let mut ret = vec![];
-        for &id in ids {
-            // TODO: 平行？
-            ret.push(self.make_last_time_record(id).await?);
-        }
-        Ok(ret)
+        };
+        let query = ArgsQuery {
+            ids,
+            dir,
+            no_humble,
+            status: StatusFilter::All,
+            key,
+            limit: max.map_or(-1, |max| (max.get() - min.get()) as i64),
+            offset: min.get() as i64 - 1,
+        };
+        let fut = async {
+            log::info!("忽略歷史 {:?}", query);
+            dispatch!(&self.storage, s => s.ignore_args(&query))?;
+
+            log::info!("ignore last args");
+            let mut ret = vec![];
+            for &id in ids {
+                // TODO: 平行？
+                ret.push(self.make_last_time_record(id).await?);
+            }
+            Ok(ret)
+        };
+        self.journaled("rm", fut).await
     }
 
     pub async fn amend_args_by_id(
@@ -554,108 +677,93 @@ impl Historian {
         args: &str,
         envs: Option<&str>,
     ) -> Result<(), DBError> {
-        let event_id = event_id.get() as i64;
-
-        macro_rules! amend {
-            ($($set:literal, $var:expr),*) => {{
-                sqlx::query!(
-                    "UPDATE events SET ignored = false, args = ?"
-                    + $( "," + $set + "=? " +)*
-                    "WHERE (type = ? OR type = ?) AND id = ? ",
-                    args,
-                    $($var,)*
-                    EXEC_CODE,
-                    PRE_EXEC_CODE,
-                    event_id,
-                )
-                .execute(&*self.pool.read().unwrap())
-                .await?
-            }}
-        }
-        if let Some(envs) = envs {
-            amend!("envs", envs);
-        } else {
-            amend!();
-        }
-        Ok(())
+        let fut = async { dispatch!(&self.storage, s => s.amend(event_id, args, envs)) };
+        self.journaled("amend", fut).await
     }
 
-    /// 除了輸入進來的 script id 外，其它事件通通砍除
-    pub async fn clear_except_script_ids(&self, script_ids: &[i64]) -> Result<(), DBError> {
-        let ids = join_id_str(script_ids);
-        let pool = self.pool.read().unwrap();
-        // FIXME: 一旦可以綁定陣列就換掉這個醜死人的 instr
-        sqlx::query!(
-            "
-            DELETE FROM events
-            WHERE instr(?, '[' || script_id || ']') <= 0
-            ",
-            ids
-        )
-        .execute(&*pool)
-        .await?;
+    /// 依 id 順序匯出腳本的事件，`since` 之前的事件只有在被之後的事件參照時才會匯出
+    pub async fn export(
+        &self,
+        script_ids: &[i64],
+        since: Option<NaiveDateTime>,
+    ) -> Result<Vec<RawEvent>, DBError> {
+        dispatch!(&self.storage, s => s.export(script_ids, since))
+    }
+    /// 匯入事件，`script_id` 須已對應到本地的腳本 id。事件的 id 會重新分配，`main_event_id` 及 `parent_id` 也隨之轉換
+    /// 若 `dedupe` 為真，本地已有相同腳本、類型、時間及內容的事件不會重覆匯入
+    pub async fn import(&self, events: &[RawEvent], dedupe: bool) -> Result<ImportReport, DBError> {
+        let mut report = ImportReport::default();
+        let mut id_map = std::collections::HashMap::<i64, i64>::new();
+        let mut script_ids = vec![];
+        for event in events.iter() {
+            let main_event_id = if event.main_event_id == ZERO {
+                ZERO
+            } else if let Some(id) = id_map.get(&event.main_event_id) {
+                *id
+            } else {
+                log::warn!("找不到事件 {} 的主要事件 {}", event.id, event.main_event_id);
+                report.orphaned += 1;
+                continue;
+            };
+
+            if dedupe {
+                let existing = dispatch!(&self.storage, s => s.find_duplicate(event))?;
+                if let Some(existing) = existing {
+                    log::debug!("事件 {} 已存在為 {}", event.id, existing);
+                    id_map.insert(event.id, existing);
+                    report.duplicated += 1;
+                    continue;
+                }
+            }
 
-        sqlx::query!("VACUUM").execute(&*pool).await?;
+            // NOTE: 外層的執行沒被匯入，就當作是直接執行的
+            let event = RawEvent {
+                parent_id: event.parent_id.and_then(|id| id_map.get(&id).copied()),
+                ..event.clone()
+            };
+            let id = dispatch!(&self.storage, s => s.insert_raw(&event, main_event_id))?;
+            id_map.insert(event.id, id);
+            report.imported += 1;
+            if !script_ids.contains(&event.script_id) {
+                script_ids.push(event.script_id);
+            }
+        }
 
-        Ok(())
+        for id in script_ids.into_iter() {
+            report
+                .last_times
+                .push(self.make_last_time_record(id).await?);
+        }
+        Ok(report)
     }
 
-    pub async fn tidy(&self, script_id: i64) -> Result<(), DBError> {
-        let pool = self.pool.read().unwrap();
-        sqlx::query!(
-            "
-            DELETE FROM events
-            WHERE script_id = ?
-              AND id NOT IN (
-                SELECT id FROM
-                  (
-                    SELECT id, MAX(time) FROM events
-                    WHERE script_id = ?
-                      AND type = ?
-                      AND NOT ignored
-                    GROUP BY args, dir
-                  )
-              )
-            ",
-            script_id,
-            script_id,
-            EXEC_CODE,
-        )
-        .execute(&*pool)
-        .await?;
-
-        Ok(())
+    /// 依保留規則整理歷史，不存在的腳本的事件一律砍除，但墓碑及結構性事件會被保留
+    /// 若 `dry_run` 為真，只計算會被刪除的量
+    pub async fn tidy(
+        &self,
+        script_ids: &[i64],
+        policy: &RetentionPolicy,
+        dry_run: bool,
+    ) -> Result<TidyReport, DBError> {
+        log::info!("依 {:?} 整理歷史", policy);
+        dispatch!(&self.storage, s => s.tidy(script_ids, policy, dry_run))
     }
 
     pub async fn upgrade_pre_exec(&self, run_id: i64) -> Result<i64, DBError> {
         log::debug!("升級執行事件 {}", run_id);
-        let pool = self.pool.read().unwrap();
-
-        let main_event = sqlx::query!(
-            "SELECT ignored, humble FROM events WHERE type = ? AND id = ?",
-            PRE_EXEC_CODE,
-            run_id,
-        )
-        .fetch_optional(&*pool)
-        .await?;
-
-        sqlx::query!(
-            "UPDATE events SET type = ? WHERE type = ? AND id = ?",
-            EXEC_CODE,
-            PRE_EXEC_CODE,
-            run_id,
-        )
-        .execute(&*pool)
-        .await?;
-
+        let main_event = dispatch!(&self.storage, s => s.upgrade_pre_exec(run_id))?;
         match main_event {
             None => {
                 log::warn!("找不到主要事件，可能被 tidy 掉了");
                 Ok(ZERO)
             }
-            Some(e) if e.humble || e.ignored => {
+            Some((ignored, humble)) if humble || ignored => {
                 // XXX: 用很怪異的方式告訴外面的人不要記錄最新時間，醜死
-                log::warn!("升級執行事件時發現主事件已被忽略或謙卑 {:?}", e);
+                log::warn!(
+                    "升級執行事件時發現主事件已被忽略或謙卑 ignored={} humble={}",
+                    ignored,
+                    humble
+                );
                 Ok(ZERO)
             }
             _ => Ok(run_id),
@@ -663,11 +771,6 @@ impl Historian {
     }
 }
 
-fn join_id_str(ids: &[i64]) -> String {
-    use std::fmt::Write;
-    let mut ret = String::new();
-    for id in ids {
-        write!(ret, "[{}]", id).unwrap();
-    }
-    ret
+fn parse_code(code: Option<String>) -> Option<i32> {
+    code.and_then(|c| c.parse().ok())
 }
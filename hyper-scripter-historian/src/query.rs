@@ -0,0 +1,221 @@
+use crate::{EventType, RawEvent, StatusFilter};
+use chrono::NaiveDateTime;
+use sqlx::error::Error as DBError;
+use std::path::{Path, PathBuf};
+
+/// 查詢事件的條件，以鏈式呼叫建構後交給 [`Historian::query`](crate::Historian::query)
+///
+/// 預設會列出所有腳本中未被忽略的事件，由新到舊排序
+/// ```
+/// use hyper_scripter_historian::{EventQuery, EventType, StatusFilter};
+/// let query = EventQuery::new()
+///     .scripts([1, 2])
+///     .types([EventType::Exec])
+///     .status(StatusFilter::Failed)
+///     .limit(10);
+/// ```
+#[derive(Debug, Clone)]
+pub struct EventQuery {
+    pub(crate) script_ids: Option<Vec<i64>>,
+    pub(crate) types: Option<Vec<EventType>>,
+    pub(crate) since: Option<NaiveDateTime>,
+    pub(crate) until: Option<NaiveDateTime>,
+    pub(crate) dir: Option<PathBuf>,
+    pub(crate) humble: Option<bool>,
+    pub(crate) ignored: Option<bool>,
+    pub(crate) status: StatusFilter,
+    pub(crate) code: Option<i32>,
+    pub(crate) limit: Option<u32>,
+    pub(crate) offset: u32,
+}
+
+impl Default for EventQuery {
+    fn default() -> Self {
+        EventQuery {
+            script_ids: None,
+            types: None,
+            since: None,
+            until: None,
+            dir: None,
+            humble: None,
+            ignored: Some(false),
+            status: StatusFilter::All,
+            code: None,
+            limit: None,
+            offset: 0,
+        }
+    }
+}
+
+impl EventQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// 只列出這些腳本的事件，被刪除的腳本其 id 為負的墓碑 id
+    pub fn scripts(mut self, ids: impl IntoIterator<Item = i64>) -> Self {
+        self.script_ids = Some(ids.into_iter().collect());
+        self
+    }
+    /// 只列出這些類型的事件
+    pub fn types(mut self, types: impl IntoIterator<Item = EventType>) -> Self {
+        self.types = Some(types.into_iter().collect());
+        self
+    }
+    /// 只列出此時間（含）之後的事件
+    pub fn since(mut self, time: NaiveDateTime) -> Self {
+        self.since = Some(time);
+        self
+    }
+    /// 只列出此時間（不含）之前的事件
+    pub fn until(mut self, time: NaiveDateTime) -> Self {
+        self.until = Some(time);
+        self
+    }
+    /// 只列出在此目錄下執行的事件
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+    /// 依是否謙卑篩選，`None` 代表不限（預設）
+    pub fn humble(mut self, humble: Option<bool>) -> Self {
+        self.humble = humble;
+        self
+    }
+    /// 依是否被忽略篩選，`None` 代表不限，預設只列出未被忽略的事件
+    pub fn ignored(mut self, ignored: Option<bool>) -> Self {
+        self.ignored = ignored;
+        self
+    }
+    /// 依執行結果篩選，沒有返回碼的事件只會出現在 `All` 中
+    pub fn status(mut self, status: StatusFilter) -> Self {
+        self.status = status;
+        self
+    }
+    /// 只列出返回碼為此值的事件
+    pub fn code(mut self, code: i32) -> Self {
+        self.code = Some(code);
+        self
+    }
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+    pub fn offset(mut self, offset: u32) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// 檢查返回碼是否符合條件
+    pub(crate) fn match_code(&self, code: Option<&str>) -> bool {
+        let code = code.and_then(|c| c.parse::<i32>().ok());
+        let status = match self.status {
+            StatusFilter::All => true,
+            StatusFilter::Failed => code.is_some_and(|c| c != 0),
+            StatusFilter::Succeeded => code == Some(0),
+        };
+        status && self.code.is_none_or(|c| code == Some(c))
+    }
+    pub(crate) fn dir_str(&self) -> Option<std::borrow::Cow<'_, str>> {
+        self.dir.as_deref().map(Path::to_string_lossy)
+    }
+}
+
+/// 一筆環境變數
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvRecord {
+    pub key: String,
+    pub val: String,
+}
+
+/// 查詢結果，參數及環境變數都已解析完成
+#[derive(Debug, Clone)]
+pub struct EventRecord {
+    pub id: i64,
+    pub script_id: i64,
+    pub ty: EventType,
+    /// 產生此事件的完整命令
+    pub cmd: String,
+    pub time: NaiveDateTime,
+    /// 執行的參數，非執行事件則為空
+    pub args: Vec<String>,
+    /// 執行時額外指定的環境變數，非執行事件則為空
+    pub envs: Vec<EnvRecord>,
+    /// 執行時指定的環境檔，其內容不會被記錄
+    pub env_files: Vec<PathBuf>,
+    pub dir: Option<PathBuf>,
+    /// 執行時使用的環境組合名稱
+    pub profile: Option<String>,
+    /// 事件的內容，如改名事件的 `a -> b`
+    pub content: Option<String>,
+    /// 執行事件取自對應的 ExecDone 事件，若無則代表還在執行或被中斷
+    pub code: Option<i32>,
+    /// ExecDone 事件所屬的執行事件
+    pub main_event_id: Option<i64>,
+    /// 在另一次執行中被呼叫時，外層的執行事件
+    pub parent_id: Option<i64>,
+    pub humble: bool,
+    pub ignored: bool,
+    /// 被 tidy 合併的連續相同執行次數
+    pub repeat_count: i64,
+}
+
+impl EventRecord {
+    pub(crate) fn new(event: RawEvent, code: Option<i32>) -> Result<Self, DBError> {
+        // NOTE: 很舊的事件可能沒有記錄環境變數
+        let args = match event.args.as_deref().filter(|s| !s.is_empty()) {
+            Some(args) => decode_args(args).map_err(|e| DBError::Decode(e.into()))?,
+            None => vec![],
+        };
+        let envs = match event.envs.as_deref().filter(|s| !s.is_empty()) {
+            Some(envs) => decode_envs(envs).map_err(|e| DBError::Decode(e.into()))?,
+            None => vec![],
+        };
+        let env_files = match event.env_files.as_deref() {
+            Some(files) => decode_args(files).map_err(|e| DBError::Decode(e.into()))?,
+            None => vec![],
+        };
+        Ok(EventRecord {
+            id: event.id,
+            script_id: event.script_id,
+            ty: event.ty,
+            cmd: event.cmd,
+            time: event.time,
+            args,
+            envs,
+            env_files: env_files.into_iter().map(PathBuf::from).collect(),
+            dir: event.dir.filter(|d| !d.is_empty()).map(PathBuf::from),
+            profile: event.profile,
+            content: event.content,
+            code,
+            main_event_id: Some(event.main_event_id).filter(|id| *id != 0),
+            parent_id: event.parent_id,
+            humble: event.humble,
+            ignored: event.ignored,
+            repeat_count: event.repeat_count,
+        })
+    }
+}
+
+/// 解析歷史中的參數字串，如 [`ArgsRecord::args`](crate::ArgsRecord::args)
+pub fn decode_args(args: &str) -> Result<Vec<String>, serde_json::Error> {
+    serde_json::from_str(args)
+}
+
+/// 解析歷史中的環境變數字串，如 [`ArgsRecord::envs`](crate::ArgsRecord::envs)
+pub fn decode_envs(envs: &str) -> Result<Vec<EnvRecord>, serde_json::Error> {
+    use serde::de::Error;
+    let pairs: Vec<String> = serde_json::from_str(envs)?;
+    pairs
+        .into_iter()
+        .map(|pair| match pair.split_once('=') {
+            Some((key, val)) => Ok(EnvRecord {
+                key: key.to_owned(),
+                val: val.to_owned(),
+            }),
+            None => Err(serde_json::Error::custom(format!(
+                "environment variable without `=`: {}",
+                pair
+            ))),
+        })
+        .collect()
+}
@@ -0,0 +1,311 @@
+use super::memory::State;
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+
+impl RawEvent {
+    pub(crate) fn from_db(id: i64, event: &DBEvent<'_>) -> Self {
+        RawEvent {
+            id,
+            script_id: event.script_id,
+            ty: event.ty,
+            cmd: event.cmd.to_owned(),
+            time: event.time,
+            args: event.args.map(str::to_owned),
+            content: event.content.map(str::to_owned),
+            dir: event.dir.map(str::to_owned),
+            envs: event.envs.map(str::to_owned),
+            env_files: event.env_files.map(str::to_owned),
+            profile: event.profile.map(str::to_owned),
+            main_event_id: event.main_event_id,
+            parent_id: event.parent_id,
+            humble: event.humble,
+            ignored: false,
+            repeat_count: 1,
+        }
+    }
+    /// 同 SQLite 後端 tidy 時的估計方式
+    pub(crate) fn estimated_size(&self) -> u64 {
+        let len = |s: &Option<String>| s.as_ref().map_or(0, |s| s.len());
+        (self.cmd.len()
+            + len(&self.args)
+            + len(&self.content)
+            + len(&self.dir)
+            + len(&self.envs)
+            + len(&self.profile)
+            + len(&self.env_files)
+            + 48) as u64
+    }
+}
+
+/// 對事件的部份修改，`None` 代表不修改
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Patch {
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub ty: Option<EventType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ignored: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub humble: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub envs: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_count: Option<i64>,
+}
+impl Patch {
+    pub fn apply_to(&self, event: &mut RawEvent) {
+        if let Some(ty) = self.ty {
+            event.ty = ty;
+        }
+        if let Some(ignored) = self.ignored {
+            event.ignored = ignored;
+        }
+        if let Some(humble) = self.humble {
+            event.humble = humble;
+        }
+        if let Some(args) = &self.args {
+            event.args = Some(args.clone());
+        }
+        if let Some(envs) = &self.envs {
+            event.envs = Some(envs.clone());
+        }
+        if let Some(repeat_count) = self.repeat_count {
+            event.repeat_count = repeat_count;
+        }
+    }
+}
+
+/// 修改前的原值，同 SQLite 的 mutation_events 表
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub event_id: i64,
+    pub ignored: bool,
+    pub humble: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub envs: Option<String>,
+}
+impl JournalEntry {
+    pub fn of(event: &RawEvent) -> Self {
+        JournalEntry {
+            event_id: event.id,
+            ignored: event.ignored,
+            humble: event.humble,
+            args: event.args.clone(),
+            envs: event.envs.clone(),
+        }
+    }
+    pub fn into_patch(self) -> Patch {
+        Patch {
+            ignored: Some(self.ignored),
+            humble: Some(self.humble),
+            args: self.args,
+            envs: self.envs,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mutation {
+    pub id: i64,
+    pub time: NaiveDateTime,
+    pub kind: String,
+    pub cmd: String,
+    #[serde(skip)]
+    pub open: bool,
+    pub events: Vec<JournalEntry>,
+}
+
+/// 檔案中的一行，依序重播即可得到完整的狀態
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Op {
+    /// 整理過的檔案的第一行
+    Compact {
+        time: NaiveDateTime,
+    },
+    Insert(RawEvent),
+    Update {
+        id: i64,
+        #[serde(flatten)]
+        patch: Patch,
+    },
+    Move {
+        from: i64,
+        to: i64,
+    },
+    Delete {
+        ids: Vec<i64>,
+    },
+    Begin {
+        id: i64,
+        time: NaiveDateTime,
+        kind: String,
+        cmd: String,
+    },
+    End,
+    Forget {
+        id: i64,
+    },
+    /// 整理檔案時寫入的日誌快照
+    Mutation(Mutation),
+}
+
+fn to_db_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> DBError {
+    DBError::Decode(e.into())
+}
+
+/// 新 id 的低位元數，放的是寫入者的節點編號
+pub const NODE_BITS: u32 = 16;
+
+/// 隨機選一個節點編號
+///
+/// 共用同一個 git 家目錄的機器各自附加事件時，不同節點給出的 id 不會相撞，合併檔案後不會遺失事件
+pub fn random_node() -> i64 {
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    hasher.write_i64(chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default());
+    (hasher.finish() & ((1 << NODE_BITS) - 1)) as i64
+}
+
+/// 只會附加的歷史檔案。寫入時會鎖住整個檔案，讀取前會先讀進其它程序附加的內容
+///
+/// NOTE: 只有 `hs history tidy` 會重寫整個檔案，平時不自動整理，以免和其它機器附加的內容衝突
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    /// 已讀進記憶體的位元組數
+    offset: u64,
+    /// 檔案的第一行，用來判斷檔案是否被其它程序整理過
+    head: Option<Vec<u8>>,
+}
+
+impl LogFile {
+    pub fn new(path: PathBuf) -> Self {
+        LogFile {
+            path,
+            offset: 0,
+            head: None,
+        }
+    }
+    pub fn lock(&self) -> Result<File, DBError> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&self.path)?;
+        file.lock()?;
+        Ok(file)
+    }
+    pub fn sync(&mut self, state: &mut State) -> Result<(), DBError> {
+        match File::open(&self.path) {
+            Ok(mut file) => self.sync_locked(&mut file, state),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                if self.offset > 0 {
+                    log::warn!("歷史檔案 {:?} 消失了", self.path);
+                    state.reset();
+                    self.offset = 0;
+                    self.head = None;
+                }
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+    /// 讀進其它程序附加的內容，`file` 不一定要上鎖
+    pub fn sync_locked(&mut self, file: &mut File, state: &mut State) -> Result<(), DBError> {
+        let len = file.metadata()?.len();
+        let head = read_head(file)?;
+        if self.offset > len || (self.offset > 0 && head != self.head) {
+            log::info!("歷史檔案 {:?} 被其它程序整理過，重新載入", self.path);
+            state.reset();
+            self.offset = 0;
+        }
+        if self.offset == len {
+            return Ok(());
+        }
+
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut buf = vec![];
+        file.read_to_end(&mut buf)?;
+        // NOTE: 最後一行可能還沒寫完，留待下次再讀
+        let Some(end) = buf.iter().rposition(|b| *b == b'\n') else {
+            return Ok(());
+        };
+        for line in buf[..end].split(|b| *b == b'\n') {
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            match serde_json::from_slice::<Op>(line) {
+                Ok(op) => state.replay(&op),
+                Err(e) => log::warn!(
+                    "無法解析歷史檔案中的一行 {}：{}",
+                    String::from_utf8_lossy(line),
+                    e
+                ),
+            }
+        }
+        self.offset += end as u64 + 1;
+        self.head = head;
+        Ok(())
+    }
+    /// `file` 須已上鎖並讀進最新的內容
+    pub fn append(&mut self, file: &mut File, ops: Vec<Op>) -> Result<(), DBError> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+        let mut buf = vec![];
+        for op in ops.iter() {
+            serde_json::to_writer(&mut buf, op).map_err(to_db_error)?;
+            buf.push(b'\n');
+        }
+        file.write_all(&buf)?;
+        if self.offset == 0 {
+            self.head = read_head(file)?;
+        }
+        self.offset += buf.len() as u64;
+        Ok(())
+    }
+    /// 以目前的狀態重寫整個檔案，回傳整理前後的檔案大小
+    ///
+    /// `file` 須已上鎖並讀進最新的內容
+    pub fn compact(&mut self, file: &mut File, state: &State) -> Result<(u64, u64), DBError> {
+        let size_before = file.metadata()?.len();
+        let time = chrono::Utc::now().naive_utc();
+        let mut buf = vec![];
+        let mut write_op = |op: &Op| -> Result<(), DBError> {
+            serde_json::to_writer(&mut buf, op).map_err(to_db_error)?;
+            buf.push(b'\n');
+            Ok(())
+        };
+        write_op(&Op::Compact { time })?;
+        for event in state.events() {
+            write_op(&Op::Insert(event.clone()))?;
+        }
+        for m in state.mutation_list() {
+            write_op(&Op::Mutation(m.clone()))?;
+        }
+        file.set_len(0)?;
+        file.write_all(&buf)?;
+        self.offset = buf.len() as u64;
+        self.head = read_head(file)?;
+        Ok((size_before, self.offset))
+    }
+}
+
+fn read_head(file: &mut File) -> Result<Option<Vec<u8>>, DBError> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut head = vec![];
+    BufReader::new(&mut *file).read_until(b'\n', &mut head)?;
+    if head.last() == Some(&b'\n') {
+        Ok(Some(head))
+    } else {
+        Ok(None)
+    }
+}
@@ -0,0 +1,1084 @@
+use super::jsonl::{random_node, JournalEntry, LogFile, Mutation, Op, Patch, NODE_BITS};
+use super::{
+    args_contain, latest_execs, plan_exec_retention, ArgsKey, ArgsQuery, ExecRow, Storage,
+};
+use crate::*;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// 所有事件及日誌都放在記憶體中，若有 `LogFile` 則每次修改都會附加到檔案中
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    state: State,
+    log: Option<LogFile>,
+}
+
+#[derive(Debug, Default)]
+pub struct State {
+    events: BTreeMap<i64, RawEvent>,
+    mutations: Vec<Mutation>,
+    /// 尚未寫進檔案的操作
+    pending: Vec<Op>,
+    /// 寫入檔案時放在新 id 低位的節點編號，見 [`random_node`]
+    node: Option<i64>,
+}
+
+impl State {
+    pub fn reset(&mut self) {
+        *self = State {
+            node: self.node,
+            ..Default::default()
+        };
+    }
+    pub fn events(&self) -> impl Iterator<Item = &RawEvent> {
+        self.events.values()
+    }
+    pub fn mutation_list(&self) -> &[Mutation] {
+        &self.mutations
+    }
+    /// 比 `max` 大的新 id，有節點編號時將其放在低位
+    fn next_id(max: i64, node: Option<i64>) -> i64 {
+        match node {
+            Some(node) => (((max >> NODE_BITS) + 1) << NODE_BITS) | node,
+            None => max + 1,
+        }
+    }
+    fn next_event_id(&self) -> i64 {
+        let max = self.events.last_key_value().map_or(0, |(id, _)| *id);
+        Self::next_id(max, self.node)
+    }
+    fn next_mutation_id(&self) -> i64 {
+        let max = self.mutations.iter().map(|m| m.id).max().unwrap_or(0);
+        Self::next_id(max, self.node)
+    }
+    fn apply(&mut self, op: Op) {
+        self.replay(&op);
+        self.pending.push(op);
+    }
+    fn update(&mut self, id: i64, patch: Patch) {
+        self.apply(Op::Update { id, patch });
+    }
+    /// 套用一個操作，不論是剛發生的或從檔案讀出來的
+    pub fn replay(&mut self, op: &Op) {
+        match op {
+            Op::Compact { .. } => (),
+            Op::Insert(event) => {
+                let Some(old) = self.events.get(&event.id) else {
+                    self.events.insert(event.id, event.clone());
+                    return;
+                };
+                if (old.script_id, old.ty, old.time) == (event.script_id, event.ty, event.time) {
+                    log::debug!("事件 {} 重覆了，略過", event.id);
+                    return;
+                }
+                // NOTE: 節點編號相同的兩台機器才會撞 id，保留兩者，
+                // 沿用舊節點編號重新編號，讀取同一個檔案的程序都會得到一樣的結果
+                let node = event.id & ((1 << NODE_BITS) - 1);
+                let max = self.events.last_key_value().map_or(0, |(id, _)| *id);
+                let id = Self::next_id(max, Some(node));
+                log::warn!("事件 {} 的 id 與其它事件相撞，改為 {}", event.id, id);
+                let mut event = event.clone();
+                event.id = id;
+                self.events.insert(id, event);
+            }
+            Op::Update { id, patch } => {
+                let Some(event) = self.events.get_mut(id) else {
+                    log::warn!("要修改的事件 {} 不存在", id);
+                    return;
+                };
+                let old = JournalEntry::of(event);
+                patch.apply_to(event);
+                if old == JournalEntry::of(event) {
+                    return;
+                }
+                // NOTE: 同 SQLite 的 events_journal 觸發器
+                if let Some(m) = self.mutations.iter_mut().rev().find(|m| m.open) {
+                    m.events.push(old);
+                }
+            }
+            Op::Move { from, to } => {
+                for event in self.events.values_mut() {
+                    if event.script_id == *from {
+                        event.script_id = *to;
+                    }
+                }
+            }
+            Op::Delete { ids } => {
+                for id in ids.iter() {
+                    self.events.remove(id);
+                }
+            }
+            Op::Begin {
+                id,
+                time,
+                kind,
+                cmd,
+            } => {
+                for m in self.mutations.iter_mut() {
+                    m.open = false;
+                }
+                if self.mutations.iter().any(|m| m.id == *id) {
+                    log::warn!("日誌 {} 重覆了，略過", id);
+                    return;
+                }
+                self.mutations.push(Mutation {
+                    id: *id,
+                    time: *time,
+                    kind: kind.clone(),
+                    cmd: cmd.clone(),
+                    open: true,
+                    events: vec![],
+                });
+            }
+            Op::End => {
+                for m in self.mutations.iter_mut() {
+                    m.open = false;
+                }
+                self.mutations.retain(|m| !m.events.is_empty());
+                let overflow = self.mutations.len().saturating_sub(MAX_MUTATIONS as usize);
+                self.mutations.drain(..overflow);
+            }
+            Op::Forget { id } => self.mutations.retain(|m| m.id != *id),
+            Op::Mutation(m) => {
+                if !self.mutations.iter().any(|old| old.id == m.id) {
+                    self.mutations.push(m.clone());
+                }
+            }
+        }
+    }
+    pub fn take_pending(&mut self) -> Vec<Op> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// 每個執行事件最新的返回碼
+    fn codes(&self) -> HashMap<i64, (NaiveDateTime, Option<&str>)> {
+        let mut codes: HashMap<i64, (NaiveDateTime, Option<&str>)> = HashMap::new();
+        for e in self.events().filter(|e| e.ty == EventType::ExecDone) {
+            let code = (e.time, e.content.as_deref());
+            codes
+                .entry(e.main_event_id)
+                .and_modify(|c| {
+                    if c.0 <= e.time {
+                        *c = code
+                    }
+                })
+                .or_insert(code);
+        }
+        codes
+    }
+    fn is_main(e: &RawEvent) -> bool {
+        e.ty == EventType::Exec || e.ty == EventType::PreExec
+    }
+    /// 符合條件的執行事件，同樣的參數只留最新的一筆，由新到舊排序
+    fn args_records(&self, query: &ArgsQuery<'_>) -> Vec<&RawEvent> {
+        let dir = query.dir.map(|p| p.to_string_lossy());
+        let mut groups: HashMap<_, &RawEvent> = HashMap::new();
+        for e in self.events() {
+            if e.ty != EventType::Exec || e.ignored || !query.ids.contains(&e.script_id) {
+                continue;
+            }
+            if !match_dir(e, dir.as_deref()) || (query.no_humble && e.humble) {
+                continue;
+            }
+            groups
+                .entry(args_key(e, query.key))
+                .and_modify(|last| {
+                    if last.time <= e.time {
+                        *last = e
+                    }
+                })
+                .or_insert(e);
+        }
+        let mut records: Vec<_> = groups.into_values().collect();
+        records.sort_by_key(|e| Reverse((e.time, e.id)));
+        records
+    }
+    fn mark(&mut self, ids: &[i64], is_humble: bool) {
+        let patch = if is_humble {
+            Patch {
+                humble: Some(true),
+                ..Default::default()
+            }
+        } else {
+            Patch {
+                ignored: Some(true),
+                ..Default::default()
+            }
+        };
+        let done: Vec<_> = self
+            .events()
+            .filter(|e| e.ty == EventType::ExecDone && ids.contains(&e.main_event_id))
+            .map(|e| e.id)
+            .collect();
+        for id in done.into_iter().chain(ids.iter().copied()) {
+            self.update(id, patch.clone());
+        }
+    }
+    fn last_time(&self, script_id: i64) -> LastTimeRecord {
+        let latest = |f: &dyn Fn(&RawEvent) -> bool| {
+            self.events()
+                .filter(|e| e.script_id == script_id && !e.ignored && f(e))
+                .map(|e| e.time)
+                .max()
+        };
+        LastTimeRecord {
+            script_id,
+            humble_time: latest(&|e| e.humble),
+            exec_time: latest(&|e| !e.humble && e.ty == EventType::Exec),
+            exec_done_time: latest(&|e| !e.humble && e.ty == EventType::ExecDone),
+        }
+    }
+}
+
+fn match_dir(e: &RawEvent, dir: Option<&str>) -> bool {
+    dir.is_none() || e.dir.as_deref() == dir
+}
+fn args_key(e: &RawEvent, key: ArgsKey) -> (i64, Option<&str>, Option<&str>) {
+    match key {
+        ArgsKey::Args => (e.script_id, e.args.as_deref(), None),
+        ArgsKey::Envs => (e.script_id, None, e.envs.as_deref()),
+        ArgsKey::Both => (e.script_id, e.args.as_deref(), e.envs.as_deref()),
+    }
+}
+
+impl MemoryStorage {
+    /// 以 JSON Lines 檔案保存，檔案不存在時會在第一次寫入時建立
+    ///
+    /// 檔案要到第一次讀寫時才會讀進來，用不到歷史的指令不必付出讀取的代價
+    pub fn with_log(path: PathBuf) -> Self {
+        let inner = Inner {
+            state: State {
+                node: Some(random_node()),
+                ..Default::default()
+            },
+            log: Some(LogFile::new(path)),
+        };
+        MemoryStorage {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+    fn read<T>(&self, f: impl FnOnce(&State) -> T) -> Result<T, DBError> {
+        let Inner { state, log } = &mut *self.inner.lock().unwrap();
+        if let Some(log) = log {
+            log.sync(state)?;
+        }
+        Ok(f(state))
+    }
+    fn write<T>(&self, f: impl FnOnce(&mut State) -> Result<T, DBError>) -> Result<T, DBError> {
+        let Inner { state, log } = &mut *self.inner.lock().unwrap();
+        let Some(log) = log else {
+            let res = f(state);
+            state.pending.clear();
+            return res;
+        };
+        let mut file = log.lock()?;
+        log.sync_locked(&mut file, state)?;
+        let res = f(state);
+        log.append(&mut file, state.take_pending())?;
+        res
+    }
+}
+
+impl Storage for MemoryStorage {
+    async fn close(&self) {}
+
+    async fn insert(&self, event: DBEvent<'_>) -> Result<i64, DBError> {
+        self.write(|state| {
+            let id = state.next_event_id();
+            state.apply(Op::Insert(RawEvent::from_db(id, &event)));
+            Ok(id)
+        })
+    }
+    async fn main_event_flags(&self, event_id: i64) -> Result<Option<(bool, bool)>, DBError> {
+        self.read(|state| {
+            state
+                .events
+                .get(&event_id)
+                .filter(|e| State::is_main(e))
+                .map(|e| (e.ignored, e.humble))
+        })
+    }
+    async fn upgrade_pre_exec(&self, run_id: i64) -> Result<Option<(bool, bool)>, DBError> {
+        self.write(|state| {
+            let Some(e) = state
+                .events
+                .get(&run_id)
+                .filter(|e| e.ty == EventType::PreExec)
+            else {
+                return Ok(None);
+            };
+            let flags = (e.ignored, e.humble);
+            state.update(
+                run_id,
+                Patch {
+                    ty: Some(EventType::Exec),
+                    ..Default::default()
+                },
+            );
+            Ok(Some(flags))
+        })
+    }
+    async fn remove(&self, script_id: i64) -> Result<(), DBError> {
+        self.write(|state| {
+            let ids = state
+                .events()
+                .filter(|e| e.script_id == script_id)
+                .map(|e| e.id)
+                .collect();
+            state.apply(Op::Delete { ids });
+            Ok(())
+        })
+    }
+    async fn move_events(&self, from: i64, to: i64) -> Result<(), DBError> {
+        self.write(|state| {
+            state.apply(Op::Move { from, to });
+            Ok(())
+        })
+    }
+    async fn tombstones(&self) -> Result<Vec<(i64, String)>, DBError> {
+        self.read(|state| {
+            state
+                .events()
+                .filter(|e| e.script_id < 0 && e.ty == EventType::Delete)
+                .map(|e| (e.script_id, e.content.clone().unwrap_or_default()))
+                .collect()
+        })
+    }
+
+    async fn previous_args(
+        &self,
+        id: i64,
+        dir: Option<&Path>,
+        only_success: bool,
+    ) -> Result<Option<PreviousArgs>, DBError> {
+        let dir = dir.map(|p| p.to_string_lossy());
+        self.read(|state| {
+            let codes = state.codes();
+            state
+                .events()
+                .filter(|e| e.ty == EventType::Exec && e.script_id == id && !e.ignored)
+                .filter(|e| match_dir(e, dir.as_deref()))
+                .filter(|e| !only_success || codes.get(&e.id).and_then(|c| c.1) == Some("0"))
+                .max_by_key(|e| (e.time, e.id))
+                .map(|e| PreviousArgs {
+                    args: e.args.clone().unwrap_or_default(),
+                    envs: e.envs.clone().unwrap_or_default(),
+                    env_files: e.env_files.clone(),
+                    profile: e.profile.clone(),
+                })
+        })
+    }
+    async fn previous_args_list(&self, query: &ArgsQuery<'_>) -> Result<Vec<ArgsRecord>, DBError> {
+        log::info!("查詢歷史 {:?}", query.ids);
+        self.read(|state| {
+            let codes = state.codes();
+            let limit = usize::try_from(query.limit).unwrap_or(usize::MAX);
+            state
+                .args_records(query)
+                .into_iter()
+                .map(|e| (e, codes.get(&e.id).and_then(|c| c.1)))
+                .filter(|(_, code)| match query.status {
+                    StatusFilter::All => true,
+                    StatusFilter::Failed => code.is_some_and(|c| c != "0"),
+                    StatusFilter::Succeeded => *code == Some("0"),
+                })
+                .skip(query.offset as usize)
+                .take(limit)
+                .map(|(e, code)| ArgsRecord {
+                    event_id: e.id,
+                    script_id: e.script_id,
+                    args: match query.key {
+                        ArgsKey::Envs => String::new(),
+                        _ => e.args.clone().unwrap_or_default(),
+                    },
+                    envs: match query.key {
+                        ArgsKey::Args => String::new(),
+                        _ => e.envs.clone().unwrap_or_default(),
+                    },
+                    code: parse_code(code.map(str::to_owned)),
+                })
+                .collect()
+        })
+    }
+    async fn search(&self, query: &SearchQuery<'_>) -> Result<Vec<SearchRecord>, DBError> {
+        log::info!("搜尋歷史 {:?}", query);
+        let dir = query.dir.map(|p| p.to_string_lossy());
+        let env = match query.env {
+            Some(env) if env.contains('=') => format!("\"{}", env),
+            Some(env) => format!("\"{}=", env),
+            None => String::new(),
+        };
+        self.read(|state| {
+            let codes = state.codes();
+            let mut records: Vec<_> = state
+                .events()
+                .filter(|e| {
+                    e.ty == EventType::Exec && !e.ignored && query.ids.contains(&e.script_id)
+                })
+                .filter(|e| {
+                    query
+                        .pattern
+                        .is_none_or(|p| args_contain(e.args.as_deref(), p))
+                })
+                .filter(|e| match_dir(e, dir.as_deref()))
+                .filter(|e| query.since.is_none_or(|since| e.time >= since))
+                .filter(|e| {
+                    query.env.is_none() || e.envs.as_ref().is_some_and(|s| s.contains(&env))
+                })
+                .map(|e| (e, codes.get(&e.id).and_then(|c| c.1)))
+                .filter(|(_, code)| !query.failed || code.is_some_and(|c| c != "0"))
+                .collect();
+            records.sort_by_key(|(e, _)| Reverse((e.time, e.id)));
+            records
+                .into_iter()
+                .take(query.limit.map_or(usize::MAX, |l| l as usize))
+                .map(|(e, code)| SearchRecord {
+                    event_id: e.id,
+                    script_id: e.script_id,
+                    time: e.time,
+                    dir: e.dir.clone().unwrap_or_default(),
+                    args: e.args.clone().unwrap_or_default(),
+                    envs: e.envs.clone().unwrap_or_default(),
+                    code: parse_code(code.map(str::to_owned)),
+                })
+                .collect()
+        })
+    }
+    async fn log(&self, query: &LogQuery<'_>) -> Result<Vec<LogRecord>, DBError> {
+        log::info!("查詢事件時間軸 {:?}", query);
+        self.read(|state| {
+            let mut records: Vec<_> = state
+                .events()
+                .filter(|e| !e.ignored && query.ids.contains(&e.script_id))
+                .filter(|e| query.since.is_none_or(|since| e.time >= since))
+                .filter(|e| query.until.is_none_or(|until| e.time < until))
+                .filter(|e| e.id > query.after_id.unwrap_or(ZERO))
+                .collect();
+            records.sort_by_key(|e| Reverse((e.time, e.id)));
+            records
+                .into_iter()
+                .skip(query.offset as usize)
+                .take(query.limit.map_or(usize::MAX, |l| l as usize))
+                .map(|e| {
+                    let code = if e.ty == EventType::ExecDone {
+                        parse_code(e.content.clone())
+                    } else {
+                        None
+                    };
+                    LogRecord {
+                        event_id: e.id,
+                        script_id: e.script_id,
+                        ty: e.ty,
+                        time: e.time,
+                        args: e.args.clone(),
+                        dir: e.dir.clone(),
+                        code,
+                        content: e.content.clone(),
+                        repeat_count: e.repeat_count,
+                        humble: e.humble,
+                    }
+                })
+                .collect()
+        })
+    }
+    async fn query(&self, query: &EventQuery) -> Result<Vec<(RawEvent, Option<i32>)>, DBError> {
+        log::info!("查詢事件 {:?}", query);
+        let dir = query.dir_str();
+        let types = query.types.as_ref();
+        self.read(|state| {
+            let codes = state.codes();
+            let mut records: Vec<_> = state
+                .events()
+                .filter(|e| {
+                    query
+                        .script_ids
+                        .as_ref()
+                        .is_none_or(|ids| ids.contains(&e.script_id))
+                })
+                .filter(|e| types.is_none_or(|types| types.contains(&e.ty)))
+                .filter(|e| query.since.is_none_or(|since| e.time >= since))
+                .filter(|e| query.until.is_none_or(|until| e.time < until))
+                .filter(|e| match_dir(e, dir.as_deref()))
+                .filter(|e| query.humble.is_none_or(|humble| e.humble == humble))
+                .filter(|e| query.ignored.is_none_or(|ignored| e.ignored == ignored))
+                .map(|e| {
+                    let code = if e.ty == EventType::ExecDone {
+                        e.content.as_deref()
+                    } else {
+                        codes.get(&e.id).and_then(|c| c.1)
+                    };
+                    (e, code)
+                })
+                .filter(|(_, code)| query.match_code(*code))
+                .collect();
+            records.sort_by_key(|(e, _)| Reverse((e.time, e.id)));
+            records
+                .into_iter()
+                .skip(query.offset as usize)
+                .take(query.limit.map_or(usize::MAX, |l| l as usize))
+                .map(|(e, code)| (e.clone(), parse_code(code.map(str::to_owned))))
+                .collect()
+        })
+    }
+    async fn last_time(&self, script_id: i64) -> Result<LastTimeRecord, DBError> {
+        self.read(|state| state.last_time(script_id))
+    }
+
+    async fn begin_mutation(
+        &self,
+        time: NaiveDateTime,
+        kind: &str,
+        cmd: &str,
+    ) -> Result<(), DBError> {
+        self.write(|state| {
+            let id = state.next_mutation_id();
+            state.apply(Op::Begin {
+                id,
+                time,
+                kind: kind.to_owned(),
+                cmd: cmd.to_owned(),
+            });
+            Ok(())
+        })
+    }
+    async fn end_mutation(&self) -> Result<(), DBError> {
+        self.write(|state| {
+            state.apply(Op::End);
+            Ok(())
+        })
+    }
+    async fn mutations(&self, limit: u32) -> Result<Vec<MutationRecord>, DBError> {
+        self.read(|state| {
+            state
+                .mutations
+                .iter()
+                .rev()
+                .take(limit as usize)
+                .map(|m| MutationRecord {
+                    id: m.id,
+                    time: m.time,
+                    kind: m.kind.clone(),
+                    cmd: m.cmd.clone(),
+                    event_count: m.events.len() as i64,
+                })
+                .collect()
+        })
+    }
+    async fn undo_mutation(&self, mutation_id: i64) -> Result<Vec<i64>, DBError> {
+        self.write(|state| {
+            let mut script_ids = vec![];
+            let Some(m) = state.mutations.iter().find(|m| m.id == mutation_id) else {
+                return Ok(script_ids);
+            };
+            for e in m.events.clone().into_iter().rev() {
+                let Some(event) = state.events.get(&e.event_id) else {
+                    log::warn!("事件 {} 已不存在，無法復原", e.event_id);
+                    continue;
+                };
+                if !script_ids.contains(&event.script_id) {
+                    script_ids.push(event.script_id);
+                }
+                state.update(e.event_id, e.into_patch());
+            }
+            state.apply(Op::Forget { id: mutation_id });
+            Ok(script_ids)
+        })
+    }
+
+    async fn ignore_or_humble_by_id(
+        &self,
+        is_humble: bool,
+        event_id: NonZeroU64,
+    ) -> Result<(i64, i64), DBError> {
+        let event_id = event_id.get() as i64;
+        self.write(|state| {
+            let script_id = state
+                .events
+                .get(&event_id)
+                .map(|e| e.script_id)
+                .ok_or(DBError::RowNotFound)?;
+            let latest = state
+                .events()
+                .filter(|e| State::is_main(e) && e.script_id == script_id)
+                .max_by_key(|e| (e.time, e.id))
+                .map(|e| (e.id, e.script_id))
+                .ok_or(DBError::RowNotFound)?;
+            // TODO: check if this event is exec?
+            let target = state.events.get(&event_id);
+            if target.is_some_and(|e| State::is_main(e) && !e.ignored) {
+                state.mark(&[event_id], is_humble);
+            }
+            Ok(latest)
+        })
+    }
+    async fn ignore_args(&self, query: &ArgsQuery<'_>) -> Result<(), DBError> {
+        let dir = query.dir.map(|p| p.to_string_lossy());
+        self.write(|state| {
+            let limit = usize::try_from(query.limit).unwrap_or(usize::MAX);
+            let records = state.args_records(query);
+            let targets: Vec<_> = records
+                .into_iter()
+                .skip(query.offset as usize)
+                .take(limit)
+                .map(|e| args_key(e, query.key))
+                .collect();
+            let ids: Vec<_> = state
+                .events()
+                .filter(|e| State::is_main(e) && !e.ignored)
+                .filter(|e| match_dir(e, dir.as_deref()) && !(query.no_humble && e.humble))
+                .filter(|e| targets.contains(&args_key(e, query.key)))
+                .map(|e| e.id)
+                .collect();
+            state.mark(&ids, false);
+            Ok(())
+        })
+    }
+    async fn amend(
+        &self,
+        event_id: NonZeroU64,
+        args: &str,
+        envs: Option<&str>,
+    ) -> Result<(), DBError> {
+        let event_id = event_id.get() as i64;
+        self.write(|state| {
+            if !state.events.get(&event_id).is_some_and(State::is_main) {
+                return Ok(());
+            }
+            state.update(
+                event_id,
+                Patch {
+                    ignored: Some(false),
+                    args: Some(args.to_owned()),
+                    envs: envs.map(str::to_owned),
+                    ..Default::default()
+                },
+            );
+            Ok(())
+        })
+    }
+
+    async fn export(
+        &self,
+        script_ids: &[i64],
+        since: Option<NaiveDateTime>,
+    ) -> Result<Vec<RawEvent>, DBError> {
+        self.read(|state| {
+            let referred: Vec<_> = match since {
+                Some(since) => state
+                    .events()
+                    .filter(|e| e.time >= since && e.main_event_id != ZERO)
+                    .map(|e| e.main_event_id)
+                    .collect(),
+                None => vec![],
+            };
+            state
+                .events()
+                .filter(|e| script_ids.contains(&e.script_id))
+                .filter(|e| since.is_none_or(|since| e.time >= since) || referred.contains(&e.id))
+                .cloned()
+                .collect()
+        })
+    }
+    async fn find_duplicate(&self, event: &RawEvent) -> Result<Option<i64>, DBError> {
+        self.read(|state| {
+            state
+                .events()
+                .find(|e| {
+                    e.script_id == event.script_id
+                        && e.ty == event.ty
+                        && e.time == event.time
+                        && e.args == event.args
+                        && e.content == event.content
+                })
+                .map(|e| e.id)
+        })
+    }
+    async fn insert_raw(&self, event: &RawEvent, main_event_id: i64) -> Result<i64, DBError> {
+        self.write(|state| {
+            let id = state.next_event_id();
+            let event = RawEvent {
+                id,
+                main_event_id,
+                ..event.clone()
+            };
+            state.apply(Op::Insert(event));
+            Ok(id)
+        })
+    }
+
+    async fn tidy(
+        &self,
+        script_ids: &[i64],
+        policy: &RetentionPolicy,
+        dry_run: bool,
+    ) -> Result<TidyReport, DBError> {
+        let inner = &mut *self.inner.lock().unwrap();
+        let mut file = match &mut inner.log {
+            Some(log) => {
+                let mut file = log.lock()?;
+                log.sync_locked(&mut file, &mut inner.state)?;
+                Some(file)
+            }
+            None => None,
+        };
+        let state = &mut inner.state;
+
+        let mut to_delete: Vec<i64> = state
+            .events()
+            .filter(|e| e.script_id > 0 && !script_ids.contains(&e.script_id))
+            .map(|e| e.id)
+            .collect();
+        let before = |time: Option<NaiveDateTime>, e: &RawEvent| {
+            time.is_some_and(|time| e.time < time) && !e.ty.is_structural()
+        };
+        to_delete.extend(
+            state
+                .events()
+                .filter(|e| {
+                    (e.humble && before(policy.humble_before, e))
+                        || (e.ignored && before(policy.ignored_before, e))
+                })
+                .map(|e| e.id),
+        );
+
+        let mut execs: Vec<_> = state
+            .events()
+            .filter(|e| e.ty == EventType::Exec && !e.ignored && script_ids.contains(&e.script_id))
+            .collect();
+        execs.sort_by_key(|e| (e.script_id, e.time, e.id));
+        let mut report = TidyReport::default();
+        let mut counts: Vec<(i64, i64)> = vec![];
+        if policy.dedup_only() {
+            let execs: Vec<_> = execs
+                .into_iter()
+                .map(|e| ExecRow {
+                    id: e.id,
+                    script_id: e.script_id,
+                    key: (&e.args, &e.dir),
+                    repeat_count: e.repeat_count,
+                })
+                .collect();
+            let kept = latest_execs(&execs);
+            to_delete.extend(
+                state
+                    .events()
+                    .filter(|e| {
+                        !e.ty.is_structural()
+                            && script_ids.contains(&e.script_id)
+                            && !kept.contains(&e.id)
+                    })
+                    .map(|e| e.id),
+            );
+        } else {
+            let execs: Vec<_> = execs
+                .into_iter()
+                .map(|e| ExecRow {
+                    id: e.id,
+                    script_id: e.script_id,
+                    key: (&e.args, &e.dir, &e.envs, &e.env_files, &e.profile),
+                    repeat_count: e.repeat_count,
+                })
+                .collect();
+            let dropped_execs;
+            (dropped_execs, counts) = plan_exec_retention(&execs, policy, &mut report);
+            to_delete.extend(
+                state
+                    .events()
+                    .filter(|e| {
+                        (e.ty == EventType::ExecDone && dropped_execs.contains(&e.main_event_id))
+                            || dropped_execs.contains(&e.id)
+                    })
+                    .map(|e| e.id),
+            );
+        }
+        to_delete.sort_unstable();
+        to_delete.dedup();
+
+        report.rows = to_delete.len() as u64;
+        report.bytes = to_delete
+            .iter()
+            .filter_map(|id| state.events.get(id))
+            .map(RawEvent::estimated_size)
+            .sum();
+        if dry_run {
+            return Ok(report);
+        }
+
+        state.apply(Op::Delete { ids: to_delete });
+        for (id, count) in counts.into_iter() {
+            let patch = Patch {
+                repeat_count: Some(count),
+                ..Default::default()
+            };
+            state.update(id, patch);
+        }
+        state.pending.clear();
+        if let (Some(log), Some(file)) = (&mut inner.log, &mut file) {
+            report.file_size = Some(log.compact(file, state)?);
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use chrono::Duration;
+
+    fn exec(script_id: i64, time: NaiveDateTime, args: &'static str) -> Event<'static> {
+        Event {
+            script_id,
+            time,
+            humble: false,
+            data: EventData::Exec {
+                args,
+                envs: "[]",
+                env_files: None,
+                profile: None,
+                dir: None,
+                parent: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rm_and_undo() {
+        let historian = Historian::in_memory();
+        let start = chrono::Utc::now().naive_utc();
+        for (i, args) in ["[\"1\"]", "[\"2\"]", "[\"3\"]"].into_iter().enumerate() {
+            let time = start + Duration::seconds(i as i64);
+            historian.record(&exec(1, time, args)).await.unwrap();
+        }
+        let list = |h: Historian| async move {
+            h.previous_args_list(&[1], 10, 0, false, None, StatusFilter::All)
+                .await
+                .unwrap()
+                .map(|r| r.args)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            list(historian.clone()).await,
+            ["[\"3\"]", "[\"2\"]", "[\"1\"]"]
+        );
+
+        let min = std::num::NonZeroU64::new(1).unwrap();
+        let max = std::num::NonZeroU64::new(3);
+        let last_times = historian
+            .ignore_args_range(&[1], None, false, false, true, min, max)
+            .await
+            .unwrap();
+        assert_eq!(last_times[0].exec_time, Some(start));
+        assert_eq!(list(historian.clone()).await, ["[\"1\"]"]);
+
+        let (mutations, last_times) = historian.undo(1).await.unwrap();
+        assert_eq!(mutations[0].kind, "rm");
+        assert_eq!(mutations[0].event_count, 2);
+        assert_eq!(last_times[0].exec_time, Some(start + Duration::seconds(2)));
+        assert_eq!(
+            list(historian.clone()).await,
+            ["[\"3\"]", "[\"2\"]", "[\"1\"]"]
+        );
+        assert!(historian.mutations(10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tidy_dedup() {
+        let historian = Historian::in_memory();
+        let start = chrono::Utc::now().naive_utc();
+        for (i, args) in ["[\"1\"]", "[\"2\"]", "[\"1\"]", "[\"2\"]", "[\"3\"]"]
+            .into_iter()
+            .enumerate()
+        {
+            let time = start + Duration::seconds(i as i64);
+            historian.record(&exec(1, time, args)).await.unwrap();
+        }
+        let report = historian
+            .tidy(&[1], &RetentionPolicy::default(), false)
+            .await
+            .unwrap();
+        assert_eq!(report.collapsed, 0);
+        assert_eq!(report.rows, 2);
+
+        let query = LogQuery {
+            ids: &[1],
+            ..Default::default()
+        };
+        let args: Vec<_> = historian
+            .log(&query)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.args.unwrap())
+            .collect();
+        assert_eq!(args, ["[\"3\"]", "[\"2\"]", "[\"1\"]"]);
+    }
+
+    #[tokio::test]
+    async fn test_tidy_collapse() {
+        let historian = Historian::in_memory();
+        let start = chrono::Utc::now().naive_utc();
+        for (i, args) in ["[\"1\"]", "[\"1\"]", "[\"2\"]", "[\"2\"]", "[\"2\"]"]
+            .into_iter()
+            .enumerate()
+        {
+            let time = start + Duration::seconds(i as i64);
+            historian.record(&exec(1, time, args)).await.unwrap();
+        }
+        historian.record(&exec(2, start, "[]")).await.unwrap();
+        let policy = RetentionPolicy {
+            collapse_repeats: true,
+            ..Default::default()
+        };
+        let report = historian.tidy(&[1], &policy, false).await.unwrap();
+        assert_eq!(report.collapsed, 3);
+        assert_eq!(report.rows, 4);
+
+        let query = LogQuery {
+            ids: &[1, 2],
+            ..Default::default()
+        };
+        let counts: Vec<_> = historian
+            .log(&query)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.args.unwrap(), r.repeat_count))
+            .collect();
+        assert_eq!(
+            counts,
+            [("[\"2\"]".to_owned(), 3), ("[\"1\"]".to_owned(), 2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let historian = Historian::in_memory();
+        let start = chrono::Utc::now().naive_utc();
+        for (i, (args, code)) in [("[\"a\"]", 0), ("[\"b\"]", 1), ("[\"c\"]", 2)]
+            .into_iter()
+            .enumerate()
+        {
+            let time = start + Duration::seconds(i as i64);
+            let mut event = exec(1, time, args);
+            event.data = EventData::Exec {
+                args,
+                envs: "[\"A=1=2\"]",
+                env_files: None,
+                profile: None,
+                dir: Some(std::path::Path::new("/tmp")),
+                parent: None,
+            };
+            let id = historian.record(&event).await.unwrap();
+            let done = Event {
+                data: EventData::ExecDone {
+                    code,
+                    main_event_id: id,
+                },
+                ..event
+            };
+            historian.record(&done).await.unwrap();
+        }
+        historian.record(&exec(2, start, "[]")).await.unwrap();
+
+        let query = EventQuery::new()
+            .scripts([1])
+            .types([EventType::Exec])
+            .status(StatusFilter::Failed);
+        let records = historian.query(&query).await.unwrap();
+        let args: Vec<_> = records.iter().map(|r| r.args.clone()).collect();
+        assert_eq!(args, [["c"], ["b"]]);
+        assert_eq!(records[0].code, Some(2));
+        assert_eq!(
+            records[0].dir.as_deref(),
+            Some(std::path::Path::new("/tmp"))
+        );
+        let env = &records[0].envs[0];
+        assert_eq!((env.key.as_str(), env.val.as_str()), ("A", "1=2"));
+
+        let query = EventQuery::new().types([EventType::ExecDone]).code(0);
+        let records = historian.query(&query).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].main_event_id, Some(1));
+
+        let query = EventQuery::new().offset(1).limit(2);
+        let ids: Vec<_> = historian
+            .query(&query)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(ids, [5, 4]);
+    }
+
+    /// 兩台機器從同一個檔案分岔後各自附加，合併後不該遺失任何事件
+    #[tokio::test]
+    async fn test_merge_divergent_logs() {
+        use super::MemoryStorage;
+        use crate::storage::Backend;
+        use std::num::NonZeroU64;
+
+        let dir = std::env::temp_dir().join(format!("hs-historian-merge-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let open = |name: &str, node: i64| {
+            let storage = MemoryStorage::with_log(dir.join(name));
+            storage.inner.lock().unwrap().state.node = Some(node);
+            Historian {
+                storage: Backend::Memory(storage),
+            }
+        };
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+        let start = chrono::Utc::now().naive_utc();
+        let time = |i: i64| start + Duration::seconds(i);
+
+        let a = open("a.jsonl", 1);
+        a.record(&exec(1, time(0), "[\"base\"]")).await.unwrap();
+        let base = read("a.jsonl");
+        std::fs::write(dir.join("b.jsonl"), &base).unwrap();
+
+        a.record(&exec(1, time(1), "[\"a\"]")).await.unwrap();
+        let b = open("b.jsonl", 2);
+        let b1 = b.record(&exec(1, time(2), "[\"b1\"]")).await.unwrap();
+        b.record(&exec(1, time(3), "[\"b2\"]")).await.unwrap();
+        let b1 = NonZeroU64::new(b1 as u64).unwrap();
+        b.ignore_args_by_id(b1).await.unwrap();
+
+        // 如同 git 以 union 合併：共同的開頭，再接上兩邊各自附加的行
+        let merged = base.clone() + &read("a.jsonl")[base.len()..] + &read("b.jsonl")[base.len()..];
+        std::fs::write(dir.join("merged.jsonl"), merged).unwrap();
+        let merged = open("merged.jsonl", 3);
+        let args: Vec<_> = merged
+            .previous_args_list(&[1], 10, 0, false, None, StatusFilter::All)
+            .await
+            .unwrap()
+            .map(|r| r.args)
+            .collect();
+        assert_eq!(args, ["[\"b2\"]", "[\"a\"]", "[\"base\"]"]);
+        assert_eq!(merged.mutations(10).await.unwrap().len(), 1);
+
+        merged
+            .record(&exec(1, time(4), "[\"after\"]"))
+            .await
+            .unwrap();
+        let ids: Vec<_> = merged
+            .query(&EventQuery::new().ignored(None))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        let mut unique = ids.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(ids.len(), 5);
+        assert_eq!(unique.len(), 5);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
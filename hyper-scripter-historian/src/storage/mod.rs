@@ -0,0 +1,399 @@
+use crate::{
+    ArgsRecord, DBEvent, EventQuery, LastTimeRecord, LogQuery, LogRecord, MutationRecord,
+    PreviousArgs, RawEvent, RetentionPolicy, SearchQuery, SearchRecord, StatusFilter, TidyReport,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::error::Error as DBError;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::num::NonZeroU64;
+use std::path::Path;
+
+mod jsonl;
+mod memory;
+mod sqlite;
+pub use memory::MemoryStorage;
+pub use sqlite::SqliteStorage;
+
+/// 歷史的儲存方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    /// 存在家目錄下的 SQLite 資料庫
+    #[default]
+    Sqlite,
+    /// 存在家目錄下只會附加的 JSON Lines 檔案，方便以 git 管理
+    Jsonl,
+    /// 只存在記憶體中，程式結束即消失，用於測試及效能評測
+    Memory,
+}
+
+/// 參數歷史以哪些欄位區分
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgsKey {
+    Args,
+    Envs,
+    Both,
+}
+
+/// 查詢或忽略一段參數歷史的條件，同樣的參數只取最新的一筆
+#[derive(Debug)]
+pub struct ArgsQuery<'a> {
+    pub ids: &'a [i64],
+    pub dir: Option<&'a Path>,
+    pub no_humble: bool,
+    pub status: StatusFilter,
+    pub key: ArgsKey,
+    /// 負數代表不限
+    pub limit: i64,
+    pub offset: i64,
+}
+
+/// 歷史的儲存後端，`Historian` 只負責把事件轉成儲存的格式，其餘都交給後端
+///
+/// 僅供本 crate 內部使用，後端只有 `Backend` 列出的幾種，以 `dispatch!` 靜態分派，
+/// 因此方法可直接寫成 `async fn`，不必為了 `dyn Storage` 把每個結果都裝箱
+///
+/// 所有修改事件 `ignored`、`humble`、`args` 或 `envs` 欄位的操作，若發生在
+/// `begin_mutation` 和 `end_mutation` 之間，都要記下原值供 `undo_mutation` 使用
+pub(crate) trait Storage {
+    async fn close(&self);
+
+    async fn insert(&self, event: DBEvent<'_>) -> Result<i64, DBError>;
+    /// 回傳執行事件（或預執行事件）的 `(ignored, humble)`
+    async fn main_event_flags(&self, event_id: i64) -> Result<Option<(bool, bool)>, DBError>;
+    /// 將預執行事件升級為執行事件，回傳升級前的 `(ignored, humble)`
+    async fn upgrade_pre_exec(&self, run_id: i64) -> Result<Option<(bool, bool)>, DBError>;
+    async fn remove(&self, script_id: i64) -> Result<(), DBError>;
+    async fn move_events(&self, from: i64, to: i64) -> Result<(), DBError>;
+    async fn tombstones(&self) -> Result<Vec<(i64, String)>, DBError>;
+
+    async fn previous_args(
+        &self,
+        id: i64,
+        dir: Option<&Path>,
+        only_success: bool,
+    ) -> Result<Option<PreviousArgs>, DBError>;
+    async fn previous_args_list(&self, query: &ArgsQuery<'_>) -> Result<Vec<ArgsRecord>, DBError>;
+    async fn search(&self, query: &SearchQuery<'_>) -> Result<Vec<SearchRecord>, DBError>;
+    async fn log(&self, query: &LogQuery<'_>) -> Result<Vec<LogRecord>, DBError>;
+    /// 回傳符合條件的事件及其返回碼，由新到舊排序
+    async fn query(&self, query: &EventQuery) -> Result<Vec<(RawEvent, Option<i32>)>, DBError>;
+    async fn last_time(&self, script_id: i64) -> Result<LastTimeRecord, DBError>;
+
+    async fn begin_mutation(
+        &self,
+        time: NaiveDateTime,
+        kind: &str,
+        cmd: &str,
+    ) -> Result<(), DBError>;
+    /// 關上日誌，並清理沒有修改任何事件及過舊的日誌
+    async fn end_mutation(&self) -> Result<(), DBError>;
+    /// 由新到舊列出日誌
+    async fn mutations(&self, limit: u32) -> Result<Vec<MutationRecord>, DBError>;
+    /// 復原並刪除一筆日誌，回傳受影響的腳本
+    async fn undo_mutation(&self, mutation_id: i64) -> Result<Vec<i64>, DBError>;
+
+    /// 回傳該事件所屬腳本最新的 `(執行事件, 腳本)`
+    async fn ignore_or_humble_by_id(
+        &self,
+        is_humble: bool,
+        event_id: NonZeroU64,
+    ) -> Result<(i64, i64), DBError>;
+    async fn ignore_args(&self, query: &ArgsQuery<'_>) -> Result<(), DBError>;
+    async fn amend(
+        &self,
+        event_id: NonZeroU64,
+        args: &str,
+        envs: Option<&str>,
+    ) -> Result<(), DBError>;
+
+    async fn export(
+        &self,
+        script_ids: &[i64],
+        since: Option<NaiveDateTime>,
+    ) -> Result<Vec<RawEvent>, DBError>;
+    /// 找出已存在的相同事件，比對腳本、類型、時間、參數及內容
+    async fn find_duplicate(&self, event: &RawEvent) -> Result<Option<i64>, DBError>;
+    async fn insert_raw(&self, event: &RawEvent, main_event_id: i64) -> Result<i64, DBError>;
+
+    async fn tidy(
+        &self,
+        script_ids: &[i64],
+        policy: &RetentionPolicy,
+        dry_run: bool,
+    ) -> Result<TidyReport, DBError>;
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Backend {
+    Sqlite(SqliteStorage),
+    Memory(MemoryStorage),
+}
+
+macro_rules! dispatch {
+    ($backend:expr, $storage:ident => $call:expr) => {
+        match $backend {
+            crate::storage::Backend::Sqlite($storage) => $call.await,
+            crate::storage::Backend::Memory($storage) => $call.await,
+        }
+    };
+}
+pub(crate) use dispatch;
+
+/// 參數以空白串接後是否包含 `pattern`，區分大小寫
+pub fn args_contain(args: Option<&str>, pattern: &str) -> bool {
+    let args: Vec<String> = args
+        .and_then(|args| serde_json::from_str(args).ok())
+        .unwrap_or_default();
+    args.join(" ").contains(pattern)
+}
+
+/// tidy 時用來判斷是否為重複執行的資料
+pub struct ExecRow<K> {
+    pub id: i64,
+    pub script_id: i64,
+    pub key: K,
+    pub repeat_count: i64,
+}
+
+/// 預設的整理方式，每支腳本的每組 `key` 只留下最新一筆執行事件
+///
+/// `execs` 須依腳本、時間排序
+pub fn latest_execs<K: Eq + Hash>(execs: &[ExecRow<K>]) -> Vec<i64> {
+    let mut latest = HashMap::new();
+    for exec in execs.iter() {
+        latest.insert((exec.script_id, &exec.key), exec.id);
+    }
+    let mut ids: Vec<_> = latest.into_values().collect();
+    ids.sort_unstable();
+    ids
+}
+
+/// 依保留規則決定要丟棄的執行事件，以及合併後需要更新累計次數的執行事件
+///
+/// `execs` 須依腳本、時間排序
+pub fn plan_exec_retention<K: PartialEq>(
+    execs: &[ExecRow<K>],
+    policy: &RetentionPolicy,
+    report: &mut TidyReport,
+) -> (Vec<i64>, Vec<(i64, i64)>) {
+    let mut counts: Vec<(i64, i64)> = vec![]; // (合併後保留的執行事件, 累計次數)
+    let mut dropped_execs: Vec<i64> = vec![];
+    for script_execs in execs.chunk_by(|a, b| a.script_id == b.script_id) {
+        let mut kept = vec![];
+        for exec in script_execs.iter() {
+            match kept.last_mut() {
+                Some((last_id, last_key, count, merged))
+                    if policy.collapse_repeats && *last_key == &exec.key =>
+                {
+                    dropped_execs.push(*last_id);
+                    report.collapsed += 1;
+                    *last_id = exec.id;
+                    *count += exec.repeat_count;
+                    *merged = true;
+                }
+                _ => kept.push((exec.id, &exec.key, exec.repeat_count, false)),
+            }
+        }
+        let keep_execs = policy.keep_execs.map_or(kept.len(), |n| n as usize);
+        let overflow = kept.len().saturating_sub(keep_execs);
+        dropped_execs.extend(kept[..overflow].iter().map(|(id, ..)| *id));
+        counts.extend(
+            kept[overflow..]
+                .iter()
+                .filter(|(.., merged)| *merged)
+                .map(|(id, _, count, _)| (*id, *count)),
+        );
+    }
+    (dropped_execs, counts)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+    use chrono::Duration;
+
+    fn temp_home(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("hs-historian-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn exec<'a>(script_id: i64, time: NaiveDateTime, args: &'a str, dir: &'a Path) -> Event<'a> {
+        Event {
+            script_id,
+            time,
+            humble: false,
+            data: EventData::Exec {
+                args,
+                envs: "[\"K=v\"]",
+                env_files: None,
+                profile: None,
+                dir: Some(dir),
+                parent: None,
+            },
+        }
+    }
+
+    /// 對歷史做一連串的操作，並把每一步的結果以字串記下
+    async fn scenario(h: &Historian) -> Vec<String> {
+        let mut out = vec![];
+        let start = NaiveDateTime::parse_from_str("2024-01-31 12:00:00", "%Y-%m-%d %H:%M:%S");
+        let time = |i: i64| start.unwrap() + Duration::seconds(i);
+        let (d1, d2) = (Path::new("/d1"), Path::new("/d2"));
+        let runs = [
+            (1, "[\"a\"]", d1, 0),
+            (1, "[\"b\"]", d1, 1),
+            (1, "[\"a\"]", d2, 0),
+            (1, "[\"a\"]", d2, 0),
+            (2, "[\"x y\"]", d1, 2),
+            (2, "[\"z\"]", d2, 0),
+        ];
+        for (i, (script_id, args, dir, code)) in runs.into_iter().enumerate() {
+            let t = time(i as i64 * 2);
+            let main_event_id = h.record(&exec(script_id, t, args, dir)).await.unwrap();
+            let done = Event {
+                script_id,
+                time: t + Duration::seconds(1),
+                humble: false,
+                data: EventData::ExecDone {
+                    code,
+                    main_event_id,
+                },
+            };
+            h.record(&done).await.unwrap();
+        }
+        let rename = Event {
+            script_id: 2,
+            time: time(20),
+            humble: false,
+            data: EventData::Rename {
+                from: "s2",
+                to: "s3",
+            },
+        };
+        h.record(&rename).await.unwrap();
+
+        let ids = [1, 2];
+        macro_rules! check {
+            ($e:expr) => {
+                out.push(format!("{:?}", $e));
+            };
+        }
+        check!(h.previous_args(1, None, false).await.unwrap());
+        check!(h.previous_args(1, Some(d1), true).await.unwrap());
+        for status in [StatusFilter::All, StatusFilter::Failed] {
+            let list = h.previous_args_list(&ids, 10, 0, false, None, status);
+            check!(list.await.unwrap().collect::<Vec<_>>());
+        }
+        let list = h.previous_args_list_with_envs(&ids, 10, 0, false, Some(d2), StatusFilter::All);
+        check!(list.await.unwrap().collect::<Vec<_>>());
+        let log = LogQuery {
+            ids: &ids,
+            ..Default::default()
+        };
+        check!(h.log(&log).await.unwrap());
+        let search = SearchQuery {
+            ids: &ids,
+            pattern: Some("x y"),
+            ..Default::default()
+        };
+        check!(h.search(&search).await.unwrap());
+        let query = EventQuery::new()
+            .types([EventType::Exec])
+            .status(StatusFilter::Succeeded);
+        check!(h.query(&query).await.unwrap());
+
+        let first = NonZeroU64::new(1).unwrap();
+        check!(h.humble_args_by_id(first).await.unwrap());
+        let latest = NonZeroU64::new(11).unwrap();
+        check!(h.ignore_args_by_id(latest).await.unwrap());
+        check!(h.mutations(10).await.unwrap().len());
+        check!(h.undo(1).await.unwrap().1);
+        check!(h.export(&ids, None).await.unwrap());
+
+        let report = h
+            .tidy(&[1], &RetentionPolicy::default(), true)
+            .await
+            .unwrap();
+        check!((report.rows, report.bytes, report.collapsed));
+        let policy = RetentionPolicy {
+            collapse_repeats: true,
+            ..Default::default()
+        };
+        let report = h.tidy(&ids, &policy, false).await.unwrap();
+        check!((report.rows, report.bytes, report.collapsed));
+        check!(h.log(&log).await.unwrap());
+        out
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_memory_parity() {
+        let dir = temp_home("parity");
+        let sqlite = Historian::open(dir.clone(), StorageKind::Sqlite)
+            .await
+            .unwrap();
+        let expected = scenario(&sqlite).await;
+        sqlite.close().await;
+
+        let memory = Historian::in_memory();
+        let actual = scenario(&memory).await;
+        for (expected, actual) in expected.iter().zip(actual.iter()) {
+            assert_eq!(expected, actual);
+        }
+        assert_eq!(expected.len(), actual.len());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_switch_backend() {
+        let dir = temp_home("switch");
+        let sqlite = Historian::open(dir.clone(), StorageKind::Sqlite)
+            .await
+            .unwrap();
+        scenario(&sqlite).await;
+        let exported = sqlite.export(&[1, 2], None).await.unwrap();
+        sqlite.close().await;
+
+        let jsonl = Historian::open(dir.clone(), StorageKind::Jsonl)
+            .await
+            .unwrap();
+        assert!(!db::get_file(&dir).exists(), "舊檔案應被改名");
+        // 匯入時 id 會重新編號
+        let strip_ids = |events: Vec<RawEvent>| {
+            events
+                .into_iter()
+                .map(|e| RawEvent {
+                    id: 0,
+                    main_event_id: 0,
+                    ..e
+                })
+                .collect::<Vec<_>>()
+        };
+        let migrated = jsonl.export(&[1, 2], None).await.unwrap();
+        assert_eq!(
+            format!("{:?}", strip_ids(migrated)),
+            format!("{:?}", strip_ids(exported.clone()))
+        );
+        jsonl.close().await;
+
+        // 目前的歷史不是空的，就不會再搬移，舊檔案也原封不動
+        let mut migrated = db::get_file(&dir).into_os_string();
+        migrated.push(".migrated");
+        std::fs::rename(&migrated, db::get_file(&dir)).unwrap();
+        let jsonl = Historian::open(dir.clone(), StorageKind::Jsonl)
+            .await
+            .unwrap();
+        assert_eq!(
+            jsonl.export(&[1, 2], None).await.unwrap().len(),
+            exported.len()
+        );
+        jsonl.close().await;
+        assert!(db::get_file(&dir).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,1078 @@
+use super::{latest_execs, plan_exec_retention, ArgsKey, ArgsQuery, ExecRow, Storage};
+use crate::*;
+use sqlx::{Pool, Sqlite, SqlitePool};
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Clone)]
+pub struct SqliteStorage {
+    pool: Arc<RwLock<SqlitePool>>,
+    dir_path: PathBuf,
+}
+
+async fn raw_record_event(pool: &Pool<Sqlite>, event: DBEvent<'_>) -> Result<i64, DBError> {
+    let ty = event.ty.get_code();
+    let res = sqlx::query!(
+        "
+        INSERT INTO events
+        (script_id, type, cmd, args, content, time, main_event_id, dir, envs, env_files, profile,
+        humble, parent_id)
+        VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        RETURNING id
+        ",
+        event.script_id,
+        ty,
+        event.cmd,
+        event.args,
+        event.content,
+        event.time,
+        event.main_event_id,
+        event.dir,
+        event.envs,
+        event.env_files,
+        event.profile,
+        event.humble,
+        event.parent_id
+    )
+    // NOTE: fetch_one 拿到 RETURNING 的結果就停下，敘述沒跑完，寫入對其它連線可能還不可見，
+    // 緊接著查詢主要事件的 ExecDone 就會找不到它
+    .fetch_all(pool)
+    .await?;
+    Ok(res[0].id)
+}
+
+macro_rules! last_arg {
+    ($select:literal, $offset:expr, $limit:expr, $failed:expr, $succeeded:expr, $group_by:literal, $where:literal $(+ $more_where:literal)* , $($var:expr),*) => {{
+        // NOTE: 搭配 max(time) 時，SQLite 保證其它欄位取自時間最大的那一列，故 id 即為最新的執行事件
+        sqlx::query!(
+            "
+            WITH args AS (
+                SELECT id, " + $select + ", max(time) as time FROM events
+                WHERE type = ? AND NOT ignored "
+                +
+                $where
+                $(+ $more_where)*
+                +
+                " GROUP BY script_id " + $group_by + "
+            ), status AS (
+                SELECT *, (
+                    SELECT content FROM events d
+                    WHERE d.type = ? AND d.main_event_id = args.id
+                    ORDER BY d.time DESC LIMIT 1
+                ) as code FROM args
+            ) SELECT id, code, "
+                + $select
+                + " FROM status
+            WHERE (NOT ? OR code != '0') AND (NOT ? OR code = '0')
+            ORDER BY time DESC LIMIT ? OFFSET ?
+            ",
+            EXEC_CODE,
+            $($var, )*
+            EXEC_DONE_CODE,
+            $failed,
+            $succeeded,
+            $limit,
+            $offset,
+        )
+    }};
+}
+macro_rules! do_last_arg {
+    ($select:literal, $group_by:literal, $query:expr, $storage:expr) => {{
+        let query = $query;
+        let ids = join_id_str(query.ids);
+        log::info!("查詢歷史 {}", ids);
+        let no_dir = query.dir.is_none();
+        let dir = query.dir.map(|p| p.to_string_lossy());
+        let dir = dir.as_deref().unwrap_or(EMPTY_STR);
+        let failed = query.status == StatusFilter::Failed;
+        let succeeded = query.status == StatusFilter::Succeeded;
+        // FIXME: 一旦可以綁定陣列就換掉這個醜死人的 instr
+        last_arg!(
+            $select,
+            query.offset,
+            query.limit,
+            failed,
+            succeeded,
+            $group_by,
+            "
+            AND instr(?, '[' || script_id || ']') > 0 AND (? OR dir = ?)
+            AND (NOT ? OR NOT humble)
+            ",
+            ids,
+            no_dir,
+            dir,
+            query.no_humble
+        )
+        .fetch_all(&*$storage.pool.read().unwrap())
+        .await
+    }};
+}
+
+macro_rules! ignore_or_humble_arg {
+    ($ignore_or_humble:literal, $pool:expr, $cond:literal $(+ $more_cond:literal)*, $($var:expr),+) => {
+        sqlx::query!(
+            "
+            UPDATE events SET " + $ignore_or_humble + " = true
+            WHERE type = ? AND main_event_id IN (
+                SELECT id FROM events WHERE (type = ? OR type = ?) AND NOT ignored AND "
+                + $cond $(+ $more_cond)*
+                + "
+            )
+            ",
+            EXEC_DONE_CODE,
+            EXEC_CODE,
+            PRE_EXEC_CODE,
+            $($var),*
+        )
+        .execute(&*$pool)
+        .await?;
+
+        sqlx::query!(
+            "
+            UPDATE events SET " + $ignore_or_humble + " = true
+            WHERE (type = ? OR type = ?) AND NOT ignored AND
+            "
+                + $cond $(+ $more_cond)*,
+            EXEC_CODE,
+            PRE_EXEC_CODE,
+            $($var),*
+        )
+        .execute(&*$pool)
+        .await?;
+    };
+}
+
+/// 三字元以下的詞無法用 trigram 索引，含引號或反斜線者在 JSON 中會被轉義，皆不放進全文檢索
+///
+/// 全文檢索不分大小寫，只用來縮小範圍，最後仍須精確比對
+fn fts_match_expr(pattern: &str) -> Option<String> {
+    let exprs: Vec<_> = pattern
+        .split_whitespace()
+        .filter(|t| t.chars().count() >= 3 && !t.contains(['"', '\\']))
+        .map(|t| format!("\"{}\"", t))
+        .collect();
+    if exprs.is_empty() {
+        None
+    } else {
+        Some(exprs.join(" AND "))
+    }
+}
+
+impl SqliteStorage {
+    pub async fn new(dir_path: PathBuf) -> Result<Self, DBError> {
+        db::get_pool(&dir_path).await.map(|pool| SqliteStorage {
+            pool: Arc::new(RwLock::new(pool)),
+            dir_path,
+        })
+    }
+}
+
+impl Storage for SqliteStorage {
+    async fn close(&self) {
+        log::info!("close the historian database");
+        if let Ok(pool) = self.pool.read() {
+            pool.close().await;
+        }
+    }
+    async fn insert(&self, event: DBEvent<'_>) -> Result<i64, DBError> {
+        let pool = &mut *self.pool.write().unwrap();
+        let res = raw_record_event(pool, event).await;
+        if res.is_err() {
+            pool.close().await;
+            log::warn!("資料庫錯誤 {:?}，再試最後一次！", res);
+            *pool = db::get_pool(&self.dir_path).await?;
+            return raw_record_event(pool, event).await;
+        }
+
+        res
+    }
+    async fn main_event_flags(&self, event_id: i64) -> Result<Option<(bool, bool)>, DBError> {
+        let res = sqlx::query!(
+            "SELECT ignored, humble FROM events WHERE (type = ? OR type = ?) AND id = ?",
+            EXEC_CODE,
+            PRE_EXEC_CODE,
+            event_id
+        )
+        .fetch_optional(&*self.pool.read().unwrap())
+        .await?;
+        Ok(res.map(|e| (e.ignored, e.humble)))
+    }
+    async fn upgrade_pre_exec(&self, run_id: i64) -> Result<Option<(bool, bool)>, DBError> {
+        let pool = self.pool.read().unwrap();
+
+        let main_event = sqlx::query!(
+            "SELECT ignored, humble FROM events WHERE type = ? AND id = ?",
+            PRE_EXEC_CODE,
+            run_id,
+        )
+        .fetch_optional(&*pool)
+        .await?;
+
+        sqlx::query!(
+            "UPDATE events SET type = ? WHERE type = ? AND id = ?",
+            EXEC_CODE,
+            PRE_EXEC_CODE,
+            run_id,
+        )
+        .execute(&*pool)
+        .await?;
+
+        Ok(main_event.map(|e| (e.ignored, e.humble)))
+    }
+    async fn remove(&self, script_id: i64) -> Result<(), DBError> {
+        let pool = self.pool.read().unwrap();
+        sqlx::query!("DELETE FROM events WHERE script_id = ?", script_id,)
+            .execute(&*pool)
+            .await?;
+        Ok(())
+    }
+    async fn move_events(&self, from: i64, to: i64) -> Result<(), DBError> {
+        let pool = self.pool.read().unwrap();
+        sqlx::query!(
+            "UPDATE events SET script_id = ? WHERE script_id = ?",
+            to,
+            from
+        )
+        .execute(&*pool)
+        .await?;
+        Ok(())
+    }
+    async fn tombstones(&self) -> Result<Vec<(i64, String)>, DBError> {
+        let res = sqlx::query!(
+            "SELECT script_id, content FROM events WHERE script_id < 0 AND type = ?",
+            DELETE_CODE
+        )
+        .fetch_all(&*self.pool.read().unwrap())
+        .await?;
+        Ok(res
+            .into_iter()
+            .map(|res| (res.script_id, res.content.unwrap_or_default()))
+            .collect())
+    }
+
+    async fn previous_args(
+        &self,
+        id: i64,
+        dir: Option<&Path>,
+        only_success: bool,
+    ) -> Result<Option<PreviousArgs>, DBError> {
+        let no_dir = dir.is_none();
+        let dir = dir.map(|p| p.to_string_lossy());
+        let dir = dir.as_deref().unwrap_or(EMPTY_STR);
+        let res = sqlx::query!(
+            "
+            SELECT args, envs, env_files, profile FROM events e
+            WHERE type = ? AND script_id = ? AND NOT ignored
+            AND (? OR dir = ?)
+            AND (NOT ? OR (
+                SELECT content FROM events d
+                WHERE d.type = ? AND d.main_event_id = e.id
+                ORDER BY d.time DESC LIMIT 1
+            ) = '0')
+            ORDER BY time DESC LIMIT 1
+            ",
+            EXEC_CODE,
+            id,
+            no_dir,
+            dir,
+            only_success,
+            EXEC_DONE_CODE
+        )
+        .fetch_optional(&*self.pool.read().unwrap())
+        .await?;
+        Ok(res.map(|res| PreviousArgs {
+            args: res.args.unwrap_or_default(),
+            envs: res.envs.unwrap_or_default(),
+            env_files: res.env_files,
+            profile: res.profile,
+        }))
+    }
+    async fn previous_args_list(&self, query: &ArgsQuery<'_>) -> Result<Vec<ArgsRecord>, DBError> {
+        let res = match query.key {
+            ArgsKey::Args => do_last_arg!("script_id, args", ", args", query, self)?
+                .into_iter()
+                .map(|res| ArgsRecord {
+                    event_id: res.id,
+                    script_id: res.script_id,
+                    args: res.args.unwrap_or_default(),
+                    envs: String::new(),
+                    code: parse_code(res.code),
+                })
+                .collect(),
+            ArgsKey::Both => do_last_arg!("script_id, args, envs", ", args, envs", query, self)?
+                .into_iter()
+                .map(|res| ArgsRecord {
+                    event_id: res.id,
+                    script_id: res.script_id,
+                    args: res.args.unwrap_or_default(),
+                    envs: res.envs.unwrap_or_default(),
+                    code: parse_code(res.code),
+                })
+                .collect(),
+            ArgsKey::Envs => do_last_arg!("script_id, envs", ", envs", query, self)?
+                .into_iter()
+                .map(|res| ArgsRecord {
+                    event_id: res.id,
+                    script_id: res.script_id,
+                    args: String::new(),
+                    envs: res.envs.unwrap_or_default(),
+                    code: parse_code(res.code),
+                })
+                .collect(),
+        };
+        Ok(res)
+    }
+    async fn search(&self, query: &SearchQuery<'_>) -> Result<Vec<SearchRecord>, DBError> {
+        let ids = join_id_str(query.ids);
+        let fts = query.pattern.and_then(fts_match_expr);
+        let no_fts = fts.is_none();
+        let fts = fts.unwrap_or_default();
+        let no_pattern = query.pattern.is_none();
+        let pattern = query.pattern.unwrap_or_default();
+        let no_dir = query.dir.is_none();
+        let dir = query.dir.map(|p| p.to_string_lossy());
+        let dir = dir.as_deref().unwrap_or(EMPTY_STR);
+        let no_since = query.since.is_none();
+        let since = query.since.unwrap_or_default();
+        let no_env = query.env.is_none();
+        let env = match query.env {
+            Some(env) if env.contains('=') => format!("\"{}", env),
+            Some(env) => format!("\"{}=", env),
+            None => String::new(),
+        };
+        let limit = query.limit.map_or(-1, |l| l as i64);
+        log::info!("搜尋歷史 {:?} {}", query, fts);
+
+        // FIXME: 一旦可以綁定陣列就換掉這個醜死人的 instr
+        let res = sqlx::query!(
+            r#"
+            SELECT * FROM (
+                SELECT e.id, e.script_id, e.time, e.dir, e.args, e.envs,
+                    (SELECT d.content FROM events d
+                    WHERE d.type = ? AND d.main_event_id = e.id
+                    ORDER BY d.time DESC LIMIT 1) as code
+                FROM events e
+                WHERE e.type = ? AND NOT e.ignored
+                AND instr(?, '[' || e.script_id || ']') > 0
+                AND (? OR e.id IN (SELECT rowid FROM events_fts WHERE events_fts MATCH ?))
+                AND (? OR instr((
+                    SELECT group_concat(value, ' ') FROM
+                    (SELECT value FROM json_each(e.args) ORDER BY key)
+                ), ?) > 0)
+                AND (? OR e.dir = ?)
+                AND (? OR e.time >= ?)
+                AND (? OR instr(e.envs, ?) > 0)
+            )
+            WHERE (NOT ? OR (code IS NOT NULL AND code != '0'))
+            ORDER BY time DESC LIMIT ?
+            "#,
+            EXEC_DONE_CODE,
+            EXEC_CODE,
+            ids,
+            no_fts,
+            fts,
+            no_pattern,
+            pattern,
+            no_dir,
+            dir,
+            no_since,
+            since,
+            no_env,
+            env,
+            query.failed,
+            limit
+        )
+        .fetch_all(&*self.pool.read().unwrap())
+        .await?;
+
+        Ok(res
+            .into_iter()
+            .map(|res| SearchRecord {
+                event_id: res.id,
+                script_id: res.script_id,
+                time: res.time,
+                dir: res.dir.unwrap_or_default(),
+                args: res.args.unwrap_or_default(),
+                envs: res.envs.unwrap_or_default(),
+                code: parse_code(res.code),
+            })
+            .collect())
+    }
+    async fn log(&self, query: &LogQuery<'_>) -> Result<Vec<LogRecord>, DBError> {
+        let ids = join_id_str(query.ids);
+        let no_since = query.since.is_none();
+        let since = query.since.unwrap_or_default();
+        let no_until = query.until.is_none();
+        let until = query.until.unwrap_or_default();
+        let after_id = query.after_id.unwrap_or(ZERO);
+        let limit = query.limit.map_or(-1, |l| l as i64);
+        let offset = query.offset as i64;
+        log::info!("查詢事件時間軸 {:?}", query);
+
+        // FIXME: 一旦可以綁定陣列就換掉這個醜死人的 instr
+        let res = sqlx::query!(
+            "
+            SELECT id, script_id, type as ty, time, args, dir, content, repeat_count, humble
+            FROM events
+            WHERE NOT ignored
+            AND instr(?, '[' || script_id || ']') > 0
+            AND (? OR time >= ?)
+            AND (? OR time < ?)
+            AND id > ?
+            ORDER BY time DESC, id DESC LIMIT ? OFFSET ?
+            ",
+            ids,
+            no_since,
+            since,
+            no_until,
+            until,
+            after_id,
+            limit,
+            offset
+        )
+        .fetch_all(&*self.pool.read().unwrap())
+        .await?;
+
+        Ok(res
+            .into_iter()
+            .filter_map(|res| {
+                let Some(ty) = EventType::from_code(res.ty as i8) else {
+                    log::warn!("未知的事件類型 {}", res.ty);
+                    return None;
+                };
+                let code = if ty == EventType::ExecDone {
+                    parse_code(res.content.clone())
+                } else {
+                    None
+                };
+                Some(LogRecord {
+                    event_id: res.id,
+                    script_id: res.script_id,
+                    ty,
+                    time: res.time,
+                    args: res.args,
+                    dir: res.dir,
+                    code,
+                    content: res.content,
+                    repeat_count: res.repeat_count,
+                    humble: res.humble,
+                })
+            })
+            .collect())
+    }
+    async fn query(&self, query: &EventQuery) -> Result<Vec<(RawEvent, Option<i32>)>, DBError> {
+        let no_ids = query.script_ids.is_none();
+        let ids = join_id_str(query.script_ids.as_deref().unwrap_or_default());
+        let no_types = query.types.is_none();
+        let types: Vec<_> = query
+            .types
+            .iter()
+            .flatten()
+            .map(|ty| ty.get_code() as i64)
+            .collect();
+        let types = join_id_str(&types);
+        let no_since = query.since.is_none();
+        let since = query.since.unwrap_or_default();
+        let no_until = query.until.is_none();
+        let until = query.until.unwrap_or_default();
+        let no_dir = query.dir.is_none();
+        let dir = query.dir_str();
+        let dir = dir.as_deref().unwrap_or(EMPTY_STR);
+        let no_humble = query.humble.is_none();
+        let humble = query.humble.unwrap_or_default();
+        let no_ignored = query.ignored.is_none();
+        let ignored = query.ignored.unwrap_or_default();
+        let failed = query.status == StatusFilter::Failed;
+        let succeeded = query.status == StatusFilter::Succeeded;
+        let no_code = query.code.is_none();
+        let code = query.code.map(|c| c.to_string()).unwrap_or_default();
+        let limit = query.limit.map_or(-1, |l| l as i64);
+        let offset = query.offset as i64;
+        log::info!("查詢事件 {:?}", query);
+
+        // FIXME: 一旦可以綁定陣列就換掉這個醜死人的 instr
+        let res = sqlx::query!(
+            r#"
+            SELECT * FROM (
+                SELECT e.*,
+                    CASE WHEN e.type = ? THEN e.content ELSE
+                        (SELECT d.content FROM events d
+                        WHERE d.type = ? AND d.main_event_id = e.id
+                        ORDER BY d.time DESC LIMIT 1)
+                    END as code
+                FROM events e
+                WHERE (? OR instr(?, '[' || e.script_id || ']') > 0)
+                AND (? OR instr(?, '[' || e.type || ']') > 0)
+                AND (? OR e.time >= ?)
+                AND (? OR e.time < ?)
+                AND (? OR e.dir = ?)
+                AND (? OR e.humble = ?)
+                AND (? OR e.ignored = ?)
+            )
+            WHERE (NOT ? OR (code IS NOT NULL AND code != '0'))
+            AND (NOT ? OR code = '0')
+            AND (? OR code = ?)
+            ORDER BY time DESC, id DESC LIMIT ? OFFSET ?
+            "#,
+            EXEC_DONE_CODE,
+            EXEC_DONE_CODE,
+            no_ids,
+            ids,
+            no_types,
+            types,
+            no_since,
+            since,
+            no_until,
+            until,
+            no_dir,
+            dir,
+            no_humble,
+            humble,
+            no_ignored,
+            ignored,
+            failed,
+            succeeded,
+            no_code,
+            code,
+            limit,
+            offset
+        )
+        .fetch_all(&*self.pool.read().unwrap())
+        .await?;
+
+        Ok(res
+            .into_iter()
+            .filter_map(|res| {
+                let Some(ty) = EventType::from_code(res.r#type as i8) else {
+                    log::warn!("未知的事件類型 {}", res.r#type);
+                    return None;
+                };
+                let event = RawEvent {
+                    id: res.id,
+                    script_id: res.script_id,
+                    ty,
+                    cmd: res.cmd,
+                    time: res.time,
+                    args: res.args,
+                    content: res.content,
+                    dir: res.dir,
+                    envs: res.envs,
+                    env_files: res.env_files,
+                    profile: res.profile,
+                    main_event_id: res.main_event_id.unwrap_or(ZERO),
+                    parent_id: res.parent_id,
+                    humble: res.humble,
+                    ignored: res.ignored,
+                    repeat_count: res.repeat_count,
+                };
+                Some((event, parse_code(res.code)))
+            })
+            .collect())
+    }
+    async fn last_time(&self, script_id: i64) -> Result<LastTimeRecord, DBError> {
+        let res = sqlx::query_as_unchecked!(
+            LastTimeRecord,
+            "
+            SELECT
+                ? as script_id,
+                (SELECT time FROM events
+                WHERE script_id = ? AND NOT ignored AND humble
+                ORDER BY time DESC LIMIT 1) as humble_time,
+                (SELECT time FROM events
+                WHERE script_id = ? AND NOT ignored AND NOT humble AND type = ?
+                ORDER BY time DESC LIMIT 1) as exec_time,
+                (SELECT time FROM events
+                WHERE script_id = ? AND NOT ignored AND NOT humble AND type = ?
+                ORDER BY time DESC LIMIT 1) as exec_done_time
+            ",
+            script_id,
+            script_id,
+            script_id,
+            EXEC_CODE,
+            script_id,
+            EXEC_DONE_CODE
+        )
+        .fetch_one(&*self.pool.read().unwrap())
+        .await?;
+
+        Ok(LastTimeRecord {
+            script_id,
+            exec_time: res.exec_time,
+            exec_done_time: res.exec_done_time,
+            humble_time: res.humble_time,
+        })
+    }
+
+    async fn begin_mutation(
+        &self,
+        time: NaiveDateTime,
+        kind: &str,
+        cmd: &str,
+    ) -> Result<(), DBError> {
+        let pool = self.pool.read().unwrap();
+        // NOTE: 之前的程序可能中途出錯而沒關上日誌
+        sqlx::query!("UPDATE mutations SET open = false WHERE open")
+            .execute(&*pool)
+            .await?;
+        sqlx::query!(
+            "INSERT INTO mutations (time, kind, cmd) VALUES(?, ?, ?)",
+            time,
+            kind,
+            cmd
+        )
+        .execute(&*pool)
+        .await?;
+        Ok(())
+    }
+    async fn end_mutation(&self) -> Result<(), DBError> {
+        let pool = self.pool.read().unwrap();
+        sqlx::query!("UPDATE mutations SET open = false WHERE open")
+            .execute(&*pool)
+            .await?;
+        log::debug!("清理沒有修改任何事件及過舊的日誌");
+        sqlx::query!(
+            "
+            DELETE FROM mutations WHERE id NOT IN (SELECT mutation_id FROM mutation_events)
+            OR id NOT IN (SELECT id FROM mutations ORDER BY id DESC LIMIT ?);
+            DELETE FROM mutation_events WHERE mutation_id NOT IN (SELECT id FROM mutations);
+            ",
+            MAX_MUTATIONS
+        )
+        .execute(&*pool)
+        .await?;
+        Ok(())
+    }
+    async fn mutations(&self, limit: u32) -> Result<Vec<MutationRecord>, DBError> {
+        let res = sqlx::query_as!(
+            MutationRecord,
+            r#"
+            SELECT m.id as "id!", m.time, m.kind, m.cmd,
+                (SELECT COUNT(*) FROM mutation_events e WHERE e.mutation_id = m.id) as "event_count!: i64"
+            FROM mutations m
+            ORDER BY m.id DESC LIMIT ?
+            "#,
+            limit
+        )
+        .fetch_all(&*self.pool.read().unwrap())
+        .await?;
+        Ok(res)
+    }
+    async fn undo_mutation(&self, mutation_id: i64) -> Result<Vec<i64>, DBError> {
+        let mut script_ids = vec![];
+        let pool = self.pool.read().unwrap();
+        // NOTE: 復原到一半失敗的話，事件與紀錄都要維持原狀，才能再復原一次
+        let mut tx = pool.begin().await?;
+        let events = sqlx::query!(
+            "
+            SELECT event_id, ignored, humble, args, envs FROM mutation_events
+            WHERE mutation_id = ? ORDER BY id DESC
+            ",
+            mutation_id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        for e in events.into_iter() {
+            let res = sqlx::query!(
+                "
+                UPDATE events SET ignored = ?, humble = ?, args = ?, envs = ? WHERE id = ?
+                RETURNING script_id
+                ",
+                e.ignored,
+                e.humble,
+                e.args,
+                e.envs,
+                e.event_id
+            )
+            .fetch_optional(&mut *tx)
+            .await?;
+            match res {
+                Some(res) if !script_ids.contains(&res.script_id) => script_ids.push(res.script_id),
+                Some(_) => (),
+                None => log::warn!("事件 {} 已不存在，無法復原", e.event_id),
+            }
+        }
+        sqlx::query!(
+            "
+            DELETE FROM mutation_events WHERE mutation_id = ?;
+            DELETE FROM mutations WHERE id = ?
+            ",
+            mutation_id,
+            mutation_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(script_ids)
+    }
+
+    async fn ignore_or_humble_by_id(
+        &self,
+        is_humble: bool,
+        event_id: NonZeroU64,
+    ) -> Result<(i64, i64), DBError> {
+        let pool = self.pool.read().unwrap();
+        let event_id = event_id.get() as i64;
+        let latest_record = sqlx::query!(
+            "
+            SELECT id, script_id FROM events
+            WHERE (type = ? OR type = ?) AND script_id = (SELECT script_id FROM events WHERE id = ?)
+            ORDER BY time DESC LIMIT 1
+            ",
+            EXEC_CODE,
+            PRE_EXEC_CODE,
+            event_id,
+        )
+        .fetch_one(&*pool)
+        .await?;
+        // TODO: check if this event is exec?
+
+        if is_humble {
+            ignore_or_humble_arg!("humble", pool, "id = ?", event_id);
+        } else {
+            ignore_or_humble_arg!("ignored", pool, "id = ?", event_id);
+        }
+        Ok((latest_record.id, latest_record.script_id))
+    }
+    async fn ignore_args(&self, query: &ArgsQuery<'_>) -> Result<(), DBError> {
+        let ids_str = join_id_str(query.ids);
+        let no_dir = query.dir.is_none();
+        let dir = query.dir.map(|p| p.to_string_lossy());
+        let dir = dir.as_deref().unwrap_or(EMPTY_STR);
+        let (no_humble, limit, offset) = (query.no_humble, query.limit, query.offset);
+
+        let pool = self.pool.read().unwrap();
+        macro_rules! ignore_arg {
+            ($($target:literal)*) => {{
+                // NOTE: 我們知道 script_id || args 串接起來必然是唯一的（因為 args 的格式為 [...]）
+                // FIXME: 一旦可以綁定陣列就換掉這個醜死人的 instr
+                ignore_or_humble_arg!(
+                    "ignored",
+                    pool,
+                    "
+                    (? OR dir == ?) AND
+                    (NOT ? OR NOT humble) AND
+                    (script_id " $(+ "||" + $target)* + ") IN (
+                        WITH records AS (
+                            SELECT max(time) as time, script_id " $(+ "," + $target)* +" FROM events
+                            WHERE instr(?, '[' || script_id || ']') > 0
+                            AND type = ? AND NOT ignored
+                            AND (? OR dir == ?)
+                            AND (NOT ? OR NOT humble)
+                            GROUP BY script_id " $( + "," + $target)* + " ORDER BY time DESC LIMIT ? OFFSET ?
+                        ) SELECT script_id " $(+ "||" + $target)* + " as t FROM records
+                    )
+                    ",
+                    no_dir,
+                    dir,
+                    no_humble,
+                    ids_str,
+                    EXEC_CODE,
+                    no_dir,
+                    dir,
+                    no_humble,
+                    limit,
+                    offset
+                );
+            }};
+        }
+
+        match query.key {
+            ArgsKey::Both => ignore_arg!("args" "envs"),
+            ArgsKey::Envs => ignore_arg!("envs"),
+            ArgsKey::Args => ignore_arg!("args"),
+        }
+        Ok(())
+    }
+    async fn amend(
+        &self,
+        event_id: NonZeroU64,
+        args: &str,
+        envs: Option<&str>,
+    ) -> Result<(), DBError> {
+        let event_id = event_id.get() as i64;
+
+        macro_rules! amend {
+            ($($set:literal, $var:expr),*) => {{
+                sqlx::query!(
+                    "UPDATE events SET ignored = false, args = ?"
+                    + $( "," + $set + "=? " +)*
+                    "WHERE (type = ? OR type = ?) AND id = ? ",
+                    args,
+                    $($var,)*
+                    EXEC_CODE,
+                    PRE_EXEC_CODE,
+                    event_id,
+                )
+                .execute(&*self.pool.read().unwrap())
+                .await?
+            }}
+        }
+        if let Some(envs) = envs {
+            amend!("envs", envs);
+        } else {
+            amend!();
+        }
+        Ok(())
+    }
+
+    async fn export(
+        &self,
+        script_ids: &[i64],
+        since: Option<NaiveDateTime>,
+    ) -> Result<Vec<RawEvent>, DBError> {
+        let ids = join_id_json(script_ids);
+        let no_since = since.is_none();
+        let since = since.unwrap_or_default();
+        let res = sqlx::query!(
+            "
+            SELECT * FROM events
+            WHERE script_id IN (SELECT value FROM json_each(?))
+            AND (? OR time >= ? OR id IN (
+                SELECT main_event_id FROM events WHERE time >= ? AND main_event_id != 0
+            ))
+            ORDER BY id
+            ",
+            ids,
+            no_since,
+            since,
+            since
+        )
+        .fetch_all(&*self.pool.read().unwrap())
+        .await?;
+
+        Ok(res
+            .into_iter()
+            .filter_map(|res| {
+                let Some(ty) = EventType::from_code(res.r#type as i8) else {
+                    log::warn!("未知的事件類型 {}", res.r#type);
+                    return None;
+                };
+                Some(RawEvent {
+                    id: res.id,
+                    script_id: res.script_id,
+                    ty,
+                    cmd: res.cmd,
+                    time: res.time,
+                    args: res.args,
+                    content: res.content,
+                    dir: res.dir,
+                    envs: res.envs,
+                    env_files: res.env_files,
+                    profile: res.profile,
+                    main_event_id: res.main_event_id.unwrap_or(ZERO),
+                    parent_id: res.parent_id,
+                    humble: res.humble,
+                    ignored: res.ignored,
+                    repeat_count: res.repeat_count,
+                })
+            })
+            .collect())
+    }
+    async fn find_duplicate(&self, event: &RawEvent) -> Result<Option<i64>, DBError> {
+        let ty = event.ty.get_code();
+        let res = sqlx::query!(
+            "
+            SELECT id FROM events
+            WHERE script_id = ? AND type = ? AND time = ? AND args IS ? AND content IS ?
+            ",
+            event.script_id,
+            ty,
+            event.time,
+            event.args,
+            event.content
+        )
+        .fetch_optional(&*self.pool.read().unwrap())
+        .await?;
+        Ok(res.map(|res| res.id))
+    }
+    async fn insert_raw(&self, event: &RawEvent, main_event_id: i64) -> Result<i64, DBError> {
+        let ty = event.ty.get_code();
+        let res = sqlx::query!(
+            "
+            INSERT INTO events
+            (script_id, type, cmd, args, content, time, main_event_id, dir, envs, env_files,
+            profile, humble, ignored, repeat_count, parent_id)
+            VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING id
+            ",
+            event.script_id,
+            ty,
+            event.cmd,
+            event.args,
+            event.content,
+            event.time,
+            main_event_id,
+            event.dir,
+            event.envs,
+            event.env_files,
+            event.profile,
+            event.humble,
+            event.ignored,
+            event.repeat_count,
+            event.parent_id
+        )
+        .fetch_one(&*self.pool.read().unwrap())
+        .await?;
+        Ok(res.id)
+    }
+
+    async fn tidy(
+        &self,
+        script_ids: &[i64],
+        policy: &RetentionPolicy,
+        dry_run: bool,
+    ) -> Result<TidyReport, DBError> {
+        let pool = self.pool.read().unwrap();
+        let ids = join_id_json(script_ids);
+        let mut to_delete: Vec<i64> = vec![];
+
+        let orphans = sqlx::query!(
+            "
+            SELECT id FROM events
+            WHERE script_id > 0 AND script_id NOT IN (SELECT value FROM json_each(?))
+            ",
+            ids
+        )
+        .fetch_all(&*pool)
+        .await?;
+        to_delete.extend(orphans.into_iter().map(|r| r.id));
+
+        macro_rules! drop_before {
+            ($time:expr, $flag:literal) => {
+                if let Some(time) = $time {
+                    let res = sqlx::query!(
+                        "SELECT id FROM events WHERE " + $flag + " AND time < ? AND type < ?",
+                        time,
+                        FIRST_STRUCTURAL_CODE
+                    )
+                    .fetch_all(&*pool)
+                    .await?;
+                    to_delete.extend(res.into_iter().map(|r| r.id));
+                }
+            };
+        }
+        drop_before!(policy.humble_before, "humble");
+        drop_before!(policy.ignored_before, "ignored");
+
+        let execs = sqlx::query!(
+            "
+            SELECT id, script_id, args, dir, envs, env_files, profile, repeat_count FROM events
+            WHERE type = ? AND NOT ignored AND script_id IN (SELECT value FROM json_each(?))
+            ORDER BY script_id, time, id
+            ",
+            EXEC_CODE,
+            ids
+        )
+        .fetch_all(&*pool)
+        .await?;
+        let mut report = TidyReport::default();
+        let mut counts: Vec<(i64, i64)> = vec![];
+        if policy.dedup_only() {
+            let execs: Vec<_> = execs
+                .into_iter()
+                .map(|exec| ExecRow {
+                    id: exec.id,
+                    script_id: exec.script_id,
+                    key: (exec.args, exec.dir),
+                    repeat_count: exec.repeat_count,
+                })
+                .collect();
+            let kept = join_id_json(&latest_execs(&execs));
+            let res = sqlx::query!(
+                "
+                SELECT id FROM events
+                WHERE type < ? AND script_id IN (SELECT value FROM json_each(?))
+                AND id NOT IN (SELECT value FROM json_each(?))
+                ",
+                FIRST_STRUCTURAL_CODE,
+                ids,
+                kept
+            )
+            .fetch_all(&*pool)
+            .await?;
+            to_delete.extend(res.into_iter().map(|r| r.id));
+        } else {
+            let execs: Vec<_> = execs
+                .into_iter()
+                .map(|exec| ExecRow {
+                    id: exec.id,
+                    script_id: exec.script_id,
+                    key: (exec.args, exec.dir, exec.envs, exec.env_files, exec.profile),
+                    repeat_count: exec.repeat_count,
+                })
+                .collect();
+            let dropped_execs;
+            (dropped_execs, counts) = plan_exec_retention(&execs, policy, &mut report);
+            let dropped_execs = join_id_json(&dropped_execs);
+            let done_events = sqlx::query!(
+                "
+                SELECT id FROM events
+                WHERE (type = ? AND main_event_id IN (SELECT value FROM json_each(?)))
+                OR id IN (SELECT value FROM json_each(?))
+                ",
+                EXEC_DONE_CODE,
+                dropped_execs,
+                dropped_execs
+            )
+            .fetch_all(&*pool)
+            .await?;
+            to_delete.extend(done_events.into_iter().map(|r| r.id));
+        }
+        to_delete.sort_unstable();
+        to_delete.dedup();
+
+        let to_delete = join_id_json(&to_delete);
+        let stat = sqlx::query!(
+            r#"
+            SELECT COUNT(*) as "rows!: i64", SUM(
+                length(CAST(cmd AS BLOB)) + IFNULL(length(CAST(args AS BLOB)), 0)
+                + IFNULL(length(CAST(content AS BLOB)), 0) + IFNULL(length(CAST(dir AS BLOB)), 0)
+                + IFNULL(length(CAST(envs AS BLOB)), 0) + IFNULL(length(CAST(profile AS BLOB)), 0)
+                + IFNULL(length(CAST(env_files AS BLOB)), 0)
+                + 48
+            ) as "bytes: i64"
+            FROM events WHERE id IN (SELECT value FROM json_each(?))
+            "#,
+            to_delete
+        )
+        .fetch_one(&*pool)
+        .await?;
+        report.rows = stat.rows as u64;
+        report.bytes = stat.bytes.unwrap_or_default() as u64;
+        if dry_run {
+            return Ok(report);
+        }
+
+        let file = db::get_file(&self.dir_path);
+        let get_size = || std::fs::metadata(&file).map(|m| m.len()).ok();
+        let size_before = get_size();
+        sqlx::query!(
+            "DELETE FROM events WHERE id IN (SELECT value FROM json_each(?))",
+            to_delete
+        )
+        .execute(&*pool)
+        .await?;
+        for (id, count) in counts.into_iter() {
+            sqlx::query!("UPDATE events SET repeat_count = ? WHERE id = ?", count, id)
+                .execute(&*pool)
+                .await?;
+        }
+        sqlx::query!("VACUUM").execute(&*pool).await?;
+        report.file_size = size_before.zip(get_size());
+
+        Ok(report)
+    }
+}
+
+fn join_id_json(ids: &[i64]) -> String {
+    let ids: Vec<_> = ids.iter().map(|id| id.to_string()).collect();
+    format!("[{}]", ids.join(","))
+}
+
+fn join_id_str(ids: &[i64]) -> String {
+    use std::fmt::Write;
+    let mut ret = String::new();
+    for id in ids {
+        write!(ret, "[{}]", id).unwrap();
+    }
+    ret
+}
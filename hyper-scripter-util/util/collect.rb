@@ -4,6 +4,7 @@
 # [HS_HELP]: USAGE:
 # [HS_HELP]:     hs collect
 
+require 'shellwords'
 require_relative './common'
 
 HOME = HS_ENV.home
@@ -40,7 +41,7 @@ def extract_name(file)
   ty = File.extname(file)
   name = file.delete_suffix(ty)
   ty = ty.delete_prefix('.') # the first char is `.`
-  ty = 'txt' if ty == ''
+  ty = nil if ty == ''
 
   if name.start_with? '.anonymous'
     name = name.sub(%r{^\.anonymous/}, '')
@@ -83,7 +84,18 @@ directory_tree(HOME).each do |full_path|
 
   # TODO: handle the case where type name != ext name
   begin
-    if types.include?(ty)
+    if ty.nil?
+      ty = HS_ENV.detect_type(full_path)
+      warn "collect #{name} with type #{ty}"
+      if ty == 'txt'
+        HS_ENV.do_hs("edit =#{name} -T txt --fast", false)
+      else
+        # the type may have an extension, so the file has to be moved
+        content = Shellwords.escape(File.read(full_path))
+        File.delete(full_path)
+        HS_ENV.do_hs("edit =#{name} -T #{ty} --no-template --fast -- #{content}", false)
+      end
+    elsif types.include?(ty)
       HS_ENV.do_hs("edit =#{name} -T #{ty} --fast", false)
     else
       name = "#{name}.#{ty}"
@@ -87,6 +87,14 @@ class HSEnv
     exec cmd.to_s
   end
 
+  # guess the script type by shebang, fallback to `txt`
+  def detect_type(path)
+    require 'shellwords'
+    do_hs("types detect #{Shellwords.escape(path)} 2>/dev/null", false).strip
+  rescue StandardError
+    'txt'
+  end
+
   def env_var(var_name)
     k = ENV_MAP[var_name]
     v = ENV[k]
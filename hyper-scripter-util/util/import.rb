@@ -55,6 +55,7 @@ def import_dir(dir, namespace)
 
   puts "import directory #{dir}"
   out = other_env.do_hs('ls --plain', true)
+  types = HS_ENV.do_hs('types --no-sub', false).split
   parse(out).each do |script|
     new_name = if namespace.nil? || script.name.start_with?('.')
                  script.name
@@ -75,9 +76,16 @@ def import_dir(dir, namespace)
         next
       end
 
+      ty = script.ty
+      unless types.include?(ty)
+        path = other_env.do_hs("which =#{script.name}", true).strip
+        ty = HS_ENV.detect_type(path)
+        puts "unknown type #{script.ty}, import #{script.name} with type #{ty}"
+      end
+
       content = Shellwords.escape(content)
       tags_str = script.tags.join(',')
-      HS_ENV.do_hs("edit =#{new_name} -t #{tags_str} -T #{script.ty} --no-template --fast -- #{content}", false)
+      HS_ENV.do_hs("edit =#{new_name} -t #{tags_str} -T #{ty} --no-template --fast -- #{content}", false)
     end
   end
 
@@ -5,6 +5,7 @@ use criterion::{criterion_group, criterion_main, Bencher, Criterion};
 use hyper_scripter::{
     fuzzy::*, my_env_logger, script::ScriptName, util::main_util::prepare_pre_run,
 };
+use hyper_scripter_historian::StorageKind;
 use rand::{rngs::StdRng, seq::index::sample, Rng, SeedableRng};
 use std::hint::black_box;
 
@@ -132,6 +133,8 @@ struct MyBencher<'a, 'b> {
     with_alias: bool,
     check_res: bool,
     script_content: Option<String>,
+    /// 不需要讀歷史的評測只把歷史存在記憶體中，省下資料庫的開銷
+    memory_history: bool,
 }
 struct MyBencherWithSetup<'a, 'b, S> {
     b: MyBencher<'a, 'b>,
@@ -147,8 +150,12 @@ impl<'a, 'b> MyBencher<'a, 'b> {
             with_alias,
             check_res: false,
             script_content: None,
+            memory_history: true,
         }
     }
+    fn keep_history(&mut self) {
+        self.memory_history = false;
+    }
     fn check_res(&mut self) {
         self.check_res = true;
     }
@@ -202,12 +209,21 @@ impl<'a, 'b, S: FnMut(&mut StdRng, &str) -> String> MyBencherWithSetup<'a, 'b, S
             })
             .collect();
 
-        let (script_count, check_res, script_content) =
-            (b.script_count, b.check_res, b.script_content);
+        let (script_count, check_res, script_content, memory_history) = (
+            b.script_count,
+            b.check_res,
+            b.script_content,
+            b.memory_history,
+        );
         let script_content = script_content.as_deref().unwrap_or("echo $NAME");
         b.b.iter_with_setup(
             || {
                 let _ = setup();
+                if memory_history {
+                    let mut conf = load_conf();
+                    conf.history_backend = StorageKind::Memory;
+                    conf.store().unwrap();
+                }
                 prepare_pre_run(Some("#!/usr/bin/bash")).unwrap();
                 for (name, tag_arr) in data.data.iter() {
                     let tag_str = gen_tag_string(tag_arr);
@@ -277,6 +293,7 @@ fn run_criterion(c: &mut Criterion, name: &str, script_count: usize, epoch: usiz
         c.bench_function(name, |b| {
             let mut b = MyBencher::new(b, script_count, epoch, with_alias);
             b.check_res();
+            b.keep_history();
             let b = b.with_setup(script_count * 10, |rng, name| {
                 let i = rng.gen_range(0..5);
                 format!("--no-alias --dummy ={}! {}", name, i)
@@ -288,6 +305,7 @@ fn run_criterion(c: &mut Criterion, name: &str, script_count: usize, epoch: usiz
             let mut b = MyBencher::new(b, script_count, epoch, with_alias);
             b.script_content("$HS_EXE -H $HS_HOME history rm-id $HS_RUN_ID");
             b.check_res();
+            b.keep_history();
             b.run(|_, name, _| format!("={}!", name));
         });
     } else {
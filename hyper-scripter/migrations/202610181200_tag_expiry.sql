@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS tag_expiry (
+    script_id integer NOT NULL,
+    tag text NOT NULL,
+    expire_time datetime NOT NULL,
+    PRIMARY KEY (script_id, tag)
+);
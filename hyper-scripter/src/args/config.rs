@@ -0,0 +1,15 @@
+use clap::Parser;
+use serde::Serialize;
+use supplement::Supplement;
+
+#[derive(Parser, Debug, Serialize, Supplement)]
+pub enum ConfigCmd {
+    #[command(about = "Print a config value, e.g. `hs config get alias.la`")]
+    Get { key: String },
+    #[command(about = "Set a config value, e.g. `hs config set recent 7`")]
+    Set { key: String, value: String },
+    #[command(about = "Remove a config value, e.g. `hs config unset alias.la`")]
+    Unset { key: String },
+    #[command(about = "Check the config for errors that the file format can't catch")]
+    Validate,
+}
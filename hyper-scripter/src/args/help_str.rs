@@ -1,4 +1,5 @@
 pub const TYPE_HELP: &str = "Type of the script, e.g. `sh`";
+pub const TEMPLATE_HELP: &str = "Template of a type, e.g. `sh`, or a named one, e.g. `sh:deploy`";
 pub const TAGS_HELP: &str = "Tags of the script"; // TODO: advanced topic?
 pub const EDIT_CONCRETE_QUERY_HELP: &str = "New script. `?` for new anonymous."; // TODO: dir
 pub const EDIT_QUERY_HELP: &str = "Target script.
@@ -10,4 +11,4 @@ Otherwise, do fuzzy search.";
 pub const LIST_QUERY_HELP: &str = "Target script.
 `-` or `^{N}` for previous script, and `={NAME}` for exact name matching.
 Otherwise, do fuzzy search.
-Wildcard such as name/* is also allowed.";
+Wildcard such as name/* is also allowed, and so is `@@{PRESET}` for a saved query preset.";
@@ -1,24 +1,31 @@
-use crate::config::{Alias, Config, Iter as AliasArgIter, PromptLevel, Recent};
+use crate::config::{Alias, Config, PromptLevel, Recent};
 use crate::env_pair::EnvPair;
-use crate::error::Result;
+use crate::error::{DisplayError, DisplayResult, Error, FormatCode::Time as TimeCode, Result};
 use crate::list::Grouping;
 use crate::path;
 use crate::query::{EditQuery, ListQuery, RangeQuery, ScriptOrDirQuery, ScriptQuery};
 use crate::script_type::{ScriptFullType, ScriptType};
 use crate::tag::TagSelector;
-use crate::to_display_args;
+use crate::util::impl_ser_by_to_string;
 use crate::Either;
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use clap::{CommandFactory, Error as ClapError, Parser, ValueEnum};
 use serde::Serialize;
+use std::borrow::Cow;
 use std::num::NonZeroUsize;
 use std::path::PathBuf;
+use std::str::FromStr;
 use supplement::Supplement;
 
 mod tags;
 pub use tags::*;
+mod config;
 mod help_str;
+mod query;
 mod types;
+pub use config::*;
 use help_str::*;
+pub use query::*;
 pub use types::*;
 
 #[derive(Parser, Debug, Serialize, Supplement)]
@@ -91,38 +98,27 @@ pub struct Root {
 pub struct ArgsIter<'a, T> {
     base_args: &'a [T],
     add_run: bool,
-    after_args: Option<AliasArgIter<'a>>,
-    remaining_args: &'a [String],
+    /// 別名展開後的參數，沒有別名時直接借用原本的參數
+    rest: Cow<'a, [String]>,
 }
-impl<'a, T> Clone for ArgsIter<'a, T> {
-    fn clone(&self) -> Self {
-        ArgsIter {
-            base_args: self.base_args,
-            after_args: self.after_args,
-            remaining_args: self.remaining_args,
-            add_run: self.add_run,
-        }
-    }
-}
-impl<'a, T> Copy for ArgsIter<'a, T> {}
 impl<'a, T: 'a + AsRef<str>> ArgsIter<'a, T> {
-    fn non_base_iter(&self) -> impl Iterator<Item = &'a str> {
+    fn non_base_iter(&self) -> impl Iterator<Item = &str> {
         let it1 = if self.add_run { Some("run") } else { None }.into_iter();
-        let it2 = self.after_args.into_iter().flatten();
-        let it3 = self.remaining_args.iter().map(|s| s.as_str());
-        it1.chain(it2).chain(it3)
+        let it2 = self.rest.iter().map(|s| s.as_str());
+        it1.chain(it2)
     }
-    fn non_base_iter_with_trailing(&self) -> impl Iterator<Item = &'a str> {
+    fn non_base_iter_with_trailing(&self) -> impl Iterator<Item = &str> {
         let enabled = self.non_base_iter().next() == Some("run");
         let it = self.non_base_iter();
         TrailingIter {
             it,
             enabled,
             do_escape_next: false,
+            is_opt_value: false,
             first: true,
         }
     }
-    pub fn iter(&self) -> impl Iterator<Item = &'a str> {
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
         let it1 = self.base_args.iter().map(AsRef::as_ref);
         it1.chain(self.non_base_iter_with_trailing())
     }
@@ -134,10 +130,37 @@ impl<'a, T: 'a + AsRef<str>> std::fmt::Debug for ArgsIter<'a, T> {
     }
 }
 
+/// `run` 子命令中需要另外帶值的選項，如 `--profile prod`
+fn run_value_opts() -> &'static [String] {
+    static OPTS: std::sync::OnceLock<Vec<String>> = std::sync::OnceLock::new();
+    OPTS.get_or_init(|| {
+        let cmd = Root::command();
+        let run = cmd.find_subcommand("run").unwrap();
+        let mut ret = vec![];
+        for arg in run.get_arguments() {
+            if arg.is_positional()
+                || !arg.get_action().takes_values()
+                || arg.is_require_equals_set()
+            {
+                continue;
+            }
+            if let Some(long) = arg.get_long() {
+                ret.push(format!("--{}", long));
+            }
+            if let Some(short) = arg.get_short() {
+                ret.push(format!("-{}", short));
+            }
+        }
+        ret
+    })
+}
+
 struct TrailingIter<I> {
     it: I,
     enabled: bool,
     do_escape_next: bool,
+    /// 下一個參數是選項的值，而非腳本名
+    is_opt_value: bool,
     first: bool,
 }
 impl<'a, I: Iterator<Item = &'a str>> Iterator for TrailingIter<I> {
@@ -155,8 +178,12 @@ impl<'a, I: Iterator<Item = &'a str>> Iterator for TrailingIter<I> {
 
         let next = self.it.next();
         if let Some(next) = next {
-            if next == "--" {
+            if self.is_opt_value {
+                self.is_opt_value = false;
+            } else if next == "--" {
                 self.enabled = false;
+            } else if run_value_opts().iter().any(|opt| opt == next) {
+                self.is_opt_value = true;
             } else if next == "-" || !next.starts_with("-") {
                 // TODO: This breaks with `hs run -s hide -p`... Because "hide" will cause an escape
                 self.do_escape_next = true;
@@ -175,6 +202,33 @@ pub struct AliasRoot {
     #[arg(allow_hyphen_values = true)]
     pub subcmd: Vec<String>,
 }
+/// 別名展開的其中一步
+#[derive(Debug)]
+pub struct AliasStep {
+    pub name: String,
+    pub is_shell: bool,
+    /// 展開後的參數（不含開頭的 root 參數）
+    pub args: Vec<String>,
+}
+
+/// 找出與該名稱（或其別稱）相同的子命令
+pub fn find_subcmd(name: &str) -> Option<&'static str> {
+    static SUBCMDS: std::sync::OnceLock<Vec<(String, Vec<String>)>> = std::sync::OnceLock::new();
+    let subcmds = SUBCMDS.get_or_init(|| {
+        Root::command()
+            .get_subcommands()
+            .map(|cmd| {
+                let aliases = cmd.get_all_aliases().map(str::to_owned).collect();
+                (cmd.get_name().to_owned(), aliases)
+            })
+            .collect()
+    });
+    subcmds
+        .iter()
+        .find(|(subcmd, aliases)| subcmd == name || aliases.iter().any(|a| a == name))
+        .map(|(subcmd, _)| subcmd.as_str())
+}
+
 impl AliasRoot {
     fn find_alias<'a>(&'a self, conf: &'a Config) -> (Option<&'a Alias>, &'a [String]) {
         if self.root_args.no_alias {
@@ -192,29 +246,66 @@ impl AliasRoot {
             }
         }
     }
+    /// 反覆展開別名，直到第一個參數不再是別名為止。
+    /// 展開成自己的別名（如 `ls = ["ls", "-l"]`）不會再展開，其它的循環則報錯
+    pub fn expand_steps<'a>(&'a self, conf: &'a Config) -> Result<(Vec<AliasStep>, &'a [String])> {
+        let (alias, remaining_args) = self.find_alias(conf);
+        let mut steps: Vec<AliasStep> = vec![];
+        let Some(mut alias) = alias else {
+            return Ok((steps, remaining_args));
+        };
+        let mut name = self.subcmd[0].as_str();
+        let mut args = remaining_args;
+        loop {
+            let (is_shell, expanded) = alias.expand(name, args)?;
+            let next = expanded.first().filter(|_| !is_shell).cloned();
+            steps.push(AliasStep {
+                name: name.to_owned(),
+                is_shell,
+                args: expanded,
+            });
+
+            let Some(next) = next else {
+                break;
+            };
+            let Some((next_name, next_alias)) = conf.alias.get_key_value(&next) else {
+                break;
+            };
+            if next_name == name {
+                log::info!("別名 {} 展開成自己，不再展開", name);
+                break;
+            }
+            if steps.iter().any(|s| &s.name == next_name) {
+                let mut names: Vec<_> = steps.into_iter().map(|s| s.name).collect();
+                names.push(next);
+                log::error!("別名循環：{:?}", names);
+                return Err(Error::AliasLoop(names));
+            }
+            log::info!("別名 {} => {:?}", next_name, next_alias);
+            name = next_name;
+            alias = next_alias;
+            args = &steps.last().unwrap().args[1..];
+        }
+        Ok((steps, remaining_args))
+    }
     pub fn expand_alias<'a, T: 'a + AsRef<str>>(
         &'a self,
         args: &'a [T],
         conf: &'a Config,
-    ) -> Either<ArgsIter<'a, T>, Vec<String>> {
-        let (alias, remaining_args) = self.find_alias(conf);
-        let (after_args, alias_len) = if let Some(alias) = alias {
-            let (is_shell, after_args) = alias.args();
-
-            if is_shell {
+    ) -> Result<Either<ArgsIter<'a, T>, Vec<String>>> {
+        let (mut steps, remaining_args) = self.expand_steps(conf)?;
+        let alias_len = if steps.is_empty() { 0 } else { 1 };
+        let rest = match steps.pop() {
+            None => Cow::Borrowed(remaining_args),
+            Some(AliasStep {
+                is_shell: true,
+                args,
+                ..
+            }) => {
                 // shell 別名，完全無視開頭的參數（例如 `hs -s tag -H path/to/home`）
-                let remaining_args = remaining_args
-                    .iter()
-                    .map(|s| to_display_args(s).to_string());
-                let ret: Vec<_> = after_args
-                    .map(ToOwned::to_owned)
-                    .chain(remaining_args)
-                    .collect();
-                return Either::Two(ret);
-            }
-            (Some(after_args), 1)
-        } else {
-            (None, 0)
+                return Ok(Either::Two(args));
+            }
+            Some(step) => Cow::Owned(step.args),
         };
 
         let mut add_run = false;
@@ -226,13 +317,12 @@ impl AliasRoot {
         let base_args = &args[..base_len];
         let new_args = ArgsIter {
             base_args,
-            after_args,
-            remaining_args,
+            rest,
             add_run,
         };
 
         // log::trace!("新的參數為 {:?}", new_args);
-        Either::One(new_args)
+        Ok(Either::One(new_args))
     }
 }
 
@@ -259,6 +349,12 @@ pub enum Subs {
         tags: Option<TagSelector>,
         #[arg(long, short, help = "Create script without invoking the editor")]
         fast: bool,
+        #[arg(
+            long = "var",
+            conflicts_with = "no_template",
+            help = "Value of a variable declared by the template, e.g. `--var port=8080`"
+        )]
+        vars: Vec<EnvPair>,
         #[arg(default_value = "?", help = EDIT_QUERY_HELP)]
         edit_query: Vec<EditQuery<ListQuery>>,
         #[arg(last = true)]
@@ -274,13 +370,37 @@ pub enum Subs {
             help = "Unset an alias."
         )]
         unset: bool,
+        #[arg(
+            long,
+            short,
+            requires = "before",
+            conflicts_with = "unset",
+            help = "Description shown when listing aliases"
+        )]
+        description: Option<String>,
+        #[arg(
+            long,
+            value_name = "LINE",
+            allow_hyphen_values = true,
+            conflicts_with_all = ["unset", "description", "before"],
+            help = "Print each step of expanding the given command line, e.g. `hs alias --explain 'p -a'`"
+        )]
+        explain: Option<String>,
         before: Option<String>,
         #[arg(allow_hyphen_values = true)]
         after: Vec<String>,
     },
 
-    #[command(about = "Print the path to script")]
-    Config,
+    #[command(about = "Print the path to config file")]
+    Config {
+        #[arg(
+            long,
+            help = "Show the effective config values and which file each of them comes from"
+        )]
+        explain: bool,
+        #[command(subcommand)]
+        subcmd: Option<ConfigCmd>,
+    },
     #[command(about = "Run the script", disable_help_flag = true)]
     Run {
         #[arg(
@@ -294,6 +414,12 @@ pub enum Subs {
         no_caution: bool,
         #[arg(long, help = "Add a dummy run history instead of actually running it")]
         dummy: bool,
+        #[arg(
+            long,
+            visible_alias = "explain",
+            help = "Print the commands, working directory and env changes instead of running"
+        )]
+        dry_run: bool,
         #[arg(long, short)]
         repeat: Option<u64>,
         #[clap(
@@ -304,9 +430,9 @@ pub enum Subs {
             default_missing_value = "all",
             require_equals = true,
             value_enum,
-            help = "Use arguments from last run")
+            help = "Use arguments from last run, or from the last successful run with `last-success`")
         ]
-        previous: Option<HistoryDisplay>,
+        previous: Option<PreviousMode>,
         #[arg(
             long,
             short = 'E',
@@ -316,6 +442,13 @@ pub enum Subs {
         error_no_previous: bool,
         #[arg(long, short, requires = "previous", help = "")]
         dir: Option<PathBuf>,
+        #[arg(long, help = "Run with the named env profile from config")]
+        profile: Option<String>,
+        #[arg(
+            long = "env-file",
+            help = "Load environment variables from a dotenv file"
+        )]
+        env_files: Vec<PathBuf>,
         #[arg(default_value = "-", help = SCRIPT_QUERY_HELP)]
         script_query: ScriptQuery,
         #[arg(
@@ -345,6 +478,21 @@ pub enum Subs {
             help = "Actually remove scripts, rather than hiding them with tag."
         )]
         purge: bool,
+        #[arg(
+            long,
+            requires = "purge",
+            help = "Keep the history of purged scripts under a tombstone id"
+        )]
+        keep_history: bool,
+    },
+    #[command(about = "Tag idle scripts according to the archive policy in config")]
+    Archive {
+        #[arg(long, help = "Only show the changes, don't apply them")]
+        dry_run: bool,
+        #[arg(long, short, help = "Apply the changes without prompting")]
+        yes: bool,
+        #[arg(long, help = "Override the idle days in archive policy")]
+        days: Option<u32>,
     },
     #[command(about = "Set recent filter")]
     Recent { recent_filter: Option<Recent> },
@@ -352,6 +500,11 @@ pub enum Subs {
     LS(List),
     #[command(about = "Manage script types")]
     Types(Types),
+    #[command(about = "Manage templates")]
+    Template {
+        #[command(subcommand)]
+        subcmd: TemplateCmd,
+    },
     #[command(about = "Copy the script to another one")]
     CP {
         #[arg(long, short, help = TAGS_HELP)]
@@ -377,6 +530,11 @@ pub enum Subs {
         #[command(subcommand)]
         subcmd: Option<Tags>,
     },
+    #[command(about = "Manage named query presets")]
+    Query {
+        #[command(subcommand)]
+        subcmd: Option<QueryPresets>,
+    },
     #[command(about = "Manage script history")]
     History {
         #[command(subcommand)]
@@ -414,6 +572,74 @@ impl HistoryDisplay {
     }
 }
 
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, ValueEnum)]
+pub enum PreviousMode {
+    Env,
+    Args,
+    All,
+    LastSuccess,
+}
+impl PreviousMode {
+    pub fn show_args(&self) -> bool {
+        !matches!(self, Self::Env)
+    }
+    pub fn show_env(&self) -> bool {
+        !matches!(self, Self::Args)
+    }
+    pub fn only_success(&self) -> bool {
+        matches!(self, Self::LastSuccess)
+    }
+}
+
+/// 時間界限，可為相對於現在的時間（如 `30d`、`12h`）或本地日期（如 `2024-01-31`）
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TimeBound {
+    s: String,
+    time: NaiveDateTime,
+}
+impl_ser_by_to_string!(TimeBound);
+impl TimeBound {
+    /// UTC 時間
+    pub fn time(&self) -> NaiveDateTime {
+        self.time
+    }
+}
+impl std::fmt::Display for TimeBound {
+    fn fmt(&self, w: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(w, "{}", self.s)
+    }
+}
+impl FromStr for TimeBound {
+    type Err = DisplayError;
+    fn from_str(s: &str) -> DisplayResult<Self> {
+        let relative = s.len() > 1 && s.is_char_boundary(s.len() - 1);
+        let relative = relative.then(|| s.split_at(s.len() - 1));
+        let time = if let Some((Ok(amount), unit)) = relative.map(|(a, u)| (a.parse(), u)) {
+            let amount: i64 = amount;
+            let duration = match unit {
+                "m" => Duration::minutes(amount),
+                "h" => Duration::hours(amount),
+                "d" => Duration::days(amount),
+                "w" => Duration::weeks(amount),
+                _ => return TimeCode.to_display_res(s.to_owned()),
+            };
+            Utc::now().naive_utc() - duration
+        } else if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+            let time = date.and_hms_opt(0, 0, 0).unwrap();
+            match Local.from_local_datetime(&time).earliest() {
+                Some(time) => time.naive_utc(),
+                None => return TimeCode.to_display_res(s.to_owned()),
+            }
+        } else {
+            return TimeCode.to_display_res(s.to_owned());
+        };
+        Ok(TimeBound {
+            s: s.to_owned(),
+            time,
+        })
+    }
+}
+
 #[derive(Parser, Debug, Serialize, Supplement)]
 pub enum History {
     RM {
@@ -433,13 +659,9 @@ pub enum History {
         name = "rm-id",
         about = "Remove an event by it's id.\nUseful if you want to keep those illegal arguments from polluting the history."
     )]
-    RMID {
-        event_id: u64,
-    },
+    RMID { event_id: u64 },
     #[command(about = "Humble an event by it's id")]
-    Humble {
-        event_id: u64,
-    },
+    Humble { event_id: u64 },
     Show {
         #[arg(default_value = "-", help = LIST_QUERY_HELP)]
         queries: Vec<ListQuery>,
@@ -455,6 +677,16 @@ pub enum History {
         dir: Option<PathBuf>,
         #[arg(long, default_value = "args", value_enum)]
         display: HistoryDisplay,
+        #[arg(long, help = "Show the exit status of each run")]
+        with_status: bool,
+        #[arg(long, help = "Only show runs that exited with non-zero code")]
+        failed: bool,
+        #[arg(
+            long,
+            conflicts_with = "failed",
+            help = "Only show runs that exited with zero code"
+        )]
+        succeeded: bool,
     },
     Neglect {
         #[arg(required = true,  help = LIST_QUERY_HELP)]
@@ -473,7 +705,97 @@ pub enum History {
         )]
         args: Vec<String>,
     },
-    Tidy,
+    #[command(
+        about = "Revert the last changes made by `history rm`, `rm-id`, `humble` or `amend`"
+    )]
+    Undo {
+        #[arg(default_value = "1", help = "How many changes to revert")]
+        count: u32,
+        #[arg(long, help = "List the changes that can be reverted instead")]
+        list: bool,
+    },
+    #[command(about = "Remove history according to the retention rules in config")]
+    Tidy {
+        #[arg(long, help = "Only report how much would be removed")]
+        dry_run: bool,
+    },
+    #[command(about = "Export the history as JSON Lines, with script names instead of ids")]
+    Export {
+        #[arg(
+            long,
+            help = "Only export events after this time, e.g. 30d or 2024-01-31"
+        )]
+        since: Option<TimeBound>,
+        #[arg(help = LIST_QUERY_HELP)]
+        queries: Vec<ListQuery>,
+    },
+    #[command(about = "Import the history exported by `history export`")]
+    Import {
+        #[arg(help = "The exported file, or - for standard input")]
+        file: PathBuf,
+        #[arg(long, help = "Skip events that already exist")]
+        dedupe: bool,
+    },
+    #[command(about = "Search the run history of all scripts")]
+    Search {
+        #[arg(help = "Text to find in the arguments")]
+        pattern: Option<String>,
+        #[arg(short, long)]
+        dir: Option<PathBuf>,
+        #[arg(
+            long,
+            help = "Only show runs after this time, e.g. 30d, 12h or 2024-01-31"
+        )]
+        since: Option<TimeBound>,
+        #[arg(long, help = "Only show runs that exited with non-zero code")]
+        failed: bool,
+        #[arg(
+            long,
+            help = "Only show runs with this env variable, e.g. KEY or KEY=VAL"
+        )]
+        env: Option<String>,
+        #[arg(short, long, default_value = "20")]
+        limit: u32,
+    },
+    #[command(about = "Show events of all scripts in chronological order")]
+    Log {
+        #[arg(short, long, default_value = "50")]
+        limit: u32,
+        #[arg(short, long, default_value = "0")]
+        offset: u32,
+        #[arg(
+            long,
+            help = "Only show events after this time, e.g. 30d, 12h or 2024-01-31"
+        )]
+        since: Option<TimeBound>,
+        #[arg(
+            long,
+            help = "Only show events before this time, e.g. 1d or 2024-01-31"
+        )]
+        until: Option<TimeBound>,
+        #[arg(long, help = "Group events by day")]
+        by_day: bool,
+        #[arg(short, long, help = "Keep printing new events as they are recorded")]
+        follow: bool,
+        #[arg(long, help = "Also show events of scripts purged with --keep-history")]
+        purged: bool,
+    },
+    #[command(
+        name = "to-script",
+        about = "Create a new sh script that replays the runs in a time window"
+    )]
+    ToScript {
+        #[arg(long, help = "Only take runs after this time, e.g. 2h or 2024-01-31")]
+        since: TimeBound,
+        #[arg(long, help = "Only take runs before this time, e.g. 1h or 2024-01-31")]
+        until: Option<TimeBound>,
+        #[arg(short, long, help = "Only take runs in this directory")]
+        dir: Option<PathBuf>,
+        #[arg(long, short, help = TAGS_HELP)]
+        tags: Option<TagSelector>,
+        #[arg(help = "Name of the new script")]
+        name: String,
+    },
 }
 
 #[derive(Parser, Debug, Serialize, Default, Supplement)]
@@ -566,12 +888,41 @@ where
     Root::try_parse_from(iter.iter())
 }
 
+/// 列出一行參數的別名展開過程，以及最後 `--dump-args` 會印出的結果
+pub fn explain_alias(line: &str) -> Result<(Vec<AliasStep>, String)> {
+    let mut args = vec!["hs".to_owned()];
+    args.extend(
+        shlex::split(line).ok_or_else(|| Error::msg(format!("Invalid command: {}", line)))?,
+    );
+    let alias_root = match AliasRoot::try_parse_from(&args) {
+        Ok(alias_root) => alias_root,
+        Err(err) => return Ok((vec![], err.render().to_string())),
+    };
+    let conf = Config::get();
+    let (steps, _) = alias_root.expand_steps(conf)?;
+    let dumped = match alias_root.expand_alias(&args, conf)? {
+        Either::One(new_args) => {
+            let res = handle_maybe_run(new_args).and_then(|mut root| {
+                root.is_from_alias = !steps.is_empty();
+                root.sanitize()?;
+                Ok(root)
+            });
+            match res {
+                Ok(root) => serde_json::to_string(&root)?,
+                Err(err) => err.render().to_string(),
+            }
+        }
+        Either::Two(new_args) => new_args.join(" "),
+    };
+    Ok((steps, dumped))
+}
+
 fn handle_alias_args(args: Vec<String>) -> Result<ArgsResult> {
     match AliasRoot::try_parse_from(&args) {
         Ok(alias_root) => {
             log::info!("別名命令行物件 {:?}", alias_root);
             set_home(&alias_root.root_args.hs_home, true, true)?;
-            let mut root = match alias_root.expand_alias(&args, Config::get()) {
+            let mut root = match alias_root.expand_alias(&args, Config::get())? {
                 Either::One(new_args) => map_clap_res!(handle_maybe_run(new_args)),
                 Either::Two(new_args) => {
                     return Ok(ArgsResult::Shell {
@@ -635,6 +986,7 @@ impl Root {
                     content: vec![],
                     tags: None,
                     fast: false,
+                    vars: vec![],
                     no_template: false,
                 });
             }
@@ -698,6 +1050,7 @@ mod test {
                 unset,
                 after,
                 before: Some(before),
+                ..
             }) => {
                 assert_eq!(*unset, false);
                 assert_eq!(before, "trash");
@@ -809,15 +1162,19 @@ mod test {
         match args.subcmd {
             Some(Subs::Run {
                 dummy: true,
+                dry_run: false,
                 previous: None,
                 error_no_previous: false,
                 repeat: Some(42),
                 dir: None,
                 no_caution: false,
                 caution: false,
+                profile: None,
+                env_files,
                 script_query,
                 args,
             }) => {
+                assert!(env_files.is_empty());
                 assert_eq!(script_query, "=script".parse().unwrap());
                 assert_eq!(args, vec!["-a", "--"]);
             }
@@ -0,0 +1,19 @@
+use clap::Parser;
+use serde::Serialize;
+use supplement::Supplement;
+
+#[derive(Parser, Debug, Serialize, Supplement)]
+pub enum QueryPresets {
+    #[command(about = "Save a query preset, which can then be used as `@@NAME` in list queries")]
+    Save {
+        name: String,
+        #[arg(
+            last = true,
+            required = true,
+            help = "Root args (e.g. `-s tag` or `--timeless`) followed by list queries"
+        )]
+        args: Vec<String>,
+    },
+    #[command(about = "Remove a query preset")]
+    RM { name: String },
+}
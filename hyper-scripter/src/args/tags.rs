@@ -1,4 +1,6 @@
-use crate::tag::TagSelector;
+use super::help_str::*;
+use crate::query::ListQuery;
+use crate::tag::{Tag, TagSelector};
 use clap::{Error as ClapError, Parser};
 use serde::Serialize;
 use supplement::Supplement;
@@ -19,6 +21,35 @@ pub enum Tags {
     Toggle {
         names: Vec<String>,
     },
+    #[command(about = "Add a tag to scripts")]
+    Add {
+        #[arg(long, help = "Only show the changes, don't apply them")]
+        dry_run: bool,
+        #[arg(long, short, help = "Apply the changes without prompting")]
+        yes: bool,
+        tag: Tag,
+        #[arg(required = true, help = LIST_QUERY_HELP)]
+        queries: Vec<ListQuery>,
+    },
+    #[command(about = "Remove a tag from scripts")]
+    Remove {
+        #[arg(long, help = "Only show the changes, don't apply them")]
+        dry_run: bool,
+        #[arg(long, short, help = "Apply the changes without prompting")]
+        yes: bool,
+        tag: Tag,
+        #[arg(required = true, help = LIST_QUERY_HELP)]
+        queries: Vec<ListQuery>,
+    },
+    #[command(about = "Rename a tag (and its descendants) in all scripts and config")]
+    Rename {
+        #[arg(long, help = "Only show the changes, don't apply them")]
+        dry_run: bool,
+        #[arg(long, short, help = "Apply the changes without prompting")]
+        yes: bool,
+        old: Tag,
+        new: Tag,
+    },
 }
 
 impl Tags {
@@ -1,10 +1,12 @@
 use super::help_str::*;
-use crate::script_type::ScriptFullType;
+use crate::script_type::{ScriptFullType, ScriptType};
 use clap::Parser;
 use serde::Serialize;
+use std::path::PathBuf;
 use supplement::Supplement;
 
 #[derive(Parser, Debug, Serialize, Supplement)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct Types {
     #[arg(long, conflicts_with_all = &["ty", "edit"])]
     pub no_sub: bool,
@@ -12,4 +14,75 @@ pub struct Types {
     pub edit: bool,
     #[arg(help = TYPE_HELP)]
     pub ty: Option<ScriptFullType>,
+    #[command(subcommand)]
+    pub subcmd: Option<TypesCmd>,
+}
+
+#[derive(Parser, Debug, Serialize, Supplement)]
+pub enum TypesCmd {
+    #[command(about = "Add a new script type")]
+    Add {
+        name: ScriptType,
+        #[arg(long, help = "File extension of the scripts, e.g. `py`")]
+        ext: Option<String>,
+        #[arg(
+            long,
+            help = "Command to run the script, e.g. `python3 {{path}}`. Scripts are run by shebang if neither this nor `--from` is given"
+        )]
+        cmd: Option<String>,
+        #[arg(long, help = "Color shown in `hs ls`")]
+        color: Option<String>,
+        #[arg(long, help = "Inherit the settings and template of an existing type")]
+        from: Option<ScriptType>,
+    },
+    #[command(about = "Remove a script type")]
+    RM {
+        name: ScriptType,
+        #[arg(
+            long,
+            help = "Change the type of the scripts still using it instead of refusing"
+        )]
+        migrate_to: Option<ScriptType>,
+    },
+    #[command(about = "Detect the type of a file by its shebang line")]
+    Detect { file: PathBuf },
+}
+
+#[derive(Parser, Debug, Serialize, Supplement)]
+pub enum TemplateCmd {
+    #[command(about = "List all templates and whether they differ from the built-in ones")]
+    List,
+    #[command(about = "Print a template")]
+    Show {
+        #[arg(help = TEMPLATE_HELP)]
+        ty: ScriptFullType,
+    },
+    #[command(about = "Edit a template, creating it if it's a new named template")]
+    Edit {
+        #[arg(help = TEMPLATE_HELP)]
+        ty: ScriptFullType,
+    },
+    #[command(about = "Show how a template differs from the built-in one. Show all if none given")]
+    DiffDefault {
+        #[arg(help = TEMPLATE_HELP)]
+        ty: Option<ScriptFullType>,
+    },
+    #[command(about = "Restore a template to the built-in one")]
+    Reset {
+        #[arg(help = TEMPLATE_HELP)]
+        ty: ScriptFullType,
+    },
+    #[command(
+        about = "Import templates from a `.hbs` file or a template directory of another home"
+    )]
+    Import {
+        #[arg(long, short, help = "Overwrite existing templates")]
+        force: bool,
+        #[arg(
+            long = "as",
+            help = "Template to import the file as. Defaults to the file name, e.g. `sh:deploy.hbs`"
+        )]
+        as_ty: Option<ScriptFullType>,
+        path: PathBuf,
+    },
 }
@@ -1,5 +1,8 @@
 use clap::Parser;
-use hyper_scripter::args::{AliasRoot, History, List, Root, RootArgs, Subs, Tags, Types};
+use hyper_scripter::args::{
+    AliasRoot, ConfigCmd, History, List, QueryPresets, Root, RootArgs, Subs, Tags, TemplateCmd,
+    Types, TypesCmd,
+};
 use hyper_scripter::config::Config;
 use hyper_scripter::error::Error;
 use hyper_scripter::error::Result;
@@ -77,8 +80,8 @@ pub async fn handle_completion(args: Vec<String>, repo: &mut Option<ScriptRepo>)
         Ok(root) => {
             let home = path::compute_home_path_optional(root.root_args.hs_home.as_ref(), false)?;
             // TODO: we can try to make this `load` reused further
-            let conf = Config::load(&home)?;
-            if let Either::One(new_args) = root.expand_alias(args, &conf) {
+            let conf = Config::load(&home)?.with_layers()?;
+            if let Ok(Either::One(new_args)) = root.expand_alias(args, &conf) {
                 let args_iter = new_args.iter().map(String::from);
                 return handle_completion_no_alias(shell, args_iter, repo).await;
             };
@@ -214,6 +217,34 @@ fn complete_alias() -> impl Iterator<Item = Completion> {
     })
 }
 
+fn complete_query_preset() -> impl Iterator<Item = Completion> {
+    Config::get().query_presets.iter().map(|(key, val)| {
+        let args = val.args.join(" ");
+        Completion::new(format!("@@{key}"), args).group("presets")
+    })
+}
+
+fn complete_config_key() -> Result<Vec<Completion>> {
+    fn walk(prefix: &str, value: &toml::Value, comps: &mut Vec<Completion>) {
+        let toml::Value::Table(table) = value else {
+            return;
+        };
+        for (key, value) in table.iter() {
+            let key = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+            walk(&key, value, comps);
+            comps.push(empty(key).group("config"));
+        }
+    }
+    let value = toml::Value::try_from(Config::get())?;
+    let mut comps = vec![];
+    walk("", &value, &mut comps);
+    Ok(comps)
+}
+
 fn prefix_plus(value: &str, mut comps: Vec<Completion>) -> Vec<Completion> {
     if !value.starts_with('+') && !value.is_empty() {
         return comps;
@@ -252,23 +283,53 @@ async fn handle_comp(
         | id!(subcmd Subs.LS List.limit)
         | id!(subcmd Subs.LS List.format)
         | id!(subcmd Subs.Run.repeat)
+        | id!(subcmd Subs.Archive.days)
         | id!(subcmd Subs.Cat.with)
         | id!(subcmd Subs.Alias.after)
+        | id!(subcmd Subs.Alias.description)
+        | id!(subcmd Subs.Alias.explain)
         | id!(subcmd Subs.History.subcmd History.Show.offset)
         | id!(subcmd Subs.History.subcmd History.Show.limit)
+        | id!(subcmd Subs.History.subcmd History.Search.pattern)
+        | id!(subcmd Subs.History.subcmd History.Search.since)
+        | id!(subcmd Subs.History.subcmd History.Search.env)
+        | id!(subcmd Subs.History.subcmd History.Search.limit)
+        | id!(subcmd Subs.History.subcmd History.Log.limit)
+        | id!(subcmd Subs.History.subcmd History.Log.offset)
+        | id!(subcmd Subs.History.subcmd History.Log.since)
+        | id!(subcmd Subs.History.subcmd History.Log.until)
+        | id!(subcmd Subs.History.subcmd History.Export.since)
+        | id!(subcmd Subs.History.subcmd History.ToScript.since)
+        | id!(subcmd Subs.History.subcmd History.ToScript.until)
+        | id!(subcmd Subs.History.subcmd History.ToScript.name)
+        | id!(subcmd Subs.History.subcmd History.Undo.count)
         | id!(subcmd Subs.History.subcmd History.RM.range)
         | id!(subcmd Subs.History.subcmd History.Amend.event_id)
         | id!(subcmd Subs.History.subcmd History.Amend.env)
         | id!(subcmd Subs.History.subcmd History.RMID.event_id)
-        | id!(subcmd Subs.History.subcmd History.Humble.event_id) => vec![],
+        | id!(subcmd Subs.History.subcmd History.Humble.event_id)
+        | id!(subcmd Subs.Tags.subcmd Tags.Rename.new)
+        | id!(subcmd Subs.Query.subcmd QueryPresets.Save.args)
+        | id!(subcmd Subs.Config.subcmd ConfigCmd.Set.value)
+        | id!(subcmd Subs.Edit.vars)
+        | id!(subcmd Subs.Types Types.subcmd TypesCmd.Add.name)
+        | id!(subcmd Subs.Types Types.subcmd TypesCmd.Add.ext)
+        | id!(subcmd Subs.Types Types.subcmd TypesCmd.Add.cmd)
+        | id!(subcmd Subs.Types Types.subcmd TypesCmd.Add.color) => vec![],
 
         id!(root_args RootArgs.hs_home)
         | id!(subcmd Subs.Edit.content)
         | id!(subcmd Subs.Run.dir)
+        | id!(subcmd Subs.Run.env_files)
         | id!(subcmd Subs.Run.args)
         | id!(subcmd Subs.History.subcmd History.Show.dir)
         | id!(subcmd Subs.History.subcmd History.RM.dir)
-        | id!(subcmd Subs.History.subcmd History.Amend.args) => std::process::exit(1),
+        | id!(subcmd Subs.History.subcmd History.Search.dir)
+        | id!(subcmd Subs.History.subcmd History.ToScript.dir)
+        | id!(subcmd Subs.History.subcmd History.Import.file)
+        | id!(subcmd Subs.History.subcmd History.Amend.args)
+        | id!(subcmd Subs.Types Types.subcmd TypesCmd.Detect.file)
+        | id!(subcmd Subs.Template.subcmd TemplateCmd.Import.path) => std::process::exit(1),
 
         id!(subcmd Subs.Recent.recent_filter) => {
             vec![empty("no-neglect"), empty("timeless")]
@@ -288,10 +349,35 @@ async fn handle_comp(
             }
             comps
         }
+        id!(subcmd Subs.Run.profile) => {
+            get_root(id, &history)?;
+            let mut profiles: Vec<_> = Config::get().env_profiles.iter().collect();
+            profiles.sort_by_key(|(name, _)| *name);
+            profiles
+                .into_iter()
+                .map(|(name, p)| {
+                    let desc = if p.dangerous { "dangerous" } else { "" };
+                    Completion::new(name, desc).group("profiles")
+                })
+                .collect()
+        }
         id!(subcmd Subs.Alias.before) => {
             get_root(id, &history)?;
             complete_alias().collect()
         }
+        id!(subcmd Subs.Config.subcmd ConfigCmd.Get.key)
+        | id!(subcmd Subs.Config.subcmd ConfigCmd.Set.key)
+        | id!(subcmd Subs.Config.subcmd ConfigCmd.Unset.key) => {
+            get_root(id, &history)?;
+            complete_config_key()?
+        }
+        id!(subcmd Subs.Query.subcmd QueryPresets.Save.name)
+        | id!(subcmd Subs.Query.subcmd QueryPresets.RM.name) => {
+            get_root(id, &history)?;
+            complete_query_preset()
+                .map(|c| c.value(|v| v[2..].to_owned()))
+                .collect()
+        }
         id!(root_args RootArgs.toggle)
         | id!(subcmd Subs.Tags.subcmd Tags.Toggle.names)
         | id!(subcmd Subs.Tags.subcmd Tags.Set.name)
@@ -324,7 +410,13 @@ async fn handle_comp(
                 .map(|ty| empty(format!("+{ty}")).group("tags"));
             tags.collect()
         }
-        id!(subcmd Subs.Edit.tags) | id!(subcmd Subs.MV.tags) | id!(subcmd Subs.CP.tags) => {
+        id!(subcmd Subs.Edit.tags)
+        | id!(subcmd Subs.MV.tags)
+        | id!(subcmd Subs.CP.tags)
+        | id!(subcmd Subs.History.subcmd History.ToScript.tags)
+        | id!(subcmd Subs.Tags.subcmd Tags.Add.tag)
+        | id!(subcmd Subs.Tags.subcmd Tags.Remove.tag)
+        | id!(subcmd Subs.Tags.subcmd Tags.Rename.old) => {
             let root = get_root(id, &history)?;
             *repo = Some(init_repo(root.root_args, false).await?);
             let tags = main_util::known_tags_iter(repo.as_mut().unwrap())
@@ -332,24 +424,45 @@ async fn handle_comp(
             let tags: Vec<_> = tags.collect();
             prefix_plus(value, tags)
         }
+        id!(subcmd Subs.Help.args)
+        | id!(subcmd Subs.MV.new)
+        | id!(subcmd Subs.CP.new)
+        | id!(subcmd Subs.Run.script_query) => complete_script(value, id, &history, repo).await?,
         id!(subcmd Subs.Cat.queries)
         | id!(subcmd Subs.Which.queries)
         | id!(subcmd Subs.Edit.edit_query)
-        | id!(subcmd Subs.Help.args)
         | id!(subcmd Subs.MV.origin)
         | id!(subcmd Subs.CP.origin)
-        | id!(subcmd Subs.MV.new)
-        | id!(subcmd Subs.CP.new)
         | id!(subcmd Subs.LS List.queries)
         | id!(subcmd Subs.RM.queries)
-        | id!(subcmd Subs.Run.script_query)
         | id!(subcmd Subs.History.subcmd History.Neglect.queries)
         | id!(subcmd Subs.History.subcmd History.Show.queries)
         | id!(subcmd Subs.History.subcmd History.RM.queries)
-        | id!(subcmd Subs.Top.queries) => complete_script(value, id, &history, repo).await?,
+        | id!(subcmd Subs.History.subcmd History.Export.queries)
+        | id!(subcmd Subs.Tags.subcmd Tags.Add.queries)
+        | id!(subcmd Subs.Tags.subcmd Tags.Remove.queries)
+        | id!(subcmd Subs.Top.queries) => {
+            if value.starts_with("@@") {
+                get_root(id, &history)?;
+                complete_query_preset().collect()
+            } else {
+                complete_script(value, id, &history, repo).await?
+            }
+        }
 
-        id!(subcmd Subs.MV.ty) => list_types(id, &history, false)?.collect(),
-        id!(subcmd Subs.Edit.ty) | id!(subcmd Subs.Types Types.ty) => {
+        id!(subcmd Subs.MV.ty)
+        | id!(subcmd Subs.Types Types.subcmd TypesCmd.Add.from)
+        | id!(subcmd Subs.Types Types.subcmd TypesCmd.RM.name)
+        | id!(subcmd Subs.Types Types.subcmd TypesCmd.RM.migrate_to) => {
+            list_types(id, &history, false)?.collect()
+        }
+        id!(subcmd Subs.Edit.ty)
+        | id!(subcmd Subs.Types Types.ty)
+        | id!(subcmd Subs.Template.subcmd TemplateCmd.Show.ty)
+        | id!(subcmd Subs.Template.subcmd TemplateCmd.Edit.ty)
+        | id!(subcmd Subs.Template.subcmd TemplateCmd.DiffDefault.ty)
+        | id!(subcmd Subs.Template.subcmd TemplateCmd.Reset.ty)
+        | id!(subcmd Subs.Template.subcmd TemplateCmd.Import.as_ty) => {
             list_types(id, &history, true)?.collect()
         }
     };
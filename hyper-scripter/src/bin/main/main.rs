@@ -1,15 +1,19 @@
 #![feature(more_qualified_paths)]
 
-use futures::future::try_join_all;
-use fxhash::FxHashSet as HashSet;
+use chrono::{Duration, Local, TimeZone, Utc};
+use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
 use hyper_scripter::args::{
-    self, ArgsResult, History, HistoryDisplay, List, Root, Subs, Tags, Types,
+    self, ArgsResult, ConfigCmd, History, HistoryDisplay, List, QueryPresets, Root, Subs, Tags,
+    TemplateCmd, Types, TypesCmd,
 };
-use hyper_scripter::config::{config_file, Config, NamedTagSelector};
+use hyper_scripter::color::{Color, Stylize};
+use hyper_scripter::config::{config_file, Alias, Config, NamedTagSelector};
 use hyper_scripter::db;
 use hyper_scripter::env_pair::EnvPair;
-use hyper_scripter::error::{Contextable, DisplayError, Error, ExitCode, RedundantOpt, Result};
-use hyper_scripter::extract_msg::extract_all_help_from_content;
+use hyper_scripter::error::{
+    Contextable, DisplayError, Error, ExitCode, FormatCode, RedundantOpt, Result,
+};
+use hyper_scripter::extract_msg::{extract_all_help_from_content, ENV_FILE_INDEX};
 use hyper_scripter::list::{fmt_list, DisplayStyle, ListOptions};
 use hyper_scripter::my_env_logger;
 use hyper_scripter::path;
@@ -17,15 +21,21 @@ use hyper_scripter::query::{self, EditQuery, ListQuery, ScriptOrDirQuery, Script
 use hyper_scripter::script::{IntoScriptName, ScriptInfo, ScriptName};
 use hyper_scripter::script_repo::{RepoEntry, ScriptRepo, Visibility};
 use hyper_scripter::script_time::ScriptTime;
-use hyper_scripter::tag::TagSelector;
+use hyper_scripter::script_type::ScriptFullType;
+use hyper_scripter::tag::{TagSelector, TagSet};
+use hyper_scripter::template_var;
 use hyper_scripter::to_display_args;
 use hyper_scripter::util::{
-    self,
+    self, config_edit,
     holder::{RepoHolder, Resource},
-    main_util::{self, EditTagArgs},
-    print_iter,
+    main_util::{self, EditTagArgs, TagChange},
+    print_iter, shebang_handle,
+    template_lib::{self, TemplateStatus},
+};
+use hyper_scripter_historian::{
+    ArgsRecord, EventData, EventQuery, EventType, Historian, LastTimeRecord, LogQuery, LogRecord,
+    MutationRecord, RetentionPolicy, SearchQuery, StatusFilter, StorageKind,
 };
-use hyper_scripter_historian::{Historian, LastTimeRecord};
 
 mod completion;
 
@@ -76,18 +86,49 @@ async fn main_err_handle(errs: &mut Vec<Error>) -> Result {
         print!("{}", dumped);
         return Ok(());
     }
-    if matches!(root.subcmd, Some(Subs::Config)) {
+    if matches!(
+        root.subcmd,
+        Some(Subs::Config {
+            explain: false,
+            subcmd: None
+        })
+    ) {
         root.set_home_unless_from_alias(true, false)?;
         let file = config_file(path::get_home());
         print!("{}", file.to_string_lossy());
         return Ok(());
     }
+    if let Some(Subs::Config {
+        subcmd: Some(cmd), ..
+    }) = &root.subcmd
+    {
+        // NOTE: 不載入設定檔，如此即使設定檔有錯也能修改
+        root.set_home_unless_from_alias(true, false)?;
+        match cmd {
+            ConfigCmd::Get { key } => println!("{}", config_edit::get(key)?),
+            ConfigCmd::Set { key, value } => config_edit::set(key, Some(value))?,
+            ConfigCmd::Unset { key } => config_edit::set(key, None)?,
+            ConfigCmd::Validate => {
+                let problems = config_edit::validate()?;
+                for problem in problems.iter() {
+                    println!("{}", problem);
+                }
+                if !problems.is_empty() {
+                    let file = config_file(path::get_home());
+                    return FormatCode::Config.to_res(file.to_string_lossy().into_owned());
+                }
+            }
+        }
+        return Ok(());
+    }
 
     root.set_home_unless_from_alias(true, true)?;
 
     if matches!(root.subcmd, Some(Subs::Migrate)) {
         db::do_migrate(db::get_file()).await?;
-        Historian::do_migrate(path::get_home()).await?;
+        if Config::get().history_backend == StorageKind::Sqlite {
+            Historian::do_migrate(path::get_home()).await?;
+        }
         return Ok(());
     }
 
@@ -100,9 +141,9 @@ async fn main_err_handle(errs: &mut Vec<Error>) -> Result {
     if let Some(conf) = ret.conf {
         log::info!("存入改變後的設定檔");
         conf.store()?;
-    } else if Config::get().is_from_dafault() {
+    } else if Config::get_home_conf().is_from_dafault() {
         log::info!("存入憑空產生的設定檔");
-        Config::get().store()?;
+        Config::get_home_conf().store()?;
     }
     Ok(())
 }
@@ -128,7 +169,7 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
 
     macro_rules! conf_mut {
         () => {{
-            ret.conf = Some(conf.clone());
+            ret.conf = Some(Config::get_home_conf().clone());
             ret.conf.as_mut().unwrap()
         }};
     }
@@ -138,25 +179,60 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
             let repo = repo.init().await?;
             main_util::load_utils(repo, None).await?;
         }
+        Subs::Alias {
+            explain: Some(line),
+            ..
+        } => {
+            log::info!("解釋別名展開 {}", line);
+            let (steps, dumped) = args::explain_alias(&line)?;
+            let repo = repo.init().await?;
+            for step in steps.iter() {
+                let after = if step.is_shell {
+                    format!("!{}", step.args.join(" "))
+                } else {
+                    let args: Vec<_> = step.args.iter().map(|s| to_display_args(s)).collect();
+                    args.join(" ")
+                };
+                println!("{}\t=> {}", step.name, after);
+                let shadowing = main_util::alias_shadowing(repo, &step.name, &step.args);
+                for warning in shadowing.into_iter() {
+                    println!("\t# {}", warning);
+                }
+            }
+            println!("{}", dumped);
+        }
         Subs::Alias {
             unset: false,
             before: Some(before),
             after,
+            description,
             ..
         } => {
             if !after.is_empty() {
                 log::info!("設定別名 {} {:?}", before, after);
+                let repo = repo.init().await?;
+                for warning in main_util::alias_shadowing(repo, &before, &after).into_iter() {
+                    log::warn!("別名遮蔽問題：{}", warning);
+                }
                 let conf = conf_mut!();
-                conf.alias.insert(before, after.into());
+                let mut alias: Alias = after.into();
+                alias.description = description;
+                conf.alias.insert(before, alias);
+            } else if let Some(description) = description {
+                log::info!("設定別名 {} 的說明", before);
+                let conf = conf_mut!();
+                let alias = conf
+                    .alias
+                    .get_mut(&before)
+                    .ok_or_else(|| Error::NoAlias(before.clone()))?;
+                alias.description = Some(description);
             } else {
                 log::info!("印出別名 {}", before);
-                let after = conf
+                let alias = conf
                     .alias
                     .get(&before)
-                    .ok_or_else(|| Error::NoAlias(before.clone()))?
-                    .after
-                    .join(" ");
-                println!("{}\t{}", before, after);
+                    .ok_or_else(|| Error::NoAlias(before.clone()))?;
+                print_alias(&before, alias);
             }
         }
         Subs::Alias {
@@ -177,9 +253,42 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
             ..
         } => {
             log::info!("印出所有別名");
-            for (before, alias) in conf.alias.iter() {
-                let after = alias.after.join(" ");
-                println!("{}\t{}", before, after);
+            let mut aliases: Vec<_> = conf.alias.iter().collect();
+            aliases.sort_by_key(|(before, _)| *before);
+            for (before, alias) in aliases.into_iter() {
+                print_alias(before, alias);
+            }
+        }
+        Subs::Config { explain: true, .. } => {
+            for (key, value, source) in conf.explain().into_iter() {
+                let value = value.replace('\n', "\\n");
+                println!("{}\t{}\t{}", key, value, source);
+            }
+        }
+        Subs::Query {
+            subcmd: Some(QueryPresets::Save { name, args }),
+        } => {
+            let preset = args.into();
+            query::parse_preset(&name, &preset)?;
+            log::info!("設定查詢預設 {} {:?}", name, preset.args);
+            let conf = conf_mut!();
+            conf.query_presets.insert(name, preset);
+        }
+        Subs::Query {
+            subcmd: Some(QueryPresets::RM { name }),
+        } => {
+            log::info!("刪除查詢預設 {}", name);
+            let conf = conf_mut!();
+            if conf.query_presets.remove(&name).is_none() {
+                return Err(Error::NoQueryPreset(name));
+            }
+        }
+        Subs::Query { subcmd: None } => {
+            log::info!("印出所有查詢預設");
+            let mut presets: Vec<_> = conf.query_presets.iter().collect();
+            presets.sort_by_key(|(name, _)| *name);
+            for (name, preset) in presets.into_iter() {
+                println!("@@{}\t{}", name, preset.args.join(" "));
             }
         }
         Subs::Edit {
@@ -188,6 +297,7 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
             fast,
             tags,
             content,
+            vars,
             no_template,
         } => {
             // TODO: 這裡邏輯太複雜了，抽出來測試吧
@@ -223,27 +333,25 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
                 prepare_vec.push((entry.id, p, prepare_resp));
             }
             if let Some(create_res) = create_res {
+                let template = if no_template {
+                    None
+                } else {
+                    // NOTE: 計算 `path` 時早已檢查過腳本類型，這裡直接不檢查了
+                    let template = util::get_or_create_template(&create_res.ty, true, true)?;
+                    Some(template_var::resolve_vars(&template, &vars)?)
+                };
                 for (name, path) in create_res.to_create.into_iter() {
                     log::info!("創造 {:?}", name);
-                    let template = if no_template {
-                        None
-                    } else {
-                        // NOTE: 計算 `path` 時早已檢查過腳本類型，這裡直接不檢查了
-                        Some(util::get_or_create_template(&create_res.ty, true, true)?)
-                    };
-                    let entry = repo
-                        .entry(&name)
-                        .or_insert(
-                            ScriptInfo::builder(
-                                0,
-                                0,
-                                name,
-                                create_res.ty.ty.clone(),
-                                create_res.tags.clone().into_iter(),
-                            )
-                            .build(),
-                        )
-                        .await?;
+                    let template = template.as_ref().map(|(t, vars)| (t.clone(), vars));
+                    let mut builder = ScriptInfo::builder(
+                        0,
+                        0,
+                        name.clone(),
+                        create_res.ty.ty.clone(),
+                        create_res.tags.clone().into_iter(),
+                    );
+                    builder.tag_expiry(create_res.tag_expiry.clone());
+                    let entry = repo.entry(&name).or_insert(builder.build()).await?;
                     let prepare_resp = util::prepare_script(&path, &*entry, template, &content)?;
                     prepare_vec.push((entry.id, path, prepare_resp));
                 }
@@ -288,7 +396,11 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
                 .filter_map(|(i, s)| if *i == 0 { Some(s) } else { None });
             let mut envs = all_helps
                 .iter()
-                .filter_map(|(i, s)| if *i != 0 { Some(s) } else { None })
+                .filter_map(|(i, s)| match *i {
+                    0 => None,
+                    ENV_FILE_INDEX => Some(format!("{} (env file)", s)),
+                    _ => Some(s.to_string()),
+                })
                 .peekable();
 
             let has_help = print_iter(helps, "\n");
@@ -304,11 +416,14 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
             caution,
             script_query,
             dummy,
+            dry_run,
             args,
             previous,
             error_no_previous,
             repeat,
             dir,
+            profile,
+            env_files,
         } => {
             let repo = repo.init().await?;
             let dir = util::option_map_res(dir, |d| path::normalize_path(d))?;
@@ -321,18 +436,18 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
                 _ => unreachable!(),
             };
 
-            main_util::run_n_times(
-                repeat.unwrap_or(1),
+            let opts = main_util::RunOptions {
+                repeat: repeat.unwrap_or(1),
                 dummy,
-                &mut entry,
-                args,
-                &mut ret.errs,
+                dry_run,
                 previous,
                 error_no_previous,
                 caution,
                 dir,
-            )
-            .await?;
+                profile,
+                env_files,
+            };
+            main_util::run_n_times(&mut entry, args, opts, &mut ret.errs).await?;
         }
         Subs::Which { queries } => {
             let repo = repo.init().await?;
@@ -368,10 +483,88 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
                 }
             }
         }
+        Subs::Template {
+            subcmd: TemplateCmd::List,
+        } => {
+            for (ty, status) in template_lib::list()?.into_iter() {
+                println!("{}\t{}", ty, status);
+            }
+        }
+        Subs::Template {
+            subcmd: TemplateCmd::Show { ty },
+        } => {
+            let template = util::get_or_create_template(&ty, false, true)?;
+            println!("{}", template);
+        }
+        Subs::Template {
+            subcmd: TemplateCmd::Edit { ty },
+        } => {
+            let (tmpl_path, _) = util::get_or_create_template_path(&ty, false, false)?;
+            util::open_editor([tmpl_path.as_ref()])?;
+        }
+        Subs::Template {
+            subcmd: TemplateCmd::DiffDefault { ty: Some(ty) },
+        } => {
+            print!("{}", template_lib::diff_default(&ty)?);
+        }
+        Subs::Template {
+            subcmd: TemplateCmd::DiffDefault { ty: None },
+        } => {
+            for (ty, status) in template_lib::list()?.into_iter() {
+                if status == TemplateStatus::Modified {
+                    print!("{}", template_lib::diff_default(&ty)?);
+                }
+            }
+        }
+        Subs::Template {
+            subcmd: TemplateCmd::Reset { ty },
+        } => {
+            template_lib::reset(&ty)?;
+        }
+        Subs::Template {
+            subcmd: TemplateCmd::Import { force, as_ty, path },
+        } => {
+            for ty in template_lib::import(&path, as_ty, force)?.into_iter() {
+                println!("{}", ty);
+            }
+        }
+        Subs::Types(Types {
+            subcmd:
+                Some(TypesCmd::Add {
+                    name,
+                    ext,
+                    cmd,
+                    color,
+                    from,
+                }),
+            ..
+        }) => {
+            let conf = conf_mut!();
+            main_util::add_type(conf, name, ext, cmd, color, from)?;
+        }
+        Subs::Types(Types {
+            subcmd: Some(TypesCmd::RM { name, migrate_to }),
+            ..
+        }) => {
+            let repo = repo.init().await?;
+            let conf = conf_mut!();
+            main_util::remove_type(repo, conf, &name, migrate_to).await?;
+        }
+        Subs::Types(Types {
+            subcmd: Some(TypesCmd::Detect { file }),
+            ..
+        }) => match shebang_handle::detect_type(&file)? {
+            Some(ty) => println!("{}", ty),
+            None => {
+                log::error!("無法從 shebang 判斷 {:?} 的類型", file);
+                return Err(Error::UnknownType(file.to_string_lossy().into_owned()));
+            }
+        },
         Subs::Types(Types {
             ty: None,
             no_sub,
             edit: _,
+            subcmd: None,
         }) => {
             let types = util::get_types(!no_sub)?;
             print_iter(types.iter(), " ");
@@ -380,6 +573,7 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
             ty: Some(ty),
             edit,
             no_sub: _,
+            subcmd: None,
         }) => {
             if edit {
                 let (tmpl_path, _) = util::get_or_create_template_path(&ty, false, false)?;
@@ -389,6 +583,18 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
                 println!("{}", template);
             }
         }
+        Subs::Archive { dry_run, yes, days } => {
+            let repo = repo.init().await?;
+            let mut policy = conf.archive.clone();
+            if let Some(days) = days {
+                policy.days = days;
+            }
+            let changes = main_util::archive_candidates(repo, &policy);
+            let ids: Vec<_> = changes.iter().map(|c| c.id).collect();
+            if main_util::apply_tag_changes(repo, changes, &[], dry_run, yes).await? {
+                main_util::neglect_archived(repo, &ids).await?;
+            }
+        }
         Subs::Recent { recent_filter } => {
             let conf = conf_mut!();
             if let Some(recent) = recent_filter {
@@ -420,10 +626,14 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
             let repo = repo.init().await?;
             fmt_list(&mut stdout.lock(), repo, opt, queries).await?;
         }
-        Subs::RM { queries, purge } => {
+        Subs::RM {
+            queries,
+            purge,
+            keep_history,
+        } => {
             let repo = repo.init().await?;
             let delete_tag: Option<TagSelector> = Some("+remove".parse().unwrap());
-            let mut to_purge = vec![]; // (Option<path>, id)
+            let mut to_purge = vec![]; // (Option<path>, id, name)
             for mut entry in query::do_list_query(repo, queries).await?.into_iter() {
                 log::info!("刪除 {:?}", *entry);
                 let try_open_res = path::open_script(&entry.name, &entry.ty, Some(true));
@@ -436,13 +646,13 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
                             None
                         }
                     };
-                    to_purge.push((p, entry.id));
+                    to_purge.push((p, entry.id, entry.name.key().into_owned()));
                 } else {
                     log::debug!("不要真的刪除腳本，改用標籤隱藏之：{:?}", entry.name);
                     match try_open_res {
                         Err(Error::PathNotFound(_)) => {
                             log::warn!("{:?} 實體不存在，消滅之", entry.name);
-                            to_purge.push((None, entry.id));
+                            to_purge.push((None, entry.id, entry.name.key().into_owned()));
                         }
                         Err(e) => {
                             log::warn!("試開腳本時出錯：{}", e); // e.g. unknown type
@@ -452,8 +662,12 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
                     main_util::mv(&mut entry, None, None, delete_tag.clone()).await?;
                 }
             }
-            for (p, id) in to_purge.into_iter() {
-                repo.remove(id).await?;
+            for (p, id, name) in to_purge.into_iter() {
+                if keep_history {
+                    repo.remove_keeping_history(id, &name).await?;
+                } else {
+                    repo.remove(id).await?;
+                }
                 if let Some(p) = p {
                     if let Err(e) = util::remove(&p) {
                         log::warn!("刪除腳本實體遭遇錯誤：{}", e);
@@ -475,7 +689,13 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
                 if let Some(tags) = &tags {
                     new_info.append_tags(tags.clone());
                 }
-                repo.entry(&new_info.name).or_insert(new_info).await?;
+                let og_id = entry.id;
+                let new_entry = repo.entry(&new_info.name).or_insert(new_info).await?;
+                let data = EventData::Copy { from: og_id };
+                new_entry
+                    .get_env()
+                    .record_structural(new_entry.id, data)
+                    .await?;
             }
         }
         Subs::MV {
@@ -531,9 +751,68 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
         }
         Subs::Tags { subcmd: None } => {
             let repo = repo.init().await?;
-            print!("known tags:\n  ");
-            print_iter(main_util::known_tags_iter(repo), " ");
-            println!("");
+            println!("known tags:");
+            let stats = main_util::known_tags_stat(repo);
+            let width = stats.iter().map(|(t, _)| t.as_ref().len()).max();
+            for (tag, stat) in stats.iter() {
+                let width = width.unwrap_or_default();
+                let mut s = format!("{:width$}", tag.as_ref()).stylize();
+                if let Some(color) = conf.get_tag_color(tag) {
+                    s.color(color);
+                }
+                print!("  {} {} scripts", s, stat.script_count);
+                if stat.hidden_count > 0 {
+                    print!(" ({} hidden)", stat.hidden_count);
+                }
+                match stat.last_exec_time {
+                    Some(time) => {
+                        let time = Local.from_utc_datetime(&time);
+                        println!(", last run {}", time.format("%Y-%m-%d %H:%M"));
+                    }
+                    None => println!(", never run"),
+                }
+            }
+
+            let orphans = main_util::orphan_config_tags(repo, conf);
+            if !orphans.is_empty() {
+                println!("tags on no script:");
+                for (tag, place) in orphans.iter() {
+                    println!("  {} ({})", tag.stylize().color(Color::Yellow), place);
+                }
+            }
+            let ns_only = main_util::namespace_only_scripts(repo);
+            if !ns_only.is_empty() {
+                println!("scripts tagged only by namespace:");
+                for script in ns_only.iter() {
+                    print!("  {} (", script.name);
+                    let mut tags: Vec<_> = script.tags.iter().collect();
+                    tags.sort();
+                    print_iter(tags.into_iter(), ",");
+                    println!(")");
+                }
+            }
+
+            if !conf.tag_registry.is_empty() {
+                println!("tag registry:");
+                let mut registry: Vec<_> = conf.tag_registry.iter().collect();
+                registry.sort_by_key(|(t, _)| *t);
+                for (tag, info) in registry.into_iter() {
+                    let mut s = tag.stylize();
+                    if let Some(color) = conf.get_tag_color(tag) {
+                        s.color(color).bold();
+                    }
+                    print!("  {}", s);
+                    if let Some(desc) = &info.description {
+                        print!(": {}", desc);
+                    }
+                    if !info.implies.is_empty() {
+                        print!(" (implies ");
+                        print_iter(info.implies.iter(), ",");
+                        print!(")");
+                    }
+                    println!();
+                }
+            }
 
             println!("caution tags:");
             println!("  {}", conf.caution_tags);
@@ -551,6 +830,85 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
             print!("  {}", conf.main_tag_selector);
             println!();
         }
+        Subs::Tags {
+            subcmd:
+                Some(Tags::Add {
+                    dry_run,
+                    yes,
+                    tag,
+                    queries,
+                }),
+        } => {
+            let repo = repo.init().await?;
+            let changes = query::do_list_query(repo, queries)
+                .await?
+                .into_iter()
+                .filter(|entry| !entry.tags.contains(&tag))
+                .map(|entry| {
+                    let mut after = entry.tags.clone();
+                    after.insert(tag.clone());
+                    TagChange::new(&entry, after)
+                })
+                .collect();
+            main_util::apply_tag_changes(repo, changes, &[], dry_run, yes).await?;
+        }
+        Subs::Tags {
+            subcmd:
+                Some(Tags::Remove {
+                    dry_run,
+                    yes,
+                    tag,
+                    queries,
+                }),
+        } => {
+            let repo = repo.init().await?;
+            let changes = query::do_list_query(repo, queries)
+                .await?
+                .into_iter()
+                .filter(|entry| entry.tags.contains(&tag))
+                .map(|entry| {
+                    let mut after = entry.tags.clone();
+                    after.remove(&tag);
+                    TagChange::new(&entry, after)
+                })
+                .collect();
+            main_util::apply_tag_changes(repo, changes, &[], dry_run, yes).await?;
+        }
+        Subs::Tags {
+            subcmd:
+                Some(Tags::Rename {
+                    dry_run,
+                    yes,
+                    old,
+                    new,
+                }),
+        } => {
+            let repo = repo.init().await?;
+            let changes = repo
+                .iter_mut(Visibility::All)
+                .filter_map(|entry| {
+                    let after: TagSet = entry
+                        .tags
+                        .iter()
+                        .map(|t| t.renamed(&old, &new).unwrap_or_else(|| t.clone()))
+                        .collect();
+                    if after == entry.tags {
+                        None
+                    } else {
+                        Some(TagChange::new(&entry, after))
+                    }
+                })
+                .collect();
+
+            let mut new_conf = Config::get_home_conf().clone();
+            let conf_changes = new_conf.rename_tag(&old, &new);
+            let applied =
+                main_util::apply_tag_changes(repo, changes, &conf_changes, dry_run, yes).await?;
+            if applied && !conf_changes.is_empty() {
+                log::info!("將設定檔中的標籤 {} 改名為 {}", old, new);
+                ret.conf = Some(new_conf);
+            }
+        }
         Subs::Tags {
             subcmd: Some(Tags::Set { content, name }),
         } => {
@@ -656,15 +1014,365 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
             }
         },
         Subs::History {
-            subcmd: History::Tidy,
+            subcmd: History::Undo { count, list },
+        } => {
+            let print_mutation = |m: &MutationRecord| {
+                let time = Local.from_utc_datetime(&m.time);
+                println!(
+                    "{}\t{}\t{} events\t{}",
+                    time.format("%Y-%m-%d %H:%M:%S"),
+                    m.kind,
+                    m.event_count,
+                    m.cmd
+                );
+            };
+            if list {
+                let historian = repo.historian().await?;
+                for m in historian.mutations(u32::MAX).await?.iter() {
+                    print_mutation(m);
+                }
+            } else {
+                let env = repo.env().await?;
+                let (mutations, last_times) = env.historian.undo(count).await?;
+                if mutations.is_empty() {
+                    log::warn!("沒有可以復原的修改");
+                }
+                for m in mutations.iter() {
+                    print!("undone: ");
+                    print_mutation(m);
+                }
+                for res in last_times.into_iter() {
+                    env.update_last_time_directly(res).await?;
+                }
+            }
+        }
+        Subs::History {
+            subcmd: History::Tidy { dry_run },
         } => {
             let repo = repo.init().await?;
             let historian = repo.historian().clone();
 
+            let retention = &conf.history_retention;
+            let days_ago = |days: u32| Utc::now().naive_utc() - Duration::days(days.into());
+            let policy = RetentionPolicy {
+                keep_execs: retention.keep_execs,
+                humble_before: retention.humble_days.map(days_ago),
+                ignored_before: retention.ignored_days.map(days_ago),
+                collapse_repeats: retention.collapse_repeats,
+            };
             let id_vec: Vec<_> = repo.iter_mut(Visibility::All).map(|e| e.id).collect();
-            let tidy_fut = id_vec.iter().map(|id| historian.tidy(*id));
-            try_join_all(tidy_fut).await?;
-            historian.clear_except_script_ids(&id_vec).await?;
+            let report = historian.tidy(&id_vec, &policy, dry_run).await?;
+            if dry_run {
+                print!("would remove");
+            } else {
+                print!("removed");
+            }
+            println!(
+                " {} events (~{} bytes), {} of them collapsed repeated runs",
+                report.rows, report.bytes, report.collapsed
+            );
+            if let Some((before, after)) = report.file_size {
+                println!("history file: {} -> {} bytes", before, after);
+            }
+        }
+        Subs::History {
+            subcmd: History::Export { since, queries },
+        } => {
+            let repo = repo.init().await?;
+            let historian = repo.historian().clone();
+            let names: HashMap<_, _> = query::do_list_query(repo, queries)
+                .await?
+                .into_iter()
+                .map(|e| (e.id, e.name.key().into_owned()))
+                .collect();
+            let ids: Vec<_> = names.keys().copied().collect();
+            let events = historian.export(&ids, since.map(|t| t.time())).await?;
+            let stdout = std::io::stdout();
+            let count = main_util::export_history(events, &names, &mut stdout.lock())?;
+            log::info!("匯出 {} 筆事件", count);
+        }
+        Subs::History {
+            subcmd: History::Import { file, dedupe },
+        } => {
+            let repo = repo.init().await?;
+            let historian = repo.historian().clone();
+            let ids: HashMap<_, _> = repo
+                .iter_mut(Visibility::All)
+                .map(|e| (e.name.key().into_owned(), e.id))
+                .collect();
+            let (events, unknown) = if file.as_os_str() == "-" {
+                main_util::parse_history_lines(std::io::stdin().lock(), &ids)?
+            } else {
+                let f = std::fs::File::open(&file).map_err(|e| util::handle_fs_err(&[&file], e))?;
+                main_util::parse_history_lines(std::io::BufReader::new(f), &ids)?
+            };
+            for name in unknown.iter() {
+                log::warn!("略過不存在的腳本 {} 的事件", name);
+            }
+
+            let report = historian.import(&events, dedupe).await?;
+            for res in report.last_times.iter() {
+                let mut entry = repo.get_mut_by_id(res.script_id).unwrap();
+                if check_time_changed(&entry, res) {
+                    entry
+                        .update(|info| {
+                            info.exec_time = res.exec_time.map(ScriptTime::new);
+                            info.exec_done_time = res.exec_done_time.map(ScriptTime::new);
+                            info.humble_time = res.humble_time;
+                        })
+                        .await?;
+                }
+            }
+            print!("imported {} events", report.imported);
+            if dedupe {
+                print!(", {} duplicated", report.duplicated);
+            }
+            println!();
+            if report.orphaned > 0 {
+                println!(
+                    "skipped {} events without their main event",
+                    report.orphaned
+                );
+            }
+            if !unknown.is_empty() {
+                print!("skipped events of unknown scripts: ");
+                print_iter(unknown.iter(), " ");
+                println!();
+            }
+        }
+        Subs::History {
+            subcmd:
+                History::Search {
+                    pattern,
+                    dir,
+                    since,
+                    failed,
+                    env,
+                    limit,
+                },
+        } => {
+            let repo = repo.init().await?;
+            let historian = repo.historian().clone();
+            let dir = util::option_map_res(dir, path::normalize_path)?;
+            let names: HashMap<_, _> = repo
+                .iter_mut(Visibility::Normal)
+                .map(|e| (e.id, e.name.key().into_owned()))
+                .collect();
+            let ids: Vec<_> = names.keys().copied().collect();
+            let query = SearchQuery {
+                ids: &ids,
+                pattern: pattern.as_deref(),
+                dir: dir.as_deref(),
+                since: since.map(|s| s.time()),
+                failed,
+                env: env.as_deref(),
+                limit: Some(limit),
+            };
+            let records = historian.search(&query).await?;
+
+            for record in records.into_iter() {
+                log::debug!("搜尋到事件 {:?}", record);
+                let args: Vec<String> = serde_json::from_str(&record.args)?;
+                let name = names.get(&record.script_id).map_or("", |s| s.as_str());
+                let time = Local.from_utc_datetime(&record.time);
+                let code = record.code.map_or("-".to_owned(), |c| c.to_string());
+                print!(
+                    "{}\t{}\t{}\t",
+                    name,
+                    time.format("%Y-%m-%d %H:%M"),
+                    record.dir
+                );
+                print_iter(args.iter().map(|s| to_display_args(s)), " ");
+                println!("\t{}", code);
+            }
+        }
+        Subs::History {
+            subcmd:
+                History::Log {
+                    limit,
+                    offset,
+                    since,
+                    until,
+                    by_day,
+                    follow,
+                    purged,
+                },
+        } => {
+            let repo = repo.init().await?;
+            let historian = repo.historian().clone();
+            let mut names: HashMap<_, _> = repo
+                .iter_mut(Visibility::Normal)
+                .map(|e| (e.id, e.name.key().into_owned()))
+                .collect();
+            if purged {
+                for (id, name) in historian.tombstones().await? {
+                    names.insert(id, format!("{} (purged)", name));
+                }
+            }
+            let ids: Vec<_> = names.keys().copied().collect();
+            let query = LogQuery {
+                ids: &ids,
+                since: since.map(|t| t.time()),
+                until: until.map(|t| t.time()),
+                after_id: None,
+                limit: Some(limit),
+                offset,
+            };
+
+            let mut last_day = None;
+            let mut print_record = |record: &LogRecord, names: &HashMap<i64, String>| {
+                let time = Local.from_utc_datetime(&record.time);
+                let time = if by_day {
+                    let day = time.date_naive();
+                    if last_day != Some(day) {
+                        println!("-- {} --", day);
+                        last_day = Some(day);
+                    }
+                    time.format("%H:%M:%S")
+                } else {
+                    time.format("%Y-%m-%d %H:%M:%S")
+                };
+                let kind = match record.ty {
+                    EventType::Exec | EventType::PreExec => "exec",
+                    EventType::ExecDone => "exit",
+                    EventType::Read => "read",
+                    EventType::Write => "edit",
+                    EventType::Neglect => "neglect",
+                    EventType::Rename => "rename",
+                    EventType::Copy => "copy",
+                    EventType::Retag => "retag",
+                    EventType::TypeChange => "type",
+                    EventType::Delete => "delete",
+                    EventType::Restore => "restore",
+                };
+                let name = names.get(&record.script_id).map_or("", |s| s.as_str());
+                print!("{}\t{}\t{}\t", time, kind, name);
+                match record.ty {
+                    EventType::Exec | EventType::PreExec => {
+                        let args = record.args.as_deref().unwrap_or("[]");
+                        let args: Vec<String> = serde_json::from_str(args).unwrap_or_default();
+                        print_iter(args.iter().map(|s| to_display_args(s)), " ");
+                        if record.repeat_count > 1 {
+                            print!(" (x{})", record.repeat_count);
+                        }
+                    }
+                    EventType::ExecDone => {
+                        print!("{}", main_util::exec_status(record.code, false));
+                    }
+                    EventType::Copy => {
+                        let from = record.content.as_deref().unwrap_or_default();
+                        match from.parse().ok().and_then(|id| names.get(&id)) {
+                            Some(name) => print!("from {}", name),
+                            None => print!("from #{}", from),
+                        }
+                    }
+                    EventType::Rename | EventType::Retag | EventType::TypeChange => {
+                        print!("{}", record.content.as_deref().unwrap_or_default());
+                    }
+                    _ => (),
+                }
+                if record.humble {
+                    print!(" (humble)");
+                }
+                println!();
+            };
+
+            let mut last_id = 0;
+            let records = historian.log(&query).await?;
+            for record in records.iter().rev() {
+                last_id = std::cmp::max(last_id, record.event_id);
+                print_record(record, &names);
+            }
+            if follow {
+                // NOTE: 開始時被篩掉的腳本維持隱藏，之後才出現的腳本則要跟著顯示
+                let known: HashSet<_> = repo.iter_with_select_hidden().map(|(i, _)| i.id).collect();
+                loop {
+                    use std::io::Write;
+                    std::io::stdout().flush()?;
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    for (id, name) in repo.fetch_names().await? {
+                        if names.contains_key(&id) || !known.contains(&id) {
+                            names.insert(id, name);
+                        }
+                    }
+                    let ids: Vec<_> = names.keys().copied().collect();
+                    let query = LogQuery {
+                        ids: &ids,
+                        after_id: Some(last_id),
+                        limit: None,
+                        offset: 0,
+                        ..query
+                    };
+                    let records = historian.log(&query).await?;
+                    for record in records.iter().rev() {
+                        last_id = std::cmp::max(last_id, record.event_id);
+                        print_record(record, &names);
+                    }
+                }
+            }
+        }
+        Subs::History {
+            subcmd:
+                History::ToScript {
+                    since,
+                    until,
+                    dir,
+                    tags,
+                    name,
+                },
+        } => {
+            let repo = repo.init().await?;
+            let historian = repo.historian().clone();
+            let name = name.into_script_name()?;
+            if repo.get_mut(&name, Visibility::All).is_some() {
+                return Err(Error::ScriptExist(name.to_string()));
+            }
+            let dir = util::option_map_res(dir, path::normalize_path)?;
+            let names: HashMap<_, _> = repo
+                .iter_mut(Visibility::All)
+                .map(|e| (e.id, e.name.key().into_owned()))
+                .collect();
+
+            let mut query = EventQuery::new()
+                .scripts(names.keys().copied())
+                .types([EventType::Exec])
+                .humble(Some(false))
+                .since(since.time());
+            if let Some(until) = until {
+                query = query.until(until.time());
+            }
+            if let Some(dir) = dir {
+                query = query.dir(dir);
+            }
+            let mut records = historian.query(&query).await?;
+            records.reverse();
+            let content = main_util::replay_lines(&records, &names);
+            if content.is_empty() {
+                return Err(Error::NoRunInWindow);
+            }
+            log::info!("以 {} 筆執行紀錄創造 {:?}", content.len(), name);
+
+            let ty = ScriptFullType::default();
+            let script_path = path::open_script(&name, &ty.ty, Some(false))?;
+            let template = util::get_or_create_template(&ty, true, true)?;
+            let (template, vars) = template_var::resolve_vars(&template, &[])?;
+            let mut selector = conf.main_tag_selector.clone();
+            if let Some(tags) = tags {
+                selector.push(tags);
+            }
+            let mut tag_expiry = HashMap::default();
+            selector.fill_expiry_map(&mut tag_expiry);
+            let mut builder = ScriptInfo::builder(
+                0,
+                0,
+                name.clone(),
+                ty.ty.clone(),
+                selector.into_allowed_iter(),
+            );
+            builder.tag_expiry(tag_expiry);
+            let mut entry = repo.entry(&name).or_insert(builder.build()).await?;
+            util::prepare_script(&script_path, &entry, Some((template, &vars)), &content)?;
+            main_util::after_script(&mut entry, &script_path, None).await?;
         }
         Subs::History {
             subcmd: History::Neglect { queries },
@@ -685,10 +1393,27 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
                     offset,
                     display,
                     dir,
+                    with_status,
+                    failed,
+                    succeeded,
                 },
         } => {
             let repo = repo.init().await?;
             let historian = repo.historian().clone();
+            let status = match (failed, succeeded) {
+                (true, false) => StatusFilter::Failed,
+                (false, true) => StatusFilter::Succeeded,
+                (false, false) => StatusFilter::All,
+                _ => unreachable!(),
+            };
+            let running: HashSet<_> = if with_status {
+                main_util::get_all_active_process_locks()?
+                    .iter()
+                    .map(|l| l.get_run_id())
+                    .collect()
+            } else {
+                HashSet::default()
+            };
             let dir = util::option_map_res(dir, |d| path::normalize_path(d))?;
             let scripts = query::do_list_query(repo, queries).await?;
             let ids: Vec<_> = scripts.iter().map(|s| s.id).collect();
@@ -721,67 +1446,50 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
             }
 
             let mut script_getter = ScriptGetter::new(&ids, repo)?;
-            let mut print_basic = |script_id: i64, args: Vec<String>| -> Result {
+            let mut print_basic = |record: &ArgsRecord, args: Vec<String>| -> Result {
                 if with_name {
-                    let info = script_getter.get(script_id)?;
+                    let info = script_getter.get(record.script_id)?;
                     print!("{}", info.name.key());
                     if !args.is_empty() {
                         print!(" ");
                     }
                 }
                 print_iter(args.iter().map(|s| to_display_args(s)), " ");
+                if with_status {
+                    let running = running.contains(&record.event_id);
+                    print!("\t{}", main_util::exec_status(record.code, running));
+                }
                 println!("");
                 Ok(())
             };
 
-            match display {
-                HistoryDisplay::All => {
-                    let args_list = historian
-                        .previous_args_list_with_envs(
-                            &ids,
-                            limit,
-                            offset,
-                            no_humble,
-                            dir.as_deref(),
-                        )
-                        .await?;
-                    for (script_id, args, envs) in args_list {
-                        log::debug!("嘗試打印參數 {} {} {}", script_id, args, envs);
-                        let args: Vec<String> = serde_json::from_str(&args)?;
-                        let envs: Vec<EnvPair> = serde_json::from_str(&envs)?;
-                        print_basic(script_id, args)?;
-                        for p in envs.into_iter() {
-                            println!("  {}", p);
-                        }
-                    }
-                }
-                HistoryDisplay::Args => {
-                    let args_list = historian
-                        .previous_args_list(&ids, limit, offset, no_humble, dir.as_deref())
-                        .await?;
-                    for (script_id, args) in args_list {
-                        log::debug!("嘗試打印參數 {} {}", script_id, args);
-                        let args: Vec<String> = serde_json::from_str(&args)?;
-                        print_basic(script_id, args)?;
-                    }
-                }
-                HistoryDisplay::Env => {
-                    let args_list = historian
-                        .previous_args_list_only_envs(
-                            &ids,
-                            limit,
-                            offset,
-                            no_humble,
-                            dir.as_deref(),
-                        )
-                        .await?;
-                    for (script_id, envs) in args_list {
-                        log::debug!("嘗試打印參數 {} {}", script_id, envs);
-                        let envs: Vec<EnvPair> = serde_json::from_str(&envs)?;
-                        print_basic(script_id, vec![])?;
-                        for p in envs.into_iter() {
-                            println!("  {}", p);
-                        }
+            let dir = dir.as_deref();
+            let args_list: Vec<_> = match display {
+                HistoryDisplay::All => historian
+                    .previous_args_list_with_envs(&ids, limit, offset, no_humble, dir, status)
+                    .await?
+                    .collect(),
+                HistoryDisplay::Args => historian
+                    .previous_args_list(&ids, limit, offset, no_humble, dir, status)
+                    .await?
+                    .collect(),
+                HistoryDisplay::Env => historian
+                    .previous_args_list_only_envs(&ids, limit, offset, no_humble, dir, status)
+                    .await?
+                    .collect(),
+            };
+            for record in args_list.iter() {
+                log::debug!("嘗試打印參數 {:?}", record);
+                let args: Vec<String> = if display.show_args() {
+                    serde_json::from_str(&record.args)?
+                } else {
+                    vec![]
+                };
+                print_basic(record, args)?;
+                if display.show_env() {
+                    let envs: Vec<EnvPair> = serde_json::from_str(&record.envs)?;
+                    for p in envs.into_iter() {
+                        println!("  {}", p);
                     }
                 }
             }
@@ -828,6 +1536,14 @@ async fn main_inner(root: Root, resource: &mut Resource, ret: &mut MainReturn<'_
     Ok(())
 }
 
+fn print_alias(before: &str, alias: &Alias) {
+    let after = alias.after.join(" ");
+    match &alias.description {
+        Some(description) => println!("{}\t{}\t# {}", before, after, description),
+        None => println!("{}\t{}", before, after),
+    }
+}
+
 async fn create_read_event(entry: &mut RepoEntry<'_>) -> Result<i64> {
     entry.update(|info| info.read()).await
 }
@@ -2,20 +2,26 @@ use crate::color::Color;
 use crate::error::{Error, FormatCode, Result};
 use crate::path;
 use crate::script_type::{ScriptType, ScriptTypeConfig};
-use crate::tag::{TagGroup, TagSelector, TagSelectorGroup};
+use crate::tag::{Tag, TagGroup, TagRegistry, TagSelector, TagSelectorGroup, TagSet};
+use crate::to_display_args;
 use crate::util::{self, impl_de_by_value_enum, impl_ser_by_to_string};
 use clap::ValueEnum;
 use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
 use handlebars::Handlebars;
+use hyper_scripter_historian::StorageKind;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::SystemTime;
 
 const CONFIG_FILE: &str = ".config.toml";
 pub const CONFIG_FILE_ENV: &str = "HYPER_SCRIPTER_CONFIG";
+const SYSTEM_CONFIG_FILE: &str = "/etc/hyper-scripter/config.toml";
+const PROJECT_CONFIG_FILE: &str = ".hs.toml";
 
 crate::local_global_state!(config_state, Config, || { Default::default() });
+crate::local_global_state!(home_config_state, Config, || { Default::default() });
 crate::local_global_state!(runtime_conf_state, RuntimeConf, || { unreachable!() });
 
 struct RuntimeConf {
@@ -46,6 +52,14 @@ pub fn config_file(home: &Path) -> PathBuf {
     }
 }
 
+/// 從當前目錄往上找專案設定檔 `.hs.toml`
+fn project_config_file() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+        .find(|p| p.is_file())
+}
+
 fn is_false(b: &bool) -> bool {
     !*b
 }
@@ -62,10 +76,27 @@ pub struct NamedTagSelector {
 pub struct Alias {
     #[serde(deserialize_with = "de_nonempty_vec")]
     pub after: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
 }
 impl From<Vec<String>> for Alias {
     fn from(after: Vec<String>) -> Self {
-        Alias { after }
+        Alias {
+            after,
+            description: None,
+        }
+    }
+}
+
+/// 具名的查詢預設，可在任何接受列表查詢的地方以 `@@名字` 使用
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct QueryPreset {
+    #[serde(deserialize_with = "de_nonempty_vec")]
+    pub args: Vec<String>,
+}
+impl From<Vec<String>> for QueryPreset {
+    fn from(args: Vec<String>) -> Self {
+        QueryPreset { args }
     }
 }
 
@@ -139,6 +170,129 @@ impl Alias {
             },
         )
     }
+    /// 展開別名，`$1`、`${2:-預設值}`、`$@` 等佔位符會換成使用者給的參數，`$$` 代表 `$` 本身。
+    /// 有用到佔位符時，只有編號大於所有被引用者的參數會接在最後（用了 `$@` 則不接）；
+    /// 沒用到時則全部接在最後。shell 別名中的參數會先跳脫
+    ///
+    /// ```rust
+    /// use hyper_scripter::config::Alias;
+    ///
+    /// fn expand(after: &[&str], args: &[&str]) -> Vec<String> {
+    ///     let alias = Alias::from(after.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+    ///     let args: Vec<_> = args.iter().map(|s| s.to_string()).collect();
+    ///     alias.expand("a", &args).unwrap().1
+    /// }
+    ///
+    /// assert_eq!(expand(&["ls", "-l"], &["x", "y"]), ["ls", "-l", "x", "y"]);
+    /// assert_eq!(
+    ///     expand(&["run", "=deploy/$1!", "--", "--env", "${2:-staging}"], &["api"]),
+    ///     ["run", "=deploy/api!", "--", "--env", "staging"]
+    /// );
+    /// assert_eq!(expand(&["run", "${2:-x}", "$1"], &["a", "b", "c"]), ["run", "b", "a", "c"]);
+    /// assert_eq!(expand(&["run", "$@", "--"], &["a", "b"]), ["run", "a", "b", "--"]);
+    /// assert_eq!(expand(&["e", "msg $@ $$1"], &["a", "b"]), ["e", "msg a b $1"]);
+    /// assert_eq!(expand(&["!echo", "$1"], &["a b"]), ["echo", "'a b'"]);
+    ///
+    /// let alias = Alias::from(vec!["run".to_owned(), "$1".to_owned()]);
+    /// assert!(alias.expand("a", &[]).is_err());
+    /// ```
+    pub fn expand(&self, name: &str, args: &[String]) -> Result<(bool, Vec<String>)> {
+        let (is_shell, after) = self.args();
+        let escape = |s: &str| -> String {
+            if is_shell {
+                to_display_args(s).into_owned()
+            } else {
+                s.to_owned()
+            }
+        };
+        let mut used = Placeholders::default();
+        let mut ret = vec![];
+        for arg in after {
+            if arg == "$@" {
+                used.all = true;
+                ret.extend(args.iter().map(|a| escape(a)));
+            } else {
+                ret.push(used.substitute(name, arg, args, escape)?);
+            }
+        }
+        let leftover = if used.all {
+            &[][..]
+        } else {
+            &args[std::cmp::min(used.max, args.len())..]
+        };
+        ret.extend(leftover.iter().map(|a| escape(a)));
+        Ok((is_shell, ret))
+    }
+}
+
+#[derive(Default)]
+struct Placeholders {
+    /// 被引用的最大編號
+    max: usize,
+    /// 是否用了 `$@`
+    all: bool,
+}
+impl Placeholders {
+    fn substitute(
+        &mut self,
+        name: &str,
+        s: &str,
+        args: &[String],
+        escape: impl Fn(&str) -> String,
+    ) -> Result<String> {
+        let mut ret = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(pos) = rest.find('$') {
+            ret.push_str(&rest[..pos]);
+            rest = &rest[pos + 1..];
+            let (index, default, len) = if let Some(r) = rest.strip_prefix('$') {
+                ret.push('$');
+                rest = r;
+                continue;
+            } else if let Some(r) = rest.strip_prefix('@') {
+                self.all = true;
+                let all: Vec<_> = args.iter().map(|a| escape(a)).collect();
+                ret.push_str(&all.join(" "));
+                rest = r;
+                continue;
+            } else if let Some(r) = rest.strip_prefix('{') {
+                let Some(end) = r.find('}') else {
+                    ret.push('$');
+                    continue;
+                };
+                let (index, default) = match r[..end].split_once(":-") {
+                    Some((index, default)) => (index, Some(default)),
+                    None => (&r[..end], None),
+                };
+                (index, default, end + 2)
+            } else {
+                let end = rest
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(rest.len());
+                (&rest[..end], None, end)
+            };
+            let index = match index.parse::<usize>() {
+                Ok(index) if index > 0 => index,
+                _ => {
+                    // NOTE: 不是佔位符，當一般的 `$` 看待
+                    ret.push('$');
+                    continue;
+                }
+            };
+            rest = &rest[len..];
+            self.max = std::cmp::max(self.max, index);
+            match (args.get(index - 1), default) {
+                (Some(arg), _) => ret.push_str(&escape(arg)),
+                (None, Some(default)) => ret.push_str(default),
+                (None, None) => {
+                    log::error!("別名 {} 缺少第 {} 個參數", name, index);
+                    return Err(Error::AliasArgMissing(name.to_owned(), index));
+                }
+            }
+        }
+        ret.push_str(rest);
+        Ok(ret)
+    }
 }
 
 #[derive(Display, PartialEq, Eq, Debug, Clone, Copy, ValueEnum)]
@@ -213,6 +367,141 @@ impl<'de> serde::Deserialize<'de> for Recent {
     }
 }
 
+/// 自動封存策略：超過 `days` 天沒有動靜的腳本，會被加上 `tag` 標籤並標記為忽略
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+pub struct ArchivePolicy {
+    pub days: u32,
+    pub tag: Tag,
+    /// 只有符合此條件的腳本會被封存
+    pub select: TagGroup,
+    /// 是否在每次修改腳本時自動執行，否則只能透過 `hs archive` 手動執行
+    #[serde(default)]
+    pub auto: bool,
+}
+impl Default for ArchivePolicy {
+    fn default() -> Self {
+        ArchivePolicy {
+            days: 30,
+            tag: "hide".parse().unwrap(),
+            select: "all,^pin,^util".parse().unwrap(),
+            auto: false,
+        }
+    }
+}
+
+/// `hs history tidy` 的保留規則
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone, Default)]
+pub struct HistoryRetention {
+    /// 每支腳本最多保留幾筆執行紀錄，不設則不限
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_execs: Option<u32>,
+    /// 謙卑事件保留的天數，不設則不限
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub humble_days: Option<u32>,
+    /// 被忽略的事件保留的天數，不設則不限
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ignored_days: Option<u32>,
+    /// 將連續以相同參數執行的紀錄合併為一筆
+    /// 與 `keep_execs` 皆未設定時，每組參數及目錄只保留最新一筆執行紀錄
+    #[serde(default)]
+    pub collapse_repeats: bool,
+}
+
+/// 具名的環境變數組合，執行時以 `hs run --profile <name>` 選用
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone, Default)]
+pub struct EnvProfile {
+    /// 同 `env`，值會經過 Handlebars 渲染
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// 沒有指定組合時，符合此條件的腳本預設使用此組合
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<TagGroup>,
+    /// 以此組合執行時一律需要確認，如同帶有 caution 標籤
+    #[serde(default)]
+    pub dangerous: bool,
+}
+impl EnvProfile {
+    pub fn gen_env(&self, info: &crate::util::TmplVal<'_>) -> Result<Vec<(String, String)>> {
+        let reg = Handlebars::new();
+        let mut env: Vec<(String, String)> = Vec::with_capacity(self.env.len());
+        for (name, e) in self.env.iter() {
+            let res = reg.render_template(e, info)?;
+            env.push((name.to_owned(), res));
+        }
+        Ok(env)
+    }
+}
+
+/// 設定值的來源
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    Default,
+    File(PathBuf),
+}
+impl std::fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::File(p) => write!(f, "{}", p.to_string_lossy()),
+        }
+    }
+}
+
+/// 疊加在主設定檔上的設定層（系統設定檔或專案設定檔），只能覆寫或擴充部份欄位
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigLayer {
+    recent: Option<Recent>,
+    #[serde(default)]
+    alias: HashMap<String, Alias>,
+    #[serde(default)]
+    env: HashMap<String, String>,
+    #[serde(default)]
+    tag_selectors: Vec<NamedTagSelector>,
+    #[serde(default)]
+    types: HashMap<ScriptType, ScriptTypeConfig>,
+    #[serde(default)]
+    env_profiles: HashMap<String, EnvProfile>,
+}
+impl ConfigLayer {
+    fn load(path: &Path) -> Result<Option<Self>> {
+        match util::read_file(path) {
+            Ok(s) => {
+                log::info!("載入設定層：{:?}", path);
+                let layer = toml::from_str(&s).map_err(|err| {
+                    FormatCode::Config.to_err(format!("{}: {}", path.to_string_lossy(), err))
+                })?;
+                Ok(Some(layer))
+            }
+            Err(Error::PathNotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn merge_map<K: std::hash::Hash + Eq + std::fmt::Display, V>(
+    dst: &mut HashMap<K, V>,
+    src: HashMap<K, V>,
+    overwrite: bool,
+    mut record: impl FnMut(String),
+    field: &str,
+) {
+    for (key, value) in src.into_iter() {
+        if overwrite || !dst.contains_key(&key) {
+            record(format!("{}.{}", field, key));
+            dst.insert(key, value);
+        }
+    }
+}
+
+/// 設定檔中某處的改動，用於在實際寫入前顯示給使用者
+#[derive(Debug)]
+pub struct ConfigChange {
+    pub place: String,
+    pub before: String,
+    pub after: String,
+}
+
 #[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
 pub struct Config {
     pub recent: Recent,
@@ -222,25 +511,38 @@ pub struct Config {
     prompt_level: PromptLevel,
     #[serde(deserialize_with = "de_nonempty_vec")]
     pub editor: Vec<String>,
+    /// 歷史的儲存方式
+    #[serde(default)]
+    pub history_backend: StorageKind,
     pub tag_selectors: Vec<NamedTagSelector>,
+    #[serde(default, skip_serializing_if = "TagRegistry::is_empty")]
+    pub tag_registry: TagRegistry,
+    #[serde(default)]
+    pub archive: ArchivePolicy,
+    #[serde(default)]
+    pub history_retention: HistoryRetention,
     pub alias: HashMap<String, Alias>,
+    #[serde(default)]
+    pub query_presets: HashMap<String, QueryPreset>,
     pub types: HashMap<ScriptType, ScriptTypeConfig>,
     pub env: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env_profiles: HashMap<String, EnvProfile>,
     #[serde(skip)]
     last_modified: Option<SystemTime>,
+    /// 被其它設定層覆寫或擴充的設定值，及其來源
+    #[serde(skip)]
+    sources: HashMap<String, ConfigSource>,
 }
 impl Default for Config {
     fn default() -> Self {
         fn gen_alias(from: &str, after: &[&str]) -> (String, Alias) {
-            (
-                from.to_owned(),
-                Alias {
-                    after: after.iter().map(|s| s.to_string()).collect(),
-                },
-            )
+            let after: Vec<_> = after.iter().map(|s| s.to_string()).collect();
+            (from.to_owned(), after.into())
         }
         Config {
             last_modified: None,
+            sources: Default::default(),
             recent: Default::default(),
             editor: vec!["vim".to_string(), "-p".to_string()],
             prompt_level: PromptLevel::Smart,
@@ -263,6 +565,11 @@ impl Default for Config {
             ],
             main_tag_selector: "+all".parse().unwrap(),
             caution_tags: "caution".parse().unwrap(),
+            tag_registry: Default::default(),
+            archive: Default::default(),
+            history_backend: Default::default(),
+            history_retention: Default::default(),
+            query_presets: Default::default(),
             types: ScriptTypeConfig::default_script_types(),
             alias: [
                 gen_alias("la", &["ls", "-a"]),
@@ -317,6 +624,7 @@ impl Default for Config {
             .into_iter()
             .map(|(k, v)| (k.to_owned(), v.to_owned()))
             .collect(),
+            env_profiles: Default::default(),
         }
     }
 }
@@ -344,6 +652,14 @@ impl Config {
     }
 
     pub fn store(&self) -> Result {
+        let layer = self.sources.values().find_map(|source| match source {
+            ConfigSource::File(path) => Some(path),
+            ConfigSource::Default => None,
+        });
+        if let Some(layer) = layer {
+            log::error!("設定檔疊加了其它設定層 {:?}，不寫入", layer);
+            return Err(Error::LayeredConfig(layer.clone()));
+        }
         let path = config_file(path::get_home());
         log::info!("寫入設定檔至 {:?}…", path);
         match util::handle_fs_res(&[&path], std::fs::metadata(&path)) {
@@ -367,8 +683,129 @@ impl Config {
         self.last_modified.is_none()
     }
 
+    /// 依序疊加系統設定檔 `/etc/hyper-scripter/config.toml` 和專案設定檔 `.hs.toml`
+    ///
+    /// 主設定檔的優先度高於系統設定檔，但若主設定檔尚不存在，則系統設定檔會覆寫預設值
+    pub fn with_layers(mut self) -> Result<Self> {
+        let system = Path::new(SYSTEM_CONFIG_FILE);
+        if let Some(layer) = ConfigLayer::load(system)? {
+            let overwrite = self.is_from_dafault();
+            self.apply_layer(layer, ConfigSource::File(system.to_owned()), overwrite);
+        }
+        if let Some(project) = project_config_file() {
+            if let Some(layer) = ConfigLayer::load(&project)? {
+                self.apply_layer(layer, ConfigSource::File(project), true);
+            }
+        }
+        Ok(self)
+    }
+    fn apply_layer(&mut self, layer: ConfigLayer, source: ConfigSource, overwrite: bool) {
+        let ConfigLayer {
+            recent,
+            alias,
+            env,
+            tag_selectors,
+            types,
+            env_profiles,
+        } = layer;
+        let sources = &mut self.sources;
+        let mut record = |key: String| {
+            log::debug!("設定值 {} 來自 {}", key, source);
+            sources.insert(key, source.clone());
+        };
+        if let Some(recent) = recent {
+            if overwrite {
+                self.recent = recent;
+                record("recent".to_owned());
+            }
+        }
+        merge_map(&mut self.alias, alias, overwrite, &mut record, "alias");
+        merge_map(&mut self.env, env, overwrite, &mut record, "env");
+        merge_map(&mut self.types, types, overwrite, &mut record, "types");
+        merge_map(
+            &mut self.env_profiles,
+            env_profiles,
+            overwrite,
+            &mut record,
+            "env_profiles",
+        );
+        for selector in tag_selectors.into_iter() {
+            let key = format!("tag_selectors.{}", selector.name);
+            let existing = self
+                .tag_selectors
+                .iter_mut()
+                .find(|s| s.name == selector.name);
+            match existing {
+                Some(existing) if overwrite => *existing = selector,
+                Some(_) => continue,
+                None => self.tag_selectors.push(selector),
+            }
+            record(key);
+        }
+    }
+    /// 列出所有可被設定層覆寫的設定值，及其來源
+    pub fn explain(&self) -> Vec<(String, String, ConfigSource)> {
+        let base = if self.is_from_dafault() {
+            ConfigSource::Default
+        } else {
+            ConfigSource::File(config_file(path::get_home()))
+        };
+        let mut ret = vec![("recent".to_owned(), self.recent.to_string())];
+        let mut push_map = |field: &str, iter: &mut dyn Iterator<Item = (String, String)>| {
+            let mut v: Vec<_> = iter.map(|(k, v)| (format!("{}.{}", field, k), v)).collect();
+            v.sort();
+            ret.extend(v);
+        };
+        push_map(
+            "alias",
+            &mut self
+                .alias
+                .iter()
+                .map(|(k, v)| (k.clone(), v.after.join(" "))),
+        );
+        push_map(
+            "env",
+            &mut self.env.iter().map(|(k, v)| (k.clone(), v.clone())),
+        );
+        push_map(
+            "types",
+            &mut self
+                .types
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.get_ext().unwrap_or_default().to_owned())),
+        );
+        push_map(
+            "env_profiles",
+            &mut self.env_profiles.iter().map(|(k, v)| {
+                let mut env: Vec<_> = v.env.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+                env.sort();
+                let mut value = env.join(" ");
+                if v.dangerous {
+                    value += " (dangerous)";
+                }
+                (k.clone(), value)
+            }),
+        );
+        for selector in self.tag_selectors.iter() {
+            let mut value = selector.content.to_string();
+            if selector.inactivated {
+                value += " (inactivated)";
+            }
+            ret.push((format!("tag_selectors.{}", selector.name), value));
+        }
+        ret.into_iter()
+            .map(|(key, value)| {
+                let source = self.sources.get(&key).unwrap_or(&base).clone();
+                (key, value, source)
+            })
+            .collect()
+    }
+
     pub fn init() -> Result {
-        config_state::set(Config::load(path::get_home())?);
+        let home_conf = Config::load(path::get_home())?;
+        let conf = home_conf.clone().with_layers()?;
+        home_config_state::set(home_conf);
+        config_state::set(conf);
         Ok(())
     }
 
@@ -384,6 +821,10 @@ impl Config {
     pub fn get() -> &'static Config {
         config_state::get()
     }
+    /// 主設定檔的內容，不含其它設定層。要修改並寫入設定檔時應以此為準
+    pub fn get_home_conf() -> &'static Config {
+        home_config_state::get()
+    }
 
     // XXX: extract
     pub fn gen_env(
@@ -405,14 +846,218 @@ impl Config {
         }
         Ok(env)
     }
+    /// 決定執行腳本時使用的環境組合：有指定名稱就用該組合，
+    /// 否則用第一個（依名稱排序）以標籤綁定到此腳本的組合
+    pub fn get_env_profile(
+        &self,
+        name: Option<&str>,
+        tags: &TagSet,
+        ty: &ScriptType,
+    ) -> Result<Option<(&str, &EnvProfile)>> {
+        if let Some(name) = name {
+            let (name, profile) = self
+                .env_profiles
+                .get_key_value(name)
+                .ok_or_else(|| Error::NoEnvProfile(name.to_owned()))?;
+            return Ok(Some((name, profile)));
+        }
+        let tags = self.tag_registry.expand(tags);
+        let mut bound: Vec<_> = self
+            .env_profiles
+            .iter()
+            .filter(|(_, p)| {
+                p.tags
+                    .as_ref()
+                    .is_some_and(|t| t.select(&tags, ty).is_true())
+            })
+            .map(|(name, p)| (name.as_str(), p))
+            .collect();
+        bound.sort_by_key(|(name, _)| *name);
+        if bound.len() > 1 {
+            log::warn!("多個環境組合綁定到同一腳本，選用 {}", bound[0].0);
+        }
+        Ok(bound.into_iter().next())
+    }
     pub fn get_color(&self, ty: &ScriptType) -> Result<Color> {
-        let c = self.get_script_conf(ty)?.color.as_str();
-        Ok(Color::from(c))
+        let conf = self.get_script_conf(ty)?;
+        Ok(Color::from(conf.color.as_str()))
     }
-    pub fn get_script_conf(&self, ty: &ScriptType) -> Result<&ScriptTypeConfig> {
-        self.types
+    /// 取得類型設定，若有繼承關係則沿著 `extends` 一路補齊
+    pub fn get_script_conf(&self, ty: &ScriptType) -> Result<Cow<'_, ScriptTypeConfig>> {
+        let conf = self
+            .types
             .get(ty)
-            .ok_or_else(|| Error::UnknownType(ty.to_string()))
+            .ok_or_else(|| Error::UnknownType(ty.to_string()))?;
+        let Some(mut parent_ty) = conf.extends.as_ref() else {
+            return Ok(Cow::Borrowed(conf));
+        };
+        let mut ret = conf.clone();
+        let mut visited = vec![ty];
+        loop {
+            if visited.contains(&parent_ty) {
+                log::error!("類型繼承成環：{:?} -> {}", visited, parent_ty);
+                return FormatCode::ScriptType.to_res(ty.to_string());
+            }
+            let parent = self
+                .types
+                .get(parent_ty)
+                .ok_or_else(|| Error::UnknownType(parent_ty.to_string()))?;
+            ret.inherit(parent);
+            visited.push(parent_ty);
+            match parent.extends.as_ref() {
+                Some(p) => parent_ty = p,
+                None => break,
+            }
+        }
+        Ok(Cow::Owned(ret))
+    }
+    /// 直接繼承 `ty` 的類型
+    pub fn iter_children<'a>(&'a self, ty: &'a ScriptType) -> impl Iterator<Item = &'a ScriptType> {
+        self.types.iter().filter_map(move |(name, conf)| {
+            if conf.extends.as_ref() == Some(ty) {
+                Some(name)
+            } else {
+                None
+            }
+        })
+    }
+    pub fn get_tag_color(&self, tag: &Tag) -> Option<Color> {
+        let info = self.tag_registry.lookup(tag)?;
+        info.color.as_deref().map(Color::from)
+    }
+    /// 將設定檔中所有提到 `old` 標籤（及其子孫）的地方改名，回傳改動前後的描述
+    pub fn rename_tag(&mut self, old: &Tag, new: &Tag) -> Vec<ConfigChange> {
+        let mut changes = vec![];
+        for selector in self.tag_selectors.iter_mut() {
+            let before = selector.content.to_string();
+            if selector.content.rename_tag(old, new) {
+                changes.push(ConfigChange {
+                    place: format!("tag selector {}", selector.name),
+                    before,
+                    after: selector.content.to_string(),
+                });
+            }
+        }
+        let before = self.main_tag_selector.to_string();
+        if self.main_tag_selector.rename_tag(old, new) {
+            changes.push(ConfigChange {
+                place: "main tag selector".to_owned(),
+                before,
+                after: self.main_tag_selector.to_string(),
+            });
+        }
+        let before = self.caution_tags.to_string();
+        if self.caution_tags.rename_tag(old, new) {
+            changes.push(ConfigChange {
+                place: "caution tags".to_owned(),
+                before,
+                after: self.caution_tags.to_string(),
+            });
+        }
+        if self.tag_registry.rename_tag(old, new) {
+            changes.push(ConfigChange {
+                place: "tag registry".to_owned(),
+                before: old.to_string(),
+                after: new.to_string(),
+            });
+        }
+        changes
+    }
+    /// 檢查型別無法保證的錯誤：選擇器中不存在的類型、循環的別名和無法編譯的模版
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        let mut referenced: Vec<(String, Vec<&ScriptType>)> = vec![
+            (
+                "main_tag_selector".to_owned(),
+                self.main_tag_selector.iter_types().collect(),
+            ),
+            (
+                "caution_tags".to_owned(),
+                self.caution_tags.iter_types().collect(),
+            ),
+            (
+                "archive.select".to_owned(),
+                self.archive.select.iter_types().collect(),
+            ),
+        ];
+        for selector in self.tag_selectors.iter() {
+            let place = format!("tag_selectors.{}", selector.name);
+            referenced.push((place, selector.content.iter_types().collect()));
+        }
+        for (name, profile) in self.env_profiles.iter() {
+            if let Some(tags) = &profile.tags {
+                let place = format!("env_profiles.{}.tags", name);
+                referenced.push((place, tags.iter_types().collect()));
+            }
+        }
+        for (place, types) in referenced.into_iter() {
+            for ty in types.into_iter() {
+                if !self.types.contains_key(ty) {
+                    problems.push(format!("{}: unknown type `{}`", place, ty));
+                }
+            }
+        }
+
+        for ty in self.types.keys() {
+            match self.get_script_conf(ty) {
+                Ok(conf) if conf.color.is_empty() => {
+                    problems.push(format!("types.{}: missing color", ty));
+                }
+                Ok(_) => (),
+                Err(Error::UnknownType(parent)) => {
+                    problems.push(format!("types.{}: extends unknown type `{}`", ty, parent));
+                }
+                Err(_) => problems.push(format!("types.{}: type inheritance loop", ty)),
+            }
+        }
+
+        let mut alias_names: Vec<_> = self.alias.keys().collect();
+        alias_names.sort();
+        for start in alias_names.into_iter() {
+            let mut chain = vec![start.as_str()];
+            let mut cur = start.as_str();
+            while let Some(alias) = self.alias.get(cur) {
+                let (is_shell, mut args) = alias.args();
+                let Some(next) = args.next().filter(|_| !is_shell) else {
+                    break;
+                };
+                if next == start {
+                    // NOTE: 只在迴圈中字典序最小的別名回報，避免重複
+                    if chain.iter().all(|name| start.as_str() <= *name) {
+                        chain.push(next);
+                        problems.push(format!(
+                            "alias.{}: alias loop {}",
+                            start,
+                            chain.join(" -> ")
+                        ));
+                    }
+                    break;
+                }
+                if chain.contains(&next) {
+                    break;
+                }
+                chain.push(next);
+                cur = next;
+            }
+        }
+
+        let mut check_template = |place: String, tmpl: &str| {
+            if let Err(err) = handlebars::Template::compile(tmpl) {
+                problems.push(format!("{}: bad template: {}", place, err));
+            }
+        };
+        for (name, e) in self.env.iter() {
+            check_template(format!("env.{}", name), e);
+        }
+        for (ty, ty_conf) in self.types.iter() {
+            for (place, tmpl) in ty_conf.iter_templates() {
+                check_template(format!("types.{}.{}", ty, place), tmpl);
+            }
+        }
+
+        problems.sort();
+        problems
     }
     pub fn get_tag_selector_group(&self, toggle: &mut HashSet<String>) -> TagSelectorGroup {
         let mut group = TagSelectorGroup::default();
@@ -432,6 +1077,7 @@ impl Config {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::tag::TagInfo;
     use toml::{from_str, to_string_pretty};
     #[test]
     fn test_config_serde() {
@@ -443,4 +1089,97 @@ mod test {
         let c2: Config = from_str(&s).unwrap();
         assert_eq!(c1, c2);
     }
+    #[test]
+    fn test_store_layered() {
+        let mut conf = Config::default();
+        let layer: ConfigLayer = from_str("[env]\nFOO = 'bar'").unwrap();
+        let project = PathBuf::from("/project/.hs.toml");
+        conf.apply_layer(layer, ConfigSource::File(project.clone()), true);
+        match conf.store() {
+            Err(Error::LayeredConfig(path)) => assert_eq!(path, project),
+            res => panic!("疊加過的設定檔不該被寫入：{:?}", res),
+        }
+    }
+    #[test]
+    fn test_validate() {
+        let mut conf = Config::default();
+        assert_eq!(conf.validate(), Vec::<String>::new());
+
+        conf.main_tag_selector = "+@no-such-type".parse().unwrap();
+        conf.alias
+            .insert("a".to_owned(), vec!["b".to_owned()].into());
+        conf.alias
+            .insert("b".to_owned(), vec!["a".to_owned(), "x".to_owned()].into());
+        conf.alias
+            .insert("c".to_owned(), vec!["a".to_owned()].into());
+        conf.env.insert("BAD".to_owned(), "{{#if x}}".to_owned());
+        let problems = conf.validate();
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert_eq!(problems[0], "alias.a: alias loop a -> b -> a");
+        assert!(problems[1].starts_with("env.BAD: bad template"));
+        assert_eq!(
+            problems[2],
+            "main_tag_selector: unknown type `no-such-type`"
+        );
+    }
+    #[test]
+    fn test_type_inheritance() {
+        use crate::script_type::ExecInfo;
+        let mut conf = Config::default();
+        let ts: ScriptType = "ts".parse().unwrap();
+        let js: ScriptType = "js".parse().unwrap();
+        let exec_info = ExecInfo::new("ts-node".to_owned(), vec!["{{path}}".to_owned()]);
+        let mut ts_conf = ScriptTypeConfig::new(Some(exec_info.clone()), None, String::new());
+        ts_conf.extends = Some(js.clone());
+        conf.types.insert(ts.clone(), ts_conf);
+
+        let resolved = conf.get_script_conf(&ts).unwrap();
+        let js_conf = conf.get_script_conf(&js).unwrap();
+        assert_eq!(resolved.exec_info, Some(exec_info));
+        assert_eq!(resolved.ext, js_conf.ext);
+        assert_eq!(resolved.color, js_conf.color);
+        assert_eq!(conf.validate(), Vec::<String>::new());
+
+        conf.types.get_mut(&js).unwrap().extends = Some(ts.clone());
+        assert!(conf.get_script_conf(&ts).is_err());
+        assert_eq!(
+            conf.validate(),
+            vec![
+                "types.js: type inheritance loop".to_owned(),
+                "types.ts: type inheritance loop".to_owned()
+            ]
+        );
+    }
+    #[test]
+    fn test_tag_registry_serde() {
+        let mut c1 = Config::default();
+        c1.tag_registry.insert(
+            "prod".parse().unwrap(),
+            TagInfo {
+                description: Some("scripts touching production".to_owned()),
+                color: Some("red".to_owned()),
+                implies: vec!["caution".parse().unwrap()],
+            },
+        );
+        c1.tag_registry.insert(
+            "team/infra".parse().unwrap(),
+            TagInfo {
+                description: Some("infra team".to_owned()),
+                color: None,
+                implies: vec!["prod".parse().unwrap()],
+            },
+        );
+        let s = to_string_pretty(&c1).unwrap();
+        let c2: Config = from_str(&s).unwrap();
+        assert_eq!(c1, c2);
+
+        let tags = ["team/infra/db".parse().unwrap()].into_iter().collect();
+        let tags = c2.tag_registry.expand(&tags);
+        let caution: Tag = "caution".parse().unwrap();
+        assert!(tags.contains(&caution));
+        assert!(matches!(
+            c2.get_tag_color(&"prod/db".parse().unwrap()),
+            Some(Color::Red)
+        ));
+    }
 }
@@ -1,4 +1,4 @@
-use crate::error::{DisplayError, DisplayResult, FormatCode::EnvPair as EnvPairCode};
+use crate::error::{DisplayError, DisplayResult, FormatCode::EnvPair as EnvPairCode, Result};
 use crate::util::{impl_de_by_from_str, impl_ser_by_to_string};
 use std::str::FromStr;
 
@@ -58,10 +58,89 @@ impl EnvPair {
             });
         }
     }
+    /// 解析 dotenv 格式的內容，支援 `export` 前綴、註解、單雙引號
+    /// ```
+    /// use hyper_scripter::env_pair::EnvPair;
+    ///
+    /// let content = r#"
+    /// ## 註解
+    /// export A=1
+    /// B = 'a # b'
+    /// C="x\ny" # 行尾註解
+    /// D=
+    /// "#;
+    /// let v = EnvPair::from_dotenv(content).unwrap();
+    /// let v: Vec<_> = v.iter().map(|e| (e.key.as_str(), e.val.as_str())).collect();
+    /// assert_eq!(v, &[("A", "1"), ("B", "a # b"), ("C", "x\ny"), ("D", "")]);
+    ///
+    /// assert!(EnvPair::from_dotenv("NO_VALUE").is_err());
+    /// assert!(EnvPair::from_dotenv("A='unclosed").is_err());
+    /// ```
+    pub fn from_dotenv(content: &str) -> Result<Vec<Self>> {
+        let mut ret = vec![];
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let stripped = line.strip_prefix("export ").unwrap_or(line);
+            let Some((key, val)) = stripped.split_once('=') else {
+                return EnvPairCode.to_res(line.to_owned());
+            };
+            let key = key.trim();
+            let valid_key =
+                !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid_key {
+                return EnvPairCode.to_res(line.to_owned());
+            }
+            let Some(val) = parse_dotenv_val(val.trim()) else {
+                return EnvPairCode.to_res(line.to_owned());
+            };
+            ret.retain(|p: &EnvPair| p.key != key);
+            ret.push(EnvPair {
+                key: key.to_owned(),
+                val,
+            });
+        }
+        Ok(ret)
+    }
     pub fn sort(v: &mut Vec<Self>) {
         v.sort_by(|a, b| a.key.cmp(&b.key));
     }
 }
+fn parse_dotenv_val(val: &str) -> Option<String> {
+    let mut chars = val.chars();
+    let quote = match chars.next() {
+        Some(q @ ('\'' | '"')) => q,
+        _ => {
+            // 無引號的值，` #` 之後視為註解
+            let val = match val.find(" #") {
+                Some(pos) => &val[..pos],
+                None => val,
+            };
+            return Some(val.trim_end().to_owned());
+        }
+    };
+    let mut ret = String::new();
+    loop {
+        match chars.next()? {
+            c if c == quote => break,
+            '\\' if quote == '"' => match chars.next()? {
+                'n' => ret.push('\n'),
+                't' => ret.push('\t'),
+                c => ret.push(c),
+            },
+            c => ret.push(c),
+        }
+    }
+    let rest = chars.as_str().trim_start();
+    if rest.is_empty() || rest.starts_with('#') {
+        Some(ret)
+    } else {
+        None
+    }
+}
+
 impl FromStr for EnvPair {
     type Err = DisplayError;
     fn from_str(s: &str) -> DisplayResult<Self> {
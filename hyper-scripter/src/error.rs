@@ -42,6 +42,10 @@ pub enum FormatCode {
     Tag,
     NonEmptyArray,
     EnvPair,
+    QueryPreset,
+    TemplateVar,
+    Time,
+    HistoryLine,
     Template, // TODO: 一旦特化穩定了，就讓 handlebars 錯誤自動轉成這個
 }
 
@@ -76,7 +80,18 @@ pub enum Error {
     ScriptIsFiltered(String),
     ScriptNotFound(String),
     NoAlias(String),
+    AliasArgMissing(String, usize),
+    AliasLoop(Vec<String>),
+    NoQueryPreset(String),
+    NoEnvProfile(String),
+    UnknownConfigKey(String),
+    LayeredConfig(PathBuf),
+    MissingTemplateVar(String),
+    NoBuiltinTemplate(String),
     UnknownType(String),
+    TypeExist(String),
+    TypeInUse(String, Vec<String>),
+    TypeExtended(String, Vec<String>),
     Format(FormatCode, String),
 
     CancelExecEvent,
@@ -88,6 +103,7 @@ pub enum Error {
     TagSelectorNotFound(String),
     DontFuzz,
     NoPreviousArgs,
+    NoRunInWindow,
     Empty,
     Caution,
 
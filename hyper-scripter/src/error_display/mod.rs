@@ -25,6 +25,7 @@ impl Display for Error {
             DontFuzz | Caution => return Ok(()),
             Empty => write!(f, "No existing script!")?,
             NoPreviousArgs => write!(f, "No previous argument!")?,
+            NoRunInWindow => write!(f, "No run found in the given time window!")?,
             SysPathNotFound(SysPath::Config) => write!(
                 f,
                 "Can not find you're config path. Usually it should be `$HOME/.config`",
@@ -51,6 +52,10 @@ impl Display for Error {
                     Tag => write!(f, "tag")?,
                     PromptLevel => write!(f, "prompt level")?,
                     EnvPair => write!(f, "env pair (e.g. VAR=1)")?,
+                    QueryPreset => write!(f, "query preset")?,
+                    TemplateVar => write!(f, "template variable")?,
+                    Time => write!(f, "time (e.g. 30d or 2024-01-31)")?,
+                    HistoryLine => write!(f, "history line")?,
                     Template => write!(f, "template")?,
                     NonEmptyArray => {
                         write!(f, "non-empty array")?;
@@ -67,6 +72,34 @@ impl Display for Error {
                 write!(f, "Editor `{}` exited unexpectedly with {}", cmd, code)?
             }
             NoAlias(alias) => write!(f, "No such alias: {}", alias)?,
+            AliasLoop(names) => write!(f, "Alias loop: {}", names.join(" -> "))?,
+            AliasArgMissing(alias, index) => {
+                write!(f, "Alias `{}` needs argument ${}", alias, index)?
+            }
+            NoQueryPreset(name) => write!(f, "No such query preset: {}", name)?,
+            NoEnvProfile(name) => write!(f, "No such env profile: {}", name)?,
+            UnknownConfigKey(key) => write!(f, "Unknown config key: {}", key)?,
+            LayeredConfig(path) => write!(
+                f,
+                "Refusing to store config merged with layer {}",
+                path.to_string_lossy()
+            )?,
+            MissingTemplateVar(name) => write!(
+                f,
+                "Missing value for template variable `{}`, give it with `--var {}=...`",
+                name, name
+            )?,
+            NoBuiltinTemplate(ty) => write!(f, "No built-in template for: {}", ty)?,
+            TypeExist(ty) => write!(f, "Type already exists: {}", ty)?,
+            TypeInUse(ty, scripts) => write!(
+                f,
+                "Type `{}` is still used by: {} (use `--migrate-to` to change their type)",
+                ty,
+                scripts.join(", ")
+            )?,
+            TypeExtended(ty, children) => {
+                write!(f, "Type `{}` is extended by: {}", ty, children.join(", "))?
+            }
             RedundantOpt(opt) => write!(f, "Redundant option: {:?}", opt)?,
             _ => {
                 log::warn!("未被正確打印的錯誤：{:?}", self);
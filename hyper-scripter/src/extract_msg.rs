@@ -3,8 +3,11 @@ use crate::util::impl_ser_and_display_by_as_ref;
 const HELP_KEY: &str = "[HS_HELP]:";
 const ENV_KEY: &str = "[HS_ENV]:";
 const ENV_HELP_KEY: &str = "[HS_ENV_HELP]:";
+const ENV_FILE_KEY: &str = "[HS_ENV_FILE]:";
 
-const KEYS: &[&str] = &[HELP_KEY, ENV_KEY, ENV_HELP_KEY];
+/// 索引依序為 HS_HELP、HS_ENV、HS_ENV_HELP、HS_ENV_FILE
+const KEYS: &[&str] = &[HELP_KEY, ENV_KEY, ENV_HELP_KEY, ENV_FILE_KEY];
+pub const ENV_FILE_INDEX: usize = 3;
 
 pub struct Message {
     start: usize,
@@ -21,6 +24,11 @@ impl AsRef<str> for Message {
     }
 }
 impl_ser_and_display_by_as_ref!(Message);
+impl From<String> for Message {
+    fn from(s: String) -> Self {
+        Message { start: 0, s }
+    }
+}
 
 pub struct Iter<'a, I> {
     content_iter: I,
@@ -70,6 +78,18 @@ pub fn extract_env_from_content_help_aware(
     })
 }
 
+pub fn extract_env_file_from_content(
+    content: impl Iterator<Item = String>,
+) -> impl Iterator<Item = Message> {
+    extract_msg_from_content(content, KEYS).filter_map(|(i, s)| {
+        if i == ENV_FILE_INDEX {
+            Some(s)
+        } else {
+            None
+        }
+    })
+}
+
 pub fn extract_help_from_content<'a>(
     content: impl Iterator<Item = String>,
 ) -> impl Iterator<Item = Message> {
@@ -185,4 +205,24 @@ mod test {
         let helps = extract_help(content, true);
         assert_eq!(helps, vec!["help msg", "help msg2"])
     }
+    #[test]
+    fn test_extract_env_file() {
+        let content = "
+        [HS_ENV_FILE]: ./.env
+        [HS_ENV]: env1
+        [HS_ENV_FILE]:~/secret.env
+
+        掰
+        [HS_ENV_FILE]: this is useless
+        ";
+        let files: Vec<_> = extract_env_file_from_content(content.lines().map(str::to_string))
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(files, vec!["./.env", "~/secret.env"]);
+
+        let envs: Vec<_> = extract_env_from_content_help_aware(content.lines().map(str::to_string))
+            .map(|(x, y)| (x, y.to_string()))
+            .collect();
+        assert_eq!(envs, vec![(true, "env1".to_owned())]);
+    }
 }
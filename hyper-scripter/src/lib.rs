@@ -23,6 +23,7 @@ pub mod script_time;
 pub mod script_type;
 pub mod state;
 pub mod tag;
+pub mod template_var;
 pub mod util;
 
 pub use std::borrow::Cow;
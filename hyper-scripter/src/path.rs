@@ -10,8 +10,8 @@ use std::path::{Component, Path, PathBuf};
 pub const HS_REDIRECT: &str = ".hs_redirect";
 pub const HS_PRE_RUN: &str = ".hs_prerun";
 const PROCESS_LOCK: &str = ".hs_process_lock";
-const TEMPLATE: &str = ".hs_templates";
-const HBS_EXT: &str = ".hbs";
+pub(crate) const TEMPLATE: &str = ".hs_templates";
+pub(crate) const HBS_EXT: &str = ".hbs";
 
 macro_rules! hs_home_env {
     () => {
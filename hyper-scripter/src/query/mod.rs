@@ -1,6 +1,8 @@
 use crate::error::{
     Contextable, DisplayError, DisplayResult,
-    FormatCode::{Regex as RegexCode, ScriptQuery as ScriptQueryCode},
+    FormatCode::{
+        QueryPreset as QueryPresetCode, Regex as RegexCode, ScriptQuery as ScriptQueryCode,
+    },
     Result,
 };
 use crate::script::{ConcreteScriptName, IntoScriptName, ScriptName};
@@ -14,8 +16,10 @@ pub use util::*;
 mod range_query;
 pub use range_query::*;
 mod list_query_handler;
+mod preset;
 mod the_multifuzz_algo;
 pub use list_query_handler::*;
+pub use preset::*;
 
 #[derive(Debug, Eq, PartialEq, Display, Clone)]
 pub enum EditQuery<Q> {
@@ -109,11 +113,18 @@ pub enum ListQuery {
     Pattern(Regex, String, bool),
     #[display(fmt = "{}", _0)]
     Query(ScriptQuery),
+    #[display(fmt = "@@{}", _0)]
+    Preset(String),
 }
 impl FromStr for ListQuery {
     type Err = DisplayError;
     fn from_str(s: &str) -> DisplayResult<Self> {
-        if s.contains('*') {
+        if let Some(name) = s.strip_prefix("@@") {
+            if name.is_empty() {
+                return QueryPresetCode.to_display_res(s.to_owned());
+            }
+            Ok(ListQuery::Preset(name.to_owned()))
+        } else if s.contains('*') {
             // TODO: 好好檢查
             let s = s.to_owned();
             let re = s.replace(".", r"\.");
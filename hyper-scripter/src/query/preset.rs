@@ -0,0 +1,111 @@
+use super::ListQuery;
+use crate::args::AliasRoot;
+use crate::config::{Config, QueryPreset, Recent};
+use crate::error::{DisplayError, FormatCode::QueryPreset as QueryPresetCode, Result};
+use crate::script_repo::{RecentFilter, StableRepo};
+use crate::tag::TagSelectorGroup;
+use clap::Parser;
+use fxhash::FxHashSet as HashSet;
+
+/// 查詢預設中的根參數（`-s`、`-a`、`--timeless`、`--recent`）所帶來的篩選
+///
+/// 若預設裡沒有這些參數，則沿用當下倉庫的篩選
+#[derive(Debug)]
+pub struct PresetFilter {
+    select: Option<TagSelectorGroup>,
+    recent: Option<Recent>,
+}
+impl PresetFilter {
+    /// 是否和當下倉庫的篩選完全相同
+    pub fn is_inherited(&self) -> bool {
+        self.select.is_none() && self.recent.is_none()
+    }
+    /// 回傳所有通過篩選的腳本 id
+    pub fn passed_ids(&self, repo: &StableRepo) -> HashSet<i64> {
+        let conf = Config::get();
+        let recent_filter = match self.recent {
+            Some(recent) => RecentFilter {
+                recent,
+                archaeology: false,
+            },
+            None => repo.recent_filter(),
+        };
+        repo.iter_with_select_hidden()
+            .filter(|(info, select_hidden)| {
+                let selected = match &self.select {
+                    Some(group) => {
+                        let tags = conf.tag_registry.expand(&info.tags);
+                        group.select(&tags, &info.ty)
+                    }
+                    None => !select_hidden,
+                };
+                selected && !recent_filter.hides(info)
+            })
+            .map(|(info, _)| info.id)
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct ParsedPreset {
+    pub queries: Vec<ListQuery>,
+    pub filter: PresetFilter,
+}
+
+/// 解析查詢預設，格式同 `hs [根參數] [列表查詢]`，根參數必須放在查詢之前
+///
+/// 預設本身不可再包含其它預設
+pub fn parse_preset(name: &str, preset: &QueryPreset) -> Result<ParsedPreset> {
+    let args = std::iter::once("hs").chain(preset.args.iter().map(String::as_str));
+    let root = match AliasRoot::try_parse_from(args) {
+        Ok(root) => root,
+        Err(err) => {
+            log::error!("解析查詢預設 {} 失敗：{}", name, err);
+            return QueryPresetCode.to_res(preset.args.join(" "));
+        }
+    };
+    let root_args = root.root_args;
+    if root_args.archaeology || !root_args.toggle.is_empty() {
+        log::warn!(
+            "查詢預設 {} 中只有 -s、-a、--timeless、--recent 有作用",
+            name
+        );
+    }
+
+    let mut queries = vec![];
+    for arg in root.subcmd.iter() {
+        let query: ListQuery = arg.parse().map_err(DisplayError::into_err)?;
+        if matches!(query, ListQuery::Preset(..)) {
+            log::error!("查詢預設 {} 中不可再使用其它預設", name);
+            return QueryPresetCode.to_res(arg.clone());
+        }
+        queries.push(query);
+    }
+    if queries.is_empty() {
+        // NOTE: 只有根參數的預設，代表所有通過篩選的腳本
+        queries.push("*".parse().unwrap());
+    }
+
+    let mut select = root_args.select;
+    let mut recent = root_args.recent.map(Recent::Days);
+    if root_args.all {
+        select = vec!["all,^remove".parse().unwrap()];
+        recent = Some(Recent::Timeless);
+    } else if root_args.timeless {
+        recent = Some(Recent::Timeless);
+    }
+    let select = if select.is_empty() {
+        None
+    } else {
+        let mut group = Config::get().get_tag_selector_group(&mut Default::default());
+        for select in select.into_iter() {
+            group.push(select);
+        }
+        Some(group)
+    };
+
+    Ok(ParsedPreset {
+        queries,
+        filter: PresetFilter { select, recent },
+    })
+}
@@ -1,5 +1,5 @@
 use super::the_multifuzz_algo::{the_multifuzz_algo, MultiFuzzObj};
-use super::{ListQuery, ScriptQuery, ScriptQueryInner};
+use super::{parse_preset, ListQuery, ParsedPreset, ScriptQuery, ScriptQueryInner};
 use crate::color::Stylize;
 use crate::config::{Config, PromptLevel};
 use crate::error::{Error, Result};
@@ -36,43 +36,94 @@ pub(crate) async fn do_list_query_with_handler<'a, I, H: super::ListQueryHandler
     let mut is_empty = true;
     let mut mem = HashSet::<i64>::default();
     let mut ret = vec![];
-    let repo_ptr = repo as *mut StableRepo;
+    // NOTE: 先把查詢預設展開，若預設帶有根參數，則從所有腳本中篩出符合的
+    let mut expanded = vec![];
     for query in queries {
         is_empty = false;
-        macro_rules! insert {
-            ($script:ident) => {
-                if mem.contains(&$script.id) {
-                    continue;
-                }
-                mem.insert($script.id);
-                ret.push($script);
-            };
-        }
-        // SAFETY: `mem` 已保證回傳的陣列不可能包含相同的資料，且 `StableRepo` 保證了內容物不會位移
-        let repo = unsafe { &mut *repo_ptr };
-        let query = handler.handle_item(query);
-        match query {
+        match handler.handle_item(query) {
             None => (),
-            Some(ListQuery::Pattern(re, og, bang)) => {
-                let mut is_empty = true;
-                for script in repo.iter_mut(compute_vis(bang)) {
-                    if re.is_match(&script.name.key()) {
-                        is_empty = false;
-                        insert!(script);
+            Some(ListQuery::Preset(name)) => {
+                let conf = Config::get();
+                let preset = conf
+                    .query_presets
+                    .get(&name)
+                    .ok_or_else(|| Error::NoQueryPreset(name.clone()))?;
+                let ParsedPreset { queries, filter } = parse_preset(&name, preset)?;
+                log::info!("展開查詢預設 @@{} => {:?}", name, preset.args);
+                let passed = if filter.is_inherited() {
+                    None
+                } else {
+                    Some(filter.passed_ids(repo))
+                };
+                expanded.push((queries, passed));
+            }
+            Some(query) => expanded.push((vec![query], None)),
+        }
+    }
+    let repo_ptr = repo as *mut StableRepo;
+    for (queries, passed) in expanded.into_iter() {
+        for query in queries {
+            macro_rules! insert {
+                ($script:ident) => {
+                    if mem.contains(&$script.id) {
+                        continue;
+                    }
+                    mem.insert($script.id);
+                    ret.push($script);
+                };
+            }
+            // SAFETY: `mem` 已保證回傳的陣列不可能包含相同的資料，且 `StableRepo` 保證了內容物不會位移
+            let repo = unsafe { &mut *repo_ptr };
+            match query {
+                ListQuery::Preset(..) => unreachable!("查詢預設不可巢狀"),
+                ListQuery::Pattern(re, og, bang) => {
+                    let passed = passed.as_ref().filter(|_| !bang);
+                    let vis = compute_vis(bang || passed.is_some());
+                    let mut is_empty = true;
+                    for script in repo.iter_mut(vis) {
+                        if passed.is_some_and(|passed| !passed.contains(&script.id)) {
+                            continue;
+                        }
+                        if re.is_match(&script.name.key()) {
+                            is_empty = false;
+                            insert!(script);
+                        }
+                    }
+                    if is_empty {
+                        return Err(Error::ScriptNotFound(og.to_owned()));
                     }
                 }
-                if is_empty {
-                    return Err(Error::ScriptNotFound(og.to_owned()));
+                ListQuery::Query(mut query) => {
+                    let passed = passed.as_ref().filter(|_| !query.bang);
+                    if let (Some(passed), ScriptQueryInner::Fuzz(name)) = (passed, &query.inner) {
+                        // 篩選過的預設只在通過篩選的腳本中模糊搜尋
+                        let iter = repo
+                            .iter_mut(Visibility::All)
+                            .filter(|s| passed.contains(&s.id));
+                        match do_fuzz_query(name, iter, false).await {
+                            Ok(Some(script)) => {
+                                insert!(script);
+                            }
+                            Ok(None) => return Err(Error::ScriptNotFound(query.to_string())),
+                            Err(Error::DontFuzz) => continue,
+                            Err(e) => return Err(e),
+                        }
+                        continue;
+                    }
+                    if passed.is_some() {
+                        query.bang = true;
+                    }
+                    let script = match handler.handle_query(query, repo).await {
+                        Ok(Some(entry)) => entry,
+                        Ok(None) => continue,
+                        Err(e) => return Err(e),
+                    };
+                    if passed.is_some_and(|passed| !passed.contains(&script.id)) {
+                        return Err(Error::ScriptIsFiltered(script.name.key().to_string()));
+                    }
+                    insert!(script);
                 }
             }
-            Some(ListQuery::Query(query)) => {
-                let script = match handler.handle_query(query, repo).await {
-                    Ok(Some(entry)) => entry,
-                    Ok(None) => continue,
-                    Err(e) => return Err(e),
-                };
-                insert!(script);
-            }
         }
     }
     if is_empty && H::should_return_all_on_empty() {
@@ -116,59 +167,68 @@ pub async fn do_script_query<'b>(
         }
         ScriptQueryInner::Exact(name) => Ok(script_repo.get_mut(name, visibility)),
         ScriptQueryInner::Fuzz(name) => {
-            let level = if forbid_prompt {
-                PromptLevel::Never
-            } else {
-                Config::get_prompt_level()
-            };
-
             let iter = script_repo.iter_mut(visibility);
-            let fuzz_res = fuzzy::fuzz(name, iter, SEP).await?;
-            let mut is_multi_fuzz = false;
-            let res = match fuzz_res {
-                Some(fuzzy::FuzzResult::Single(res)) => res,
-                #[cfg(feature = "benching")]
-                Some(fuzzy::FuzzResult::Multi { ans, .. }) => {
-                    is_multi_fuzz = true;
-                    ans
-                }
-                #[cfg(not(feature = "benching"))]
-                Some(fuzzy::FuzzResult::Multi { ans, others, .. }) => {
-                    is_multi_fuzz = true;
-                    match handle_special_dot_anonymous(name, ans, others) {
-                        Either::One(res) => res,
-                        Either::Two((ans, others)) => {
-                            let (res, is_single_sink) = the_multifuzz_algo(ans, others);
-                            if is_single_sink {
-                                is_multi_fuzz = false;
-                            }
-                            res
-                        }
+            do_fuzz_query(name, iter, forbid_prompt).await
+        }
+    }
+}
+
+/// 在給定的腳本中模糊搜尋，必要時詢問使用者
+async fn do_fuzz_query<'b>(
+    name: &str,
+    iter: impl Iterator<Item = RepoEntry<'b>>,
+    forbid_prompt: bool,
+) -> Result<Option<RepoEntry<'b>>> {
+    let level = if forbid_prompt {
+        PromptLevel::Never
+    } else {
+        Config::get_prompt_level()
+    };
+
+    let fuzz_res = fuzzy::fuzz(name, iter, SEP).await?;
+    let mut is_multi_fuzz = false;
+    let res = match fuzz_res {
+        Some(fuzzy::FuzzResult::Single(res)) => res,
+        #[cfg(feature = "benching")]
+        Some(fuzzy::FuzzResult::Multi { ans, .. }) => {
+            is_multi_fuzz = true;
+            ans
+        }
+        #[cfg(not(feature = "benching"))]
+        Some(fuzzy::FuzzResult::Multi { ans, others, .. }) => {
+            is_multi_fuzz = true;
+            match handle_special_dot_anonymous(name, ans, others) {
+                Either::One(res) => res,
+                Either::Two((ans, others)) => {
+                    let (res, is_single_sink) = the_multifuzz_algo(ans, others);
+                    if is_single_sink {
+                        is_multi_fuzz = false;
                     }
-                }
-                None => return Ok(None),
-            };
-            let is_low = res.is_low;
-            let entry = res.obj;
-            let need_prompt = {
-                match level {
-                    PromptLevel::Always => true,
-                    PromptLevel::Never => false,
-                    PromptLevel::Smart => is_low || is_multi_fuzz,
-                    PromptLevel::OnMultiFuzz => is_multi_fuzz,
-                }
-            };
-            if need_prompt {
-                let ty = get_display_type(&entry.ty);
-                let msg = format!("{}({})?", entry.name, ty.display());
-                let yes = prompt(msg.stylize().color(ty.color()).bold(), true)?;
-                if !yes {
-                    return Err(Error::DontFuzz);
+                    res
                 }
             }
-            Ok(Some(entry))
+        }
+        None => return Ok(None),
+    };
+    let is_low = res.is_low;
+    let entry = res.obj;
+    let need_prompt = {
+        match level {
+            PromptLevel::Always => true,
+            PromptLevel::Never => false,
+            PromptLevel::Smart => is_low || is_multi_fuzz,
+            PromptLevel::OnMultiFuzz => is_multi_fuzz,
+        }
+    };
+    if need_prompt {
+        let ty = get_display_type(&entry.ty);
+        let msg = format!("{}({})?", entry.name, ty.display());
+        let yes = prompt(msg.stylize().color(ty.color()).bold(), true)?;
+        if !yes {
+            return Err(Error::DontFuzz);
         }
     }
+    Ok(Some(entry))
 }
 pub async fn do_script_query_strict<'b>(
     script_query: &ScriptQuery,
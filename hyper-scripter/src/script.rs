@@ -8,7 +8,7 @@ use crate::script_type::ScriptType;
 use crate::tag::{Tag, TagSelector};
 use crate::util::illegal_name;
 use chrono::NaiveDateTime;
-use fxhash::FxHashSet as HashSet;
+use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
 use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::fmt::Write;
@@ -75,7 +75,7 @@ impl ConcreteScriptName {
     }
 }
 
-pub struct ScriptPath<'a>(PathBuf, bool, Option<&'a str>);
+pub struct ScriptPath<'a>(PathBuf, bool, Option<Cow<'a, str>>);
 impl ScriptPath<'_> {
     fn get_cur_p(ext: Option<&str>) -> String {
         if let Some(ext) = ext {
@@ -88,7 +88,7 @@ impl ScriptPath<'_> {
         let mut abs_p = home.join(self.0);
         if self.1 {
             if abs_p.is_dir() {
-                let new_p = abs_p.join(Self::get_cur_p(self.2));
+                let new_p = abs_p.join(Self::get_cur_p(self.2.as_deref()));
                 log::info!("{abs_p:?} 為資料夾，為避免衝突，改用 {new_p:?}");
                 abs_p = new_p;
             }
@@ -100,7 +100,7 @@ impl ScriptPath<'_> {
         let abs_p = home.join(&p);
         if self.1 {
             if abs_p.is_dir() {
-                let new_p = p.join(Self::get_cur_p(self.2));
+                let new_p = p.join(Self::get_cur_p(self.2.as_deref()));
                 log::info!("{abs_p:?} 為資料夾，為避免衝突，改用 {new_p:?}");
                 p = new_p;
             }
@@ -201,7 +201,7 @@ impl ScriptName {
             ty: &'a ScriptType,
             fallback: bool,
             err: &mut Option<Error>,
-        ) -> Result<Option<&'a str>> {
+        ) -> Result<Option<Cow<'a, str>>> {
             let ext = match Config::get().get_script_conf(ty) {
                 Err(e) => {
                     if !fallback {
@@ -212,11 +212,12 @@ impl ScriptName {
                         e,
                     );
                     *err = Some(e);
-                    Some(ty.as_ref())
+                    Some(Cow::Borrowed(ty.as_ref()))
                 }
-                Ok(c) => c.get_ext(),
+                Ok(Cow::Borrowed(c)) => c.get_ext().map(Cow::Borrowed),
+                Ok(Cow::Owned(c)) => c.ext.map(Cow::Owned),
             };
-            if let Some(ext) = ext {
+            if let Some(ext) = &ext {
                 write!(name, ".{}", ext).unwrap();
             }
             Ok(ext)
@@ -270,6 +271,8 @@ pub struct TimelessScriptInfo {
     pub hash: i64,
     pub name: ScriptName,
     pub tags: HashSet<Tag>,
+    /// 暫時性標籤的過期時間
+    pub tag_expiry: HashMap<Tag, NaiveDateTime>,
     pub ty: ScriptType,
     pub created_time: ScriptTime,
 }
@@ -279,11 +282,15 @@ pub enum ExecPhase {
     Dummy {
         args: String,
         envs: String,
+        env_files: Option<String>,
+        profile: Option<String>,
         dir: Option<PathBuf>,
     },
     Pre {
         args: String,
         envs: String,
+        env_files: Option<String>,
+        profile: Option<String>,
         dir: Option<PathBuf>,
     },
     Normal(i64),
@@ -321,6 +328,7 @@ impl ScriptInfo {
         self.timeless_info.id = id;
     }
     pub fn append_tags(&mut self, tags: TagSelector) {
+        tags.fill_expiry_map(&mut self.tag_expiry);
         if tags.append {
             log::debug!("附加上標籤：{:?}", tags);
             tags.fill_allowed_map(&mut self.tags);
@@ -328,15 +336,20 @@ impl ScriptInfo {
             log::debug!("設定標籤：{:?}", tags);
             self.tags = tags.into_allowed_iter().collect();
         }
+        let tags = &self.timeless_info.tags;
+        self.timeless_info
+            .tag_expiry
+            .retain(|t, _| tags.contains(t));
     }
     pub fn cp(&self, new_name: ScriptName) -> Self {
-        let builder = ScriptInfo::builder(
+        let mut builder = ScriptInfo::builder(
             0,
             self.hash,
             new_name,
             self.ty.clone(),
             self.tags.iter().cloned(),
         );
+        builder.tag_expiry(self.tag_expiry.clone());
         builder.build()
     }
     /// `major time` 即不包含 `read` 事件的時間，但包含 `humble`
@@ -368,12 +381,32 @@ impl ScriptInfo {
         self.read_time = now.clone();
         self.write_time = now;
     }
-    pub fn exec(&mut self, args: &[String], envs: String, dir: Option<PathBuf>, dummy: bool) {
+    pub fn exec(
+        &mut self,
+        args: &[String],
+        envs: String,
+        env_files: Option<String>,
+        profile: Option<String>,
+        dir: Option<PathBuf>,
+        dummy: bool,
+    ) {
         let args = serde_json::to_string(args).unwrap();
         let data = if dummy {
-            ExecPhase::Dummy { args, envs, dir }
+            ExecPhase::Dummy {
+                args,
+                envs,
+                env_files,
+                profile,
+                dir,
+            }
         } else {
-            ExecPhase::Pre { args, envs, dir }
+            ExecPhase::Pre {
+                args,
+                envs,
+                env_files,
+                profile,
+                dir,
+            }
         };
         self.exec_time = Some(ScriptTime::now(data));
         // NOTE: no readtime, otherwise it will be hard to tell what event was caused by what operation.
@@ -405,6 +438,7 @@ impl ScriptInfo {
             name,
             ty,
             tags: tags.collect(),
+            tag_expiry: Default::default(),
             read_time: None,
             created_time: None,
             exec_time: None,
@@ -475,10 +509,15 @@ pub struct ScriptBuilder {
     hash: i64,
     id: i64,
     tags: HashSet<Tag>,
+    tag_expiry: HashMap<Tag, NaiveDateTime>,
     ty: ScriptType,
 }
 
 impl ScriptBuilder {
+    pub fn tag_expiry(&mut self, expiry: HashMap<Tag, NaiveDateTime>) -> &mut Self {
+        self.tag_expiry = expiry;
+        self
+    }
     pub fn exec_count(&mut self, count: u64) -> &mut Self {
         self.exec_count = count;
         self
@@ -528,6 +567,7 @@ impl ScriptBuilder {
                 name: self.name,
                 ty: self.ty,
                 tags: self.tags,
+                tag_expiry: self.tag_expiry,
                 created_time,
             },
         }
@@ -2,14 +2,17 @@ use crate::config::Recent;
 use crate::error::Result;
 use crate::script::{ExecPhase, IntoScriptName, ScriptInfo, ScriptName};
 use crate::script_type::ScriptType;
-use crate::tag::{Tag, TagSelectorGroup};
+use crate::tag::{Tag, TagRegistry, TagSelectorGroup, TagSet};
 use chrono::{Duration, NaiveDateTime, Utc};
 use fxhash::FxHashMap as HashMap;
 use hyper_scripter_historian::{Event, EventData, Historian, LastTimeRecord};
-use sqlx::SqlitePool;
+use sqlx::{SqliteConnection, SqlitePool};
 use std::collections::hash_map::Entry::{self, *};
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
+/// `hs rm` 以此標籤隱藏腳本
+pub const REMOVE_TAG: &str = "remove";
+
 pub mod helper;
 pub use helper::RepoEntry;
 
@@ -27,6 +30,12 @@ impl Display for RecentFilter {
         Ok(())
     }
 }
+impl RecentFilter {
+    /// 腳本是否會被這個時間篩選器藏起來
+    pub fn hides(&self, info: &ScriptInfo) -> bool {
+        self.archaeology ^ TimeBound::new(self.recent).is_overtime(info)
+    }
+}
 
 enum TimeBound {
     Timeless,
@@ -44,6 +53,20 @@ impl TimeBound {
             }
         }
     }
+    fn is_overtime(&self, info: &ScriptInfo) -> bool {
+        match self {
+            TimeBound::Timeless => false,
+            TimeBound::Bound(time_bound) => {
+                let neglect = info.neglect_time.as_ref().map(|t| **t);
+                let time_bound = std::cmp::max(*time_bound, neglect);
+                if let Some(time_bound) = time_bound {
+                    time_bound > info.last_major_time()
+                } else {
+                    false
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -83,12 +106,23 @@ enum TraceOption {
     Humble,
 }
 
+/// 外層執行的腳本會帶著 `HS_HOME` 及 `HS_RUN_ID` 環境變數，只認同一個家目錄下的執行
+fn parent_run() -> Option<i64> {
+    let home = std::env::var_os("HS_HOME")?;
+    if std::path::Path::new(&home) != crate::path::get_home() {
+        return None;
+    }
+    std::env::var("HS_RUN_ID").ok()?.parse().ok()
+}
+
 #[derive(Debug)]
 pub struct DBEnv {
     info_pool: SqlitePool,
     pub historian: Historian,
     trace_opt: TraceOption,
     modifies_script: bool,
+    /// 在另一次執行的腳本中被呼叫時，外層的執行事件 id
+    parent_run: Option<i64>,
 }
 
 pub struct RepoEntryOptional<'b> {
@@ -123,6 +157,7 @@ impl DBEnv {
             historian,
             modifies_script,
             trace_opt: TraceOption::Normal,
+            parent_run: parent_run(),
         }
     }
     pub async fn handle_neglect(&self, id: i64) -> Result {
@@ -138,6 +173,15 @@ impl DBEnv {
         )
         .execute(&self.info_pool)
         .await?;
+        if !matches!(self.trace_opt, TraceOption::NoTrace) {
+            let event = Event {
+                script_id: id,
+                humble: false,
+                time,
+                data: EventData::Neglect,
+            };
+            self.historian.record(&event).await?;
+        }
         Ok(())
     }
 
@@ -202,9 +246,67 @@ impl DBEnv {
         Ok(())
     }
 
-    async fn handle_delete(&self, id: i64) -> Result {
+    /// 記錄改名、複製等結構性事件，這類事件不影響最新時間
+    pub async fn record_structural(&self, id: i64, data: EventData<'_>) -> Result {
+        if matches!(self.trace_opt, TraceOption::NoTrace) {
+            return Ok(());
+        }
+        let event = Event {
+            script_id: id,
+            humble: false,
+            time: Utc::now().naive_utc(),
+            data,
+        };
+        self.historian.record(&event).await?;
+        Ok(())
+    }
+    /// 依新舊標籤記錄改標籤事件，其中加上或拿掉 `remove` 標籤分別視為刪除及還原
+    pub async fn handle_retag(&self, id: i64, name: &str, old: &TagSet, new: &TagSet) -> Result {
+        let remove_tag = Tag::new_unchecked(REMOVE_TAG.to_owned());
+        let mut diff: Vec<_> = new
+            .difference(old)
+            .filter(|t| **t != remove_tag)
+            .map(|t| format!("+{}", t.as_ref()))
+            .collect();
+        let mut removed: Vec<_> = old
+            .difference(new)
+            .filter(|t| **t != remove_tag)
+            .map(|t| format!("-{}", t.as_ref()))
+            .collect();
+        diff.sort();
+        removed.sort();
+        diff.extend(removed);
+        if !diff.is_empty() {
+            let diff = diff.join(" ");
+            self.record_structural(id, EventData::Retag { diff: &diff })
+                .await?;
+        }
+        match (old.contains(&remove_tag), new.contains(&remove_tag)) {
+            (false, true) => self.record_structural(id, EventData::Delete { name }).await,
+            (true, false) => self.record_structural(id, EventData::Restore).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// 有 `tombstone_name` 時保留歷史，將其移到墓碑 id 底下，否則連同歷史一起刪除
+    async fn handle_delete(&self, id: i64, tombstone_name: Option<&str>) -> Result {
         assert!(self.modifies_script);
-        self.historian.remove(id).await?;
+        if let Some(name) = tombstone_name {
+            let event = Event {
+                script_id: id,
+                humble: false,
+                time: Utc::now().naive_utc(),
+                data: EventData::Delete { name },
+            };
+            self.historian.record(&event).await?;
+            let tombstone_id = self.historian.keep_as_tombstone(id).await?;
+            log::debug!("腳本 {:?} 的歷史移至墓碑 {}", id, tombstone_id);
+        } else {
+            self.historian.remove(id).await?;
+        }
+        sqlx::query!("DELETE FROM tag_expiry WHERE script_id = ?", id)
+            .execute(&self.info_pool)
+            .await?;
         log::debug!("清理腳本 {:?} 的最新事件", id);
         sqlx::query!("DELETE FROM last_events WHERE script_id = ?", id)
             .execute(&self.info_pool)
@@ -234,9 +336,27 @@ impl DBEnv {
         )
         .fetch_one(&self.info_pool)
         .await?;
+        if !info.tag_expiry.is_empty() {
+            let mut conn = self.info_pool.acquire().await?;
+            write_tag_expiry(&mut conn, res.id, &info.tag_expiry).await?;
+        }
         Ok(res.id)
     }
 
+    async fn handle_tags_change(&self, changes: &[(i64, &TagSet, &TagExpiry)]) -> Result {
+        assert!(self.modifies_script);
+        let mut tx = self.info_pool.begin().await?;
+        for (id, tags, expiry) in changes.iter() {
+            let tags = join_tags(tags.iter());
+            sqlx::query!("UPDATE script_infos SET tags = ? where id = ?", tags, id)
+                .execute(&mut *tx)
+                .await?;
+            write_tag_expiry(&mut tx, *id, expiry).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
     async fn handle_change(&self, info: &mut ScriptInfo) -> Result<i64> {
         log::debug!("開始修改資料庫 {:?}", info);
         if info.changed {
@@ -255,6 +375,8 @@ impl DBEnv {
             )
             .execute(&self.info_pool)
             .await?;
+            let mut conn = self.info_pool.acquire().await?;
+            write_tag_expiry(&mut conn, info.id, &info.tag_expiry).await?;
         }
 
         if matches!(self.trace_opt, TraceOption::NoTrace) {
@@ -296,25 +418,43 @@ impl DBEnv {
             if let Some(phase) = time.mark_updated() {
                 log::debug!("{:?} 的執行事件", info.timeless_info.name);
                 match &phase {
-                    ExecPhase::Pre { args, envs, dir } => {
+                    ExecPhase::Pre {
+                        args,
+                        envs,
+                        env_files,
+                        profile,
+                        dir,
+                    } => {
                         last_event_id = record_event!(
                             **time,
                             EventData::PreExec {
                                 args,
                                 envs,
+                                env_files: env_files.as_deref(),
+                                profile: profile.as_deref(),
                                 dir: dir.as_deref(),
+                                parent: self.parent_run,
                             }
                         )
                         .await?;
                         return Ok(last_event_id); // XXX: 超級醜的作法，為了避免重復記錄其它的事件
                     }
-                    ExecPhase::Dummy { args, envs, dir } => {
+                    ExecPhase::Dummy {
+                        args,
+                        envs,
+                        env_files,
+                        profile,
+                        dir,
+                    } => {
                         last_event_id = record_event!(
                             **time,
                             EventData::Exec {
                                 args,
                                 envs,
+                                env_files: env_files.as_deref(),
+                                profile: profile.as_deref(),
                                 dir: dir.as_deref(),
+                                parent: self.parent_run,
                             }
                         )
                         .await?;
@@ -346,6 +486,26 @@ impl DBEnv {
     }
 }
 
+type TagExpiry = HashMap<Tag, NaiveDateTime>;
+
+async fn write_tag_expiry(conn: &mut SqliteConnection, id: i64, expiry: &TagExpiry) -> Result {
+    sqlx::query!("DELETE FROM tag_expiry WHERE script_id = ?", id)
+        .execute(&mut *conn)
+        .await?;
+    for (tag, time) in expiry.iter() {
+        let tag = tag.as_ref();
+        sqlx::query!(
+            "INSERT INTO tag_expiry (script_id, tag, expire_time) VALUES(?, ?, ?)",
+            id,
+            tag,
+            time
+        )
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
 fn join_tags<'a, I: Iterator<Item = &'a Tag>>(tags: I) -> String {
     let tags_arr: Vec<&str> = tags.map(|t| t.as_ref()).collect();
     tags_arr.join(",")
@@ -427,6 +587,17 @@ impl StableRepo {
         let env = &self.db_env;
         info.map(move |info| RepoEntry::new(info, env))
     }
+    /// 走訪所有腳本，並附上該腳本是否被選擇器篩掉
+    pub fn iter_with_select_hidden(&self) -> impl Iterator<Item = (&ScriptInfo, bool)> {
+        let shown = self.map.values().chain(self.time_hidden_map.values());
+        let hidden = self.select_hidden_map.values();
+        shown
+            .map(|info| (info, false))
+            .chain(hidden.map(|info| (info, true)))
+    }
+    pub fn recent_filter(&self) -> RecentFilter {
+        self.recent_filter
+    }
 }
 
 /// A repo without insert & delete
@@ -442,13 +613,25 @@ impl ScriptRepo {
     pub fn iter_mut(&mut self, visibility: Visibility) -> impl Iterator<Item = RepoEntry<'_>> {
         self.0.iter_mut(visibility)
     }
+    /// 走訪所有腳本，並附上該腳本是否被選擇器篩掉
+    pub fn iter_with_select_hidden(&self) -> impl Iterator<Item = (&ScriptInfo, bool)> {
+        self.0.iter_with_select_hidden()
+    }
     pub fn historian(&self) -> &Historian {
         &self.0.db_env.historian
     }
+    /// 從資料庫重新撈出所有腳本的 id 及名字，可看到其它行程新增或改名的腳本
+    pub async fn fetch_names(&self) -> Result<Vec<(i64, String)>> {
+        let res = sqlx::query!("SELECT id, name FROM script_infos")
+            .fetch_all(&self.0.db_env.info_pool)
+            .await?;
+        Ok(res.into_iter().map(|r| (r.id, r.name)).collect())
+    }
     pub async fn new(
         recent: RecentFilter,
         db_env: DBEnv,
         selector: &TagSelectorGroup,
+        registry: &TagRegistry,
     ) -> Result<ScriptRepo> {
         let mut select_hidden_map = HashMap::<String, ScriptInfo>::default();
         let mut time_hidden_map = HashMap::<String, ScriptInfo>::default();
@@ -460,11 +643,39 @@ impl ScriptRepo {
         )
         .fetch_all(&db_env.info_pool)
         .await?;
+
+        let now = Utc::now().naive_utc();
+        let mut expiry_map = HashMap::<i64, TagExpiry>::default();
+        let expiry_records = sqlx::query!("SELECT * FROM tag_expiry")
+            .fetch_all(&db_env.info_pool)
+            .await?;
+        for record in expiry_records.into_iter() {
+            let tag = Tag::new_unchecked(record.tag);
+            let expiry = expiry_map.entry(record.script_id).or_default();
+            expiry.insert(tag, record.expire_time);
+        }
+        let mut expired_ids = vec![];
+
         for record in scripts.into_iter() {
             let name = record.name;
             log::trace!("載入腳本：{} {} {}", name, record.ty, record.tags);
             let script_name = name.clone().into_script_name_unchecked()?; // NOTE: 從資料庫撈出來就別檢查了吧
 
+            let mut expiry = expiry_map.remove(&record.id).unwrap_or_default();
+            let mut expired = vec![];
+            expiry.retain(|tag, time| {
+                if *time <= now {
+                    log::info!("腳本 {} 的暫時標籤 {} 已過期", name, tag);
+                    expired.push(tag.clone());
+                    false
+                } else {
+                    true
+                }
+            });
+            if !expired.is_empty() {
+                expired_ids.push(record.id);
+            }
+
             let mut builder = ScriptInfo::builder(
                 record.id,
                 record.hash,
@@ -474,10 +685,16 @@ impl ScriptRepo {
                     if s.is_empty() {
                         None
                     } else {
-                        Some(Tag::new_unchecked(s.to_string()))
+                        let tag = Tag::new_unchecked(s.to_string());
+                        if expired.contains(&tag) {
+                            None
+                        } else {
+                            Some(tag)
+                        }
                     }
                 }),
             );
+            builder.tag_expiry(expiry);
 
             builder.created_time(record.created_time);
             builder.exec_count(record.exec_count.unwrap_or_default() as u64);
@@ -507,7 +724,7 @@ impl ScriptRepo {
             }
 
             let mut hide: Option<HideReason> = None;
-            if !selector.select(&script.tags, &script.ty) {
+            if !selector.select(&registry.expand(&script.tags), &script.ty) {
                 hide = Some(HideReason::Select);
             }
             if hide.is_none() {
@@ -515,18 +732,7 @@ impl ScriptRepo {
                     log::debug!("腳本 {} 曾於 {} 被忽略", script.name, neglect);
                 }
 
-                let overtime = match time_bound {
-                    TimeBound::Timeless => false,
-                    TimeBound::Bound(time_bound) => {
-                        let time_bound = std::cmp::max(time_bound, record.neglect);
-                        if let Some(time_bound) = time_bound {
-                            time_bound > script.last_major_time()
-                        } else {
-                            false
-                        }
-                    }
-                };
-                if recent.archaeology ^ overtime {
+                if recent.archaeology ^ time_bound.is_overtime(&script) {
                     hide = Some(HideReason::Time);
                 }
             }
@@ -540,6 +746,21 @@ impl ScriptRepo {
             };
         }
 
+        if !expired_ids.is_empty() {
+            if db_env.modifies_script {
+                let changes: Vec<_> = map
+                    .values()
+                    .chain(select_hidden_map.values())
+                    .chain(time_hidden_map.values())
+                    .filter(|info| expired_ids.contains(&info.id))
+                    .map(|info| (info.id, &info.tags, &info.tag_expiry))
+                    .collect();
+                db_env.handle_tags_change(&changes).await?;
+            } else {
+                log::debug!("不修改腳本，過期的標籤留待之後再從資料庫清除");
+            }
+        }
+
         Ok(ScriptRepo(StableRepo {
             map,
             select_hidden_map,
@@ -552,7 +773,7 @@ impl ScriptRepo {
         &mut self.0.time_hidden_map
     }
     pub fn recent_filter(&self) -> RecentFilter {
-        self.0.recent_filter
+        self.0.recent_filter()
     }
     pub fn no_trace(&mut self) {
         self.0.db_env.trace_opt = TraceOption::NoTrace;
@@ -571,10 +792,45 @@ impl ScriptRepo {
         self.0.iter_mut(Visibility::All).find(|e| e.id == id)
     }
 
+    /// 在同一個交易中修改多支腳本的標籤，任一筆失敗則全部不生效
+    pub async fn update_tags(&mut self, changes: Vec<(i64, TagSet)>) -> Result {
+        log::debug!("批次修改 {} 支腳本的標籤", changes.len());
+        let mut changes: HashMap<_, _> = changes.into_iter().collect();
+        let repo = &mut self.0;
+        let mut infos = vec![];
+        for info in iter_by_vis!(repo, Visibility::All) {
+            if let Some(tags) = changes.remove(&info.id) {
+                let mut expiry = info.tag_expiry.clone();
+                expiry.retain(|t, _| tags.contains(t));
+                infos.push((info, tags, expiry));
+            }
+        }
+        let db_changes: Vec<_> = infos
+            .iter()
+            .map(|(info, tags, expiry)| (info.id, tags, expiry))
+            .collect();
+        repo.db_env.handle_tags_change(&db_changes).await?;
+        for (info, tags, expiry) in infos.into_iter() {
+            let name = info.name.key();
+            repo.db_env
+                .handle_retag(info.id, &name, &info.tags, &tags)
+                .await?;
+            info.timeless_info.tags = tags;
+            info.timeless_info.tag_expiry = expiry;
+        }
+        Ok(())
+    }
+
     pub async fn remove(&mut self, id: i64) -> Result {
         // TODO: 從 map 中刪掉？但如果之後沒其它用途似乎也未必需要...
         log::debug!("從資料庫刪除腳本 {:?}", id);
-        self.0.db_env.handle_delete(id).await?;
+        self.0.db_env.handle_delete(id, None).await?;
+        Ok(())
+    }
+    /// 刪除腳本，但其歷史以墓碑的形式保留下來
+    pub async fn remove_keeping_history(&mut self, id: i64, name: &str) -> Result {
+        log::debug!("從資料庫刪除腳本 {:?}，保留其歷史", id);
+        self.0.db_env.handle_delete(id, Some(name)).await?;
         Ok(())
     }
     pub fn entry(&mut self, name: &ScriptName) -> RepoEntryOptional<'_> {
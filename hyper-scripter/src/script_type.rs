@@ -219,8 +219,9 @@ pub struct ScriptFullType {
 }
 impl FromStr for ScriptFullType {
     type Err = DisplayError;
+    /// 子模版可寫成 `sh/deploy` 或 `sh:deploy`
     fn from_str(s: &str) -> DisplayResult<Self> {
-        if let Some((first, second)) = s.split_once("/") {
+        if let Some((first, second)) = s.split_once('/').or_else(|| s.split_once(':')) {
             Ok(ScriptFullType {
                 ty: first.parse()?,
                 sub: Some(second.parse()?),
@@ -277,15 +278,61 @@ pub struct ScriptTypeConfig {
     pub exec_info: Option<ExecInfo>, // If this is None, it's shebang
     pub ext: Option<String>,
 
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub color: String,
+    /// 未設定的欄位會沿用父類型的設定
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<ScriptType>,
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     env: HashMap<String, String>,
 }
 
+impl ExecInfo {
+    pub fn new(cmd: String, args: Vec<String>) -> Self {
+        ExecInfo { cmd, args }
+    }
+}
+
 impl ScriptTypeConfig {
+    pub fn new(exec_info: Option<ExecInfo>, ext: Option<String>, color: String) -> Self {
+        ScriptTypeConfig {
+            exec_info,
+            ext,
+            color,
+            extends: None,
+            env: Default::default(),
+        }
+    }
+    /// 把自身沒設定的欄位用父類型補上，環境變數以自身為優先
+    pub fn inherit(&mut self, parent: &ScriptTypeConfig) {
+        if self.exec_info.is_none() {
+            self.exec_info = parent.exec_info.clone();
+        }
+        if self.ext.is_none() {
+            self.ext = parent.ext.clone();
+        }
+        if self.color.is_empty() {
+            self.color = parent.color.clone();
+        }
+        for (name, e) in parent.env.iter() {
+            if !self.env.contains_key(name) {
+                self.env.insert(name.clone(), e.clone());
+            }
+        }
+    }
     pub fn get_ext(&self) -> Option<&str> {
         self.ext.as_ref().map(|s| s.as_ref())
     }
+    /// 列出所有會以 Handlebars 渲染的字串，並附上其所在欄位
+    pub fn iter_templates(&self) -> impl Iterator<Item = (String, &str)> {
+        let args = self.exec_info.iter().flat_map(|info| info.args.iter());
+        let args = args.map(|arg| ("args".to_owned(), arg.as_str()));
+        let env = self
+            .env
+            .iter()
+            .map(|(name, e)| (format!("env.{}", name), e.as_str()));
+        args.chain(env)
+    }
     // XXX: extract
     pub fn gen_env(&self, info: &crate::util::TmplVal<'_>) -> Result<Vec<(String, String)>> {
         let reg = Handlebars::new();
@@ -348,12 +395,14 @@ create_default_types! {
         }),
         ext: Some("sh".to_owned()),
         color: "bright magenta".to_owned(),
+        extends: None,
         env: Default::default()
     }, []),
     ("tmux", TMUX_WELCOME_MSG, ScriptTypeConfig {
         exec_info: None,
         ext: None,
         color: "white".to_owned(),
+        extends: None,
         env: Default::default(),
     }, []),
     ("js", JS_WELCOME_MSG, ScriptTypeConfig {
@@ -363,6 +412,7 @@ create_default_types! {
         }),
         ext: Some("js".to_owned()),
         color: "bright cyan".to_owned(),
+        extends: None,
         env: gen_map(&[(
             "NODE_PATH",
             "{{{home}}}/node_modules",
@@ -375,6 +425,7 @@ create_default_types! {
         }),
         ext: Some("js".to_owned()),
         color: "bright cyan".to_owned(),
+        extends: None,
         env: gen_map(&[(
             "NODE_PATH",
             "{{{home}}}/node_modules",
@@ -387,6 +438,7 @@ create_default_types! {
         }),
         ext: Some("rb".to_owned()),
         color: "bright red".to_owned(),
+        extends: None,
         env: Default::default(),
     }, ["traverse": RB_TRAVERSE_WELCOME_MSG, "cd": RB_CD_WELCOME_MSG]),
     ("txt", DEFAULT_WELCOME_MSG, ScriptTypeConfig {
@@ -396,6 +448,7 @@ create_default_types! {
         }),
         ext: None,
         color: "bright black".to_owned(),
+        extends: None,
         env: Default::default(),
     }, [])
 }
@@ -2,12 +2,104 @@ use crate::error::{DisplayError, DisplayResult, FormatCode::Tag as TagCode};
 use crate::script_type::ScriptType;
 use crate::util::illegal_name;
 use crate::util::{impl_de_by_from_str, impl_ser_by_to_string};
-use fxhash::FxHashSet as HashSet;
+use chrono::{Duration, NaiveDateTime, Utc};
+use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use serde::{Deserialize, Serialize};
+use std::borrow::{Borrow, Cow};
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::str::FromStr;
 
 pub type TagSet = HashSet<Tag>;
 
+const TAG_SEP: char = '/';
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone, Default)]
+pub struct TagInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub implies: Vec<Tag>,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone, Default)]
+#[serde(transparent)]
+pub struct TagRegistry(HashMap<Tag, TagInfo>);
+impl TagRegistry {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn get(&self, tag: &Tag) -> Option<&TagInfo> {
+        self.0.get(tag)
+    }
+    pub fn insert(&mut self, tag: Tag, info: TagInfo) -> Option<TagInfo> {
+        self.0.insert(tag, info)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&Tag, &TagInfo)> {
+        self.0.iter()
+    }
+    pub fn rename_tag(&mut self, old: &Tag, new: &Tag) -> bool {
+        let mut changed = false;
+        let map = std::mem::take(&mut self.0);
+        for (mut tag, mut info) in map.into_iter() {
+            if let Some(renamed) = tag.renamed(old, new) {
+                tag = renamed;
+                changed = true;
+            }
+            for implied in info.implies.iter_mut() {
+                if let Some(renamed) = implied.renamed(old, new) {
+                    *implied = renamed;
+                    changed = true;
+                }
+            }
+            self.0.insert(tag, info);
+        }
+        changed
+    }
+    /// 找出最接近的登記資訊，例如 `team/infra/db` 沒有登記時，會退而求其次找 `team/infra`
+    pub fn lookup(&self, tag: &Tag) -> Option<&TagInfo> {
+        tag.ancestors().find_map(|t| self.0.get(t))
+    }
+    /// 將標籤集合加上所有被隱含的標籤（包含祖先標籤的隱含），若無任何新增則不複製
+    ///
+    /// ```
+    /// use hyper_scripter::tag::*;
+    /// let mut registry = TagRegistry::default();
+    /// let info = TagInfo {
+    ///     implies: vec!["caution".parse().unwrap()],
+    ///     ..Default::default()
+    /// };
+    /// registry.insert("prod".parse().unwrap(), info);
+    /// let tags: TagSet = ["prod/db".parse().unwrap()].into_iter().collect();
+    /// let expanded = registry.expand(&tags);
+    /// assert!(expanded.contains(&"caution".parse::<Tag>().unwrap()));
+    /// assert_eq!(expanded.len(), 2);
+    /// ```
+    pub fn expand<'a>(&self, tags: &'a TagSet) -> Cow<'a, TagSet> {
+        if self.0.is_empty() {
+            return Cow::Borrowed(tags);
+        }
+        let mut ret: Option<TagSet> = None;
+        let mut stack: Vec<&Tag> = tags.iter().collect();
+        while let Some(tag) = stack.pop() {
+            for info in tag.ancestors().filter_map(|t| self.0.get(t)) {
+                for implied in info.implies.iter() {
+                    let set = ret.get_or_insert_with(|| tags.clone());
+                    if set.insert(implied.clone()) {
+                        log::trace!("標籤 {} 隱含 {}", tag, implied);
+                        stack.push(implied);
+                    }
+                }
+            }
+        }
+        match ret {
+            Some(set) => Cow::Owned(set),
+            None => Cow::Borrowed(tags),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Default)]
 pub struct TagSelectorGroup(Vec<TagSelector>);
 impl TagSelectorGroup {
@@ -55,6 +147,46 @@ pub struct TagControl {
     allow: bool,
     mandatory: bool,
     tag: TagOrType,
+    expire: Option<TagTtl>,
+}
+
+/// 暫時性標籤的存活時間，如 `wip@7d` 中的 `7d`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TagTtl {
+    amount: u32,
+    unit: char,
+}
+impl TagTtl {
+    pub fn duration(&self) -> Duration {
+        let amount = self.amount.into();
+        match self.unit {
+            'm' => Duration::minutes(amount),
+            'h' => Duration::hours(amount),
+            'd' => Duration::days(amount),
+            'w' => Duration::weeks(amount),
+            _ => unreachable!(),
+        }
+    }
+}
+impl FromStr for TagTtl {
+    type Err = DisplayError;
+    fn from_str(s: &str) -> DisplayResult<Self> {
+        let unit = s.chars().last().unwrap_or_default();
+        if !matches!(unit, 'm' | 'h' | 'd' | 'w') {
+            log::error!("標籤存活時間格式不符：{}", s);
+            return TagCode.to_display_res(s.to_owned());
+        }
+        let amount = s[..s.len() - 1].parse().map_err(|_| {
+            log::error!("標籤存活時間格式不符：{}", s);
+            TagCode.to_err(s.to_owned())
+        })?;
+        Ok(TagTtl { amount, unit })
+    }
+}
+impl Display for TagTtl {
+    fn fmt(&self, w: &mut Formatter<'_>) -> FmtResult {
+        write!(w, "{}{}", self.amount, self.unit)
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Display)]
@@ -79,6 +211,13 @@ impl FromStr for TagOrType {
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Display)]
 pub struct Tag(String);
+impl_de_by_from_str!(Tag);
+impl_ser_by_to_string!(Tag);
+impl Borrow<str> for Tag {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
 impl AsRef<str> for Tag {
     fn as_ref(&self) -> &str {
         &self.0
@@ -92,11 +231,52 @@ impl Tag {
     pub fn new_unchecked(s: String) -> Self {
         Tag(s)
     }
+    /// 判斷自身是否為 `parent` 或其子孫標籤
+    ///
+    /// ```
+    /// use hyper_scripter::tag::Tag;
+    /// let t: Tag = "team/infra/db".parse().unwrap();
+    /// assert!(t.is_under(&"team/infra".parse().unwrap()));
+    /// assert!(t.is_under(&"team/infra/db".parse().unwrap()));
+    /// assert!(!t.is_under(&"team/inf".parse().unwrap()));
+    /// ```
+    pub fn is_under(&self, parent: &Tag) -> bool {
+        match self.0.strip_prefix(parent.0.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with(TAG_SEP),
+            None => false,
+        }
+    }
+    /// 若自身為 `old` 或其子孫標籤，回傳將 `old` 前綴換成 `new` 後的標籤
+    ///
+    /// ```
+    /// use hyper_scripter::tag::Tag;
+    /// let t: Tag = "team/infra/db".parse().unwrap();
+    /// let old: Tag = "team/infra".parse().unwrap();
+    /// let new: Tag = "platform".parse().unwrap();
+    /// assert_eq!(t.renamed(&old, &new), Some("platform/db".parse().unwrap()));
+    /// assert_eq!(new.renamed(&old, &t), None);
+    /// ```
+    pub fn renamed(&self, old: &Tag, new: &Tag) -> Option<Tag> {
+        if self.is_under(old) {
+            Some(Tag(format!("{}{}", new.0, &self.0[old.0.len()..])))
+        } else {
+            None
+        }
+    }
+    /// 由自身開始，依序列出所有祖先標籤，例如 `a/b/c` -> `a/b/c`, `a/b`, `a`
+    pub fn ancestors(&self) -> impl Iterator<Item = &str> {
+        let mut cur = Some(self.0.as_str());
+        std::iter::from_fn(move || {
+            let s = cur?;
+            cur = s.rfind(TAG_SEP).map(|pos| &s[..pos]);
+            Some(s)
+        })
+    }
 }
 impl FromStr for Tag {
     type Err = DisplayError;
     fn from_str(s: &str) -> DisplayResult<Self> {
-        if illegal_name(s) {
+        if s.split(TAG_SEP).any(illegal_name) {
             log::error!("標籤格式不符：{}", s);
             return TagCode.to_display_res(s.to_owned());
         }
@@ -118,14 +298,27 @@ impl FromStr for TagControl {
         } else {
             false
         };
+        let mut expire = None;
+        if !s.starts_with('@') {
+            if let Some((tag, ttl)) = s.split_once(EXPIRE_SEP) {
+                if !allow {
+                    log::error!("排除的標籤不可設定存活時間：{}", s);
+                    return TagCode.to_display_res(s.to_owned());
+                }
+                s = tag;
+                expire = Some(ttl.parse()?);
+            }
+        }
         Ok(TagControl {
             tag: s.parse()?,
             allow,
             mandatory,
+            expire,
         })
     }
 }
 const MANDATORY_SUFFIX: &str = "!";
+const EXPIRE_SEP: char = '@';
 const APPEND_PREFIX: &str = "+";
 impl FromStr for TagSelector {
     type Err = DisplayError;
@@ -186,6 +379,29 @@ impl TagSelector {
             }
         }
     }
+    /// 依照選擇器更新暫時性標籤的過期時間，需在 `fill_allowed_map` 之前呼叫
+    ///
+    /// 被明確設定而不帶存活時間的標籤會變回永久標籤
+    pub fn fill_expiry_map(&self, map: &mut HashMap<Tag, NaiveDateTime>) {
+        if !self.append {
+            map.clear();
+        }
+        let now = Utc::now().naive_utc();
+        for control in self.tags.0.iter() {
+            let tag = match &control.tag {
+                TagOrType::Type(_) => continue,
+                TagOrType::Tag(t) => t,
+            };
+            match control.expire {
+                Some(ttl) if control.allow => {
+                    map.insert(tag.clone(), now + ttl.duration());
+                }
+                _ => {
+                    map.remove(tag);
+                }
+            }
+        }
+    }
     pub fn into_allowed_iter(self) -> impl Iterator<Item = Tag> {
         let mut set = HashSet::default();
         self.fill_allowed_map(&mut set);
@@ -194,6 +410,15 @@ impl TagSelector {
     pub fn select(&self, tags: &TagSet, ty: &ScriptType) -> SelectResult {
         self.tags.select(tags, ty)
     }
+    pub fn rename_tag(&mut self, old: &Tag, new: &Tag) -> bool {
+        self.tags.rename_tag(old, new)
+    }
+    pub fn iter_tags(&self) -> impl Iterator<Item = &Tag> {
+        self.tags.iter_tags()
+    }
+    pub fn iter_types(&self) -> impl Iterator<Item = &ScriptType> {
+        self.tags.iter_types()
+    }
 }
 
 impl FromStr for TagGroup {
@@ -221,6 +446,9 @@ impl Display for TagGroup {
                 write!(w, "^")?;
             }
             write!(w, "{}", f.tag)?;
+            if let Some(expire) = &f.expire {
+                write!(w, "{}{}", EXPIRE_SEP, expire)?;
+            }
             if f.mandatory {
                 write!(w, "{}", MANDATORY_SUFFIX)?;
             }
@@ -241,12 +469,39 @@ impl SelectResult {
 }
 
 impl TagGroup {
+    /// 列出群組中提及的所有標籤，不包含類型和特殊的 `all`
+    pub fn iter_tags(&self) -> impl Iterator<Item = &Tag> {
+        self.0.iter().filter_map(|ctrl| match &ctrl.tag {
+            TagOrType::Tag(t) if !t.match_all() => Some(t),
+            _ => None,
+        })
+    }
+    /// 列出群組中提及的所有類型
+    pub fn iter_types(&self) -> impl Iterator<Item = &ScriptType> {
+        self.0.iter().filter_map(|ctrl| match &ctrl.tag {
+            TagOrType::Type(ty) => Some(ty),
+            _ => None,
+        })
+    }
+    /// 將所有 `old` 及其子孫標籤改名，回傳是否有任何改動
+    pub fn rename_tag(&mut self, old: &Tag, new: &Tag) -> bool {
+        let mut changed = false;
+        for ctrl in self.0.iter_mut() {
+            if let TagOrType::Tag(t) = &mut ctrl.tag {
+                if let Some(renamed) = t.renamed(old, new) {
+                    *t = renamed;
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
     pub fn select(&self, tags: &TagSet, ty: &ScriptType) -> SelectResult {
         let mut pass = SelectResult::None;
         for ctrl in self.0.iter() {
             let hit = match &ctrl.tag {
                 TagOrType::Type(t) => ty == t,
-                TagOrType::Tag(t) => t.match_all() || tags.contains(t),
+                TagOrType::Tag(t) => t.match_all() || tags.iter().any(|s| s.is_under(t)),
             };
             if ctrl.mandatory {
                 if ctrl.allow {
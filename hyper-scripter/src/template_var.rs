@@ -0,0 +1,264 @@
+//! 模版可以用 `{{!-- hs-vars: service:enum(api,web) port:int=8080 --}}` 宣告變數，
+//! 建立腳本時由 `--var` 給值或詢問使用者，再一併交給 Handlebars 渲染
+use crate::config::{Config, PromptLevel};
+use crate::env_pair::EnvPair;
+use crate::error::{Error, FormatCode::TemplateVar as TemplateVarCode, Result};
+use serde_json::{Map, Value};
+use std::str::FromStr;
+
+const DECLARE_PREFIX: &str = "hs-vars:";
+/// `prepare_script` 本來就會給模版的值，變數不能跟它們撞名
+const RESERVED: &[&str] = &[
+    "birthplace",
+    "birthplace_in_home",
+    "birthplace_rel",
+    "name",
+    "content",
+];
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum VarKind {
+    Str,
+    Int,
+    Bool,
+    Enum(Vec<String>),
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TemplateVar {
+    pub name: String,
+    pub kind: VarKind,
+    pub default: Option<String>,
+}
+
+impl FromStr for TemplateVar {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let (decl, default) = match s.split_once('=') {
+            Some((decl, default)) => (decl, Some(default.to_owned())),
+            None => (s, None),
+        };
+        let (name, kind) = match decl.split_once(':') {
+            Some((name, kind)) => (name, kind),
+            None => (decl, "str"),
+        };
+        if name.is_empty() || RESERVED.contains(&name) {
+            log::error!("模版變數名稱不合法：{}", name);
+            return TemplateVarCode.to_res(s.to_owned());
+        }
+        let kind = match kind {
+            "str" | "string" => VarKind::Str,
+            "int" => VarKind::Int,
+            "bool" => VarKind::Bool,
+            _ => {
+                let options = kind
+                    .strip_prefix("enum(")
+                    .and_then(|k| k.strip_suffix(')'))
+                    .ok_or_else(|| TemplateVarCode.to_err(s.to_owned()))?;
+                let options: Vec<_> = options
+                    .split(',')
+                    .filter(|o| !o.is_empty())
+                    .map(|o| o.to_owned())
+                    .collect();
+                if options.is_empty() {
+                    return TemplateVarCode.to_res(s.to_owned());
+                }
+                VarKind::Enum(options)
+            }
+        };
+        let var = TemplateVar {
+            name: name.to_owned(),
+            kind,
+            default,
+        };
+        if let Some(default) = &var.default {
+            var.check(default)?;
+        }
+        Ok(var)
+    }
+}
+
+impl TemplateVar {
+    /// 檢查值是否符合變數的型別，並轉成渲染用的 json 值
+    pub fn check(&self, val: &str) -> Result<Value> {
+        let bad_val = || TemplateVarCode.to_err(format!("{}={}", self.name, val));
+        Ok(match &self.kind {
+            VarKind::Str => Value::String(val.to_owned()),
+            VarKind::Int => Value::Number(val.parse::<i64>().map_err(|_| bad_val())?.into()),
+            VarKind::Bool => Value::Bool(val.parse().map_err(|_| bad_val())?),
+            VarKind::Enum(options) => {
+                if !options.iter().any(|o| o == val) {
+                    return Err(bad_val());
+                }
+                Value::String(val.to_owned())
+            }
+        })
+    }
+    fn hint(&self) -> String {
+        let kind = match &self.kind {
+            VarKind::Str => "string".to_owned(),
+            VarKind::Int => "int".to_owned(),
+            VarKind::Bool => "true/false".to_owned(),
+            VarKind::Enum(options) => options.join("/"),
+        };
+        match &self.default {
+            Some(default) => format!("{} ({}) [{}]: ", self.name, kind, default),
+            None => format!("{} ({}): ", self.name, kind),
+        }
+    }
+    fn ask(&self) -> Result<Value> {
+        use std::io::IsTerminal;
+        let term = console::Term::stderr();
+        // NOTE: stdin 不是終端機時讀到結尾只會一直拿到空字串，不能無限地問下去
+        let stdin_is_term = std::io::stdin().is_terminal();
+        loop {
+            term.write_str(&self.hint())?;
+            let line = term.read_line()?;
+            let line = line.trim();
+            let val = match (line, &self.default) {
+                ("", Some(default)) => default.as_str(),
+                ("", None) if stdin_is_term => continue,
+                ("", None) => return Err(Error::MissingTemplateVar(self.name.clone())),
+                _ => line,
+            };
+            match self.check(val) {
+                Ok(val) => return Ok(val),
+                Err(_) => term.write_line(&format!("Invalid value `{}`", val))?,
+            }
+        }
+    }
+}
+
+/// 找出模版中所有的變數宣告，並回傳拿掉宣告後的模版。獨占一行的宣告會連同換行一起拿掉
+pub fn parse_vars(template: &str) -> Result<(Vec<TemplateVar>, String)> {
+    let mut vars: Vec<TemplateVar> = vec![];
+    let mut stripped = String::with_capacity(template.len());
+    let mut pos = 0;
+    while let Some(start) = template[pos..].find("{{!") {
+        let start = pos + start;
+        let inner = &template[start + 3..];
+        let (body, len) = if let Some(body) = inner.strip_prefix("--") {
+            match body.find("--}}") {
+                Some(end) => (&body[..end], end + 9),
+                None => break,
+            }
+        } else {
+            match inner.find("}}") {
+                Some(end) => (&inner[..end], end + 5),
+                None => break,
+            }
+        };
+        let end = start + len;
+        let Some(decls) = body.trim_start().strip_prefix(DECLARE_PREFIX) else {
+            stripped.push_str(&template[pos..end]);
+            pos = end;
+            continue;
+        };
+        for decl in decls.split_whitespace() {
+            let var: TemplateVar = decl.parse()?;
+            if vars.iter().any(|v| v.name == var.name) {
+                log::error!("模版變數重複宣告：{}", var.name);
+                return TemplateVarCode.to_res(decl.to_owned());
+            }
+            vars.push(var);
+        }
+
+        let line_start = template[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = template[end..].find('\n').map(|i| end + i + 1);
+        let line_end = line_end.unwrap_or(template.len());
+        let is_blank = |s: &str| s.trim().is_empty();
+        if line_start >= pos
+            && is_blank(&template[line_start..start])
+            && is_blank(&template[end..line_end])
+        {
+            stripped.push_str(&template[pos..line_start]);
+            pos = line_end;
+        } else {
+            stripped.push_str(&template[pos..start]);
+            pos = end;
+        }
+    }
+    stripped.push_str(&template[pos..]);
+    Ok((vars, stripped))
+}
+
+/// 決定所有變數的值：優先用 `--var` 給的值，其次詢問使用者，無法詢問時才用預設值。
+/// 回傳拿掉宣告後的模版及變數的值
+pub fn resolve_vars(template: &str, given: &[EnvPair]) -> Result<(String, Map<String, Value>)> {
+    let (vars, template) = parse_vars(template)?;
+    for pair in given.iter() {
+        if !vars.iter().any(|v| v.name == pair.key) {
+            log::error!("模版沒有宣告變數 {}", pair.key);
+            return TemplateVarCode.to_res(pair.to_string());
+        }
+    }
+
+    let can_ask =
+        Config::get_prompt_level() != PromptLevel::Never && console::user_attended_stderr();
+    let mut ret = Map::new();
+    for var in vars.into_iter() {
+        let val = if let Some(pair) = given.iter().rev().find(|p| p.key == var.name) {
+            var.check(&pair.val)?
+        } else if can_ask {
+            var.ask()?
+        } else if let Some(default) = &var.default {
+            var.check(default)?
+        } else {
+            return Err(Error::MissingTemplateVar(var.name));
+        };
+        log::debug!("模版變數 {} = {}", var.name, val);
+        ret.insert(var.name, val);
+    }
+    Ok((template, ret))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_parse_vars() {
+        let tmpl = "{{!-- hs-vars: service:enum(api,web) port:int=8080 --}}
+{{! hs-vars: debug:bool=false }}
+{{! 一般的註解 }}
+{{!-- msg --}}
+run {{service}} {{port}}";
+        let (vars, stripped) = parse_vars(tmpl).unwrap();
+        assert_eq!(
+            stripped,
+            "{{! 一般的註解 }}
+{{!-- msg --}}
+run {{service}} {{port}}"
+        );
+        assert_eq!(
+            vars,
+            vec![
+                TemplateVar {
+                    name: "service".to_owned(),
+                    kind: VarKind::Enum(vec!["api".to_owned(), "web".to_owned()]),
+                    default: None,
+                },
+                TemplateVar {
+                    name: "port".to_owned(),
+                    kind: VarKind::Int,
+                    default: Some("8080".to_owned()),
+                },
+                TemplateVar {
+                    name: "debug".to_owned(),
+                    kind: VarKind::Bool,
+                    default: Some("false".to_owned()),
+                },
+            ]
+        );
+        assert_eq!(vars[1].check("80").unwrap(), json!(80));
+        assert!(vars[0].check("db").is_err());
+
+        let (vars, stripped) = parse_vars("a {{! hs-vars: x }} b\n").unwrap();
+        assert_eq!(vars.len(), 1);
+        assert_eq!(stripped, "a  b\n");
+
+        assert!(parse_vars("{{! hs-vars: port:int=abc }}").is_err());
+        assert!(parse_vars("{{! hs-vars: name }}").is_err());
+        assert!(parse_vars("{{! hs-vars: a a }}").is_err());
+        assert!(parse_vars("{{! hs-vars: a:enum() }}").is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use crate::config::{config_file, Config};
+use crate::error::{Error, FormatCode, Result};
+use crate::path;
+use toml_edit::{DocumentMut, Item, Value};
+
+fn split_key(key: &str) -> Result<Vec<&str>> {
+    let keys: Vec<_> = key.split('.').collect();
+    if keys.iter().any(|k| k.is_empty()) {
+        return Err(Error::UnknownConfigKey(key.to_owned()));
+    }
+    Ok(keys)
+}
+
+fn lookup<'a>(value: &'a toml::Value, keys: &[&str]) -> Option<&'a toml::Value> {
+    keys.iter().try_fold(value, |value, key| value.get(*key))
+}
+
+fn config_err(path: &std::path::Path, msg: impl std::fmt::Display) -> Error {
+    FormatCode::Config.to_err(format!("{}: {}", path.to_string_lossy(), msg))
+}
+
+/// 印出設定值，包含其它設定層的影響
+pub fn get(key: &str) -> Result<String> {
+    let keys = split_key(key)?;
+    let conf = Config::load(path::get_home())?.with_layers()?;
+    let value = toml::Value::try_from(&conf)?;
+    match lookup(&value, &keys) {
+        None => Err(Error::UnknownConfigKey(key.to_owned())),
+        Some(toml::Value::String(s)) => Ok(s.clone()),
+        Some(toml::Value::Table(table)) => Ok(toml::to_string_pretty(table)?),
+        Some(value) => Ok(value.to_string()),
+    }
+}
+
+/// 檢查所有設定層疊加後的結果，回傳所有問題
+pub fn validate() -> Result<Vec<String>> {
+    let conf = Config::load(path::get_home())?.with_layers()?;
+    Ok(conf.validate())
+}
+
+/// 依照路徑找到（或建出）對應的表格
+fn walk_create<'a>(mut item: &'a mut Item, keys: &[&str], full_key: &str) -> Result<&'a mut Item> {
+    for key in keys.iter() {
+        let is_inline = item.is_inline_table();
+        let Some(table) = item.as_table_like_mut() else {
+            return Err(Error::UnknownConfigKey(full_key.to_owned()));
+        };
+        if !table.contains_key(key) {
+            let new_table = if is_inline {
+                Item::Value(Value::InlineTable(Default::default()))
+            } else {
+                toml_edit::table()
+            };
+            table.insert(key, new_table);
+        }
+        item = table.get_mut(key).unwrap();
+    }
+    Ok(item)
+}
+
+/// 修改主設定檔中的值，`value` 為 `None` 代表刪除。修改後的設定檔須通過型別和 `Config::validate` 的檢查才會寫入
+pub fn set(key: &str, value: Option<&str>) -> Result {
+    let keys = split_key(key)?;
+    let (last, parent_keys) = keys.split_last().unwrap();
+    let path = config_file(path::get_home());
+    let content = match crate::util::read_file(&path) {
+        Ok(s) => s,
+        Err(Error::PathNotFound(_)) => {
+            log::info!("設定檔不存在，從預設值開始修改");
+            toml::to_string_pretty(&Config::default())?
+        }
+        Err(e) => return Err(e),
+    };
+    let doc: DocumentMut = content.parse().map_err(|e| config_err(&path, e))?;
+
+    // NOTE: 若值看起來像 TOML（如數字或陣列）就先照 TOML 解析，型別不符時再當成字串重試
+    let candidates: Vec<Option<Item>> = match value {
+        None => vec![None],
+        Some(value) => {
+            let mut v = vec![];
+            if let Ok(parsed) = value.parse::<Value>() {
+                v.push(Some(Item::Value(parsed)));
+            }
+            v.push(Some(toml_edit::value(value)));
+            v
+        }
+    };
+
+    let mut first_err = None;
+    for candidate in candidates.into_iter() {
+        let mut doc = doc.clone();
+        let parent = walk_create(doc.as_item_mut(), parent_keys, key)?;
+        let table = parent
+            .as_table_like_mut()
+            .ok_or_else(|| Error::UnknownConfigKey(key.to_owned()))?;
+        match candidate {
+            Some(mut item) => match table.get_mut(last) {
+                Some(old) => {
+                    // NOTE: 直接覆寫既有的值，才能保留其前後的註解和空白
+                    if let (Item::Value(old_value), Item::Value(value)) = (&*old, &mut item) {
+                        *value.decor_mut() = old_value.decor().clone();
+                    }
+                    *old = item;
+                }
+                None => {
+                    table.insert(last, item);
+                }
+            },
+            None => {
+                if table.remove(last).is_none() {
+                    return Err(Error::UnknownConfigKey(key.to_owned()));
+                }
+            }
+        }
+
+        let new_content = doc.to_string();
+        let conf: Config = match toml::from_str(&new_content) {
+            Ok(conf) => conf,
+            Err(err) => {
+                log::debug!("設定值不符型別：{}", err);
+                first_err.get_or_insert(config_err(&path, err));
+                continue;
+            }
+        };
+        if value.is_some() && lookup(&toml::Value::try_from(&conf)?, &keys).is_none() {
+            return Err(Error::UnknownConfigKey(key.to_owned()));
+        }
+        let problems = conf.validate();
+        if !problems.is_empty() {
+            return Err(config_err(&path, problems.join("; ")));
+        }
+        log::info!("寫入設定檔 {:?}", path);
+        return crate::util::write_file(&path, &new_content);
+    }
+    Err(first_err.unwrap())
+}
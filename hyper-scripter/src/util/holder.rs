@@ -1,4 +1,5 @@
 use crate::args::RootArgs;
+use crate::config::Config;
 use crate::error::Result;
 use crate::script_repo::{DBEnv, ScriptRepo};
 use crate::{path, util};
@@ -37,7 +38,8 @@ impl<'a> RepoHolder<'a> {
         }
     }
     pub async fn historian(self) -> Result<&'a mut Historian> {
-        let historian = Historian::new(path::get_home().to_owned()).await?;
+        let backend = Config::get().history_backend;
+        let historian = Historian::open(path::get_home().to_owned(), backend).await?;
         *self.resource = Resource::Historian(historian);
         match self.resource {
             Resource::Historian(historian) => Ok(historian),
@@ -11,7 +11,8 @@ use hyper_scripter_historian::Historian;
 /// 即使 `need_journal=false` 也可能使用 journal，具體條件同 `crate::db::get_pool`
 pub async fn init_env(mut need_journal: bool) -> Result<(DBEnv, bool)> {
     async fn init_historian() -> Result<Historian> {
-        let h = Historian::new(path::get_home().to_owned()).await?;
+        let backend = Config::get().history_backend;
+        let h = Historian::open(path::get_home().to_owned(), backend).await?;
         Ok(h)
     }
     let ((pool, init), historian) =
@@ -63,9 +64,20 @@ pub async fn init_repo(args: RootArgs, need_journal: bool) -> Result<ScriptRepo>
         },
         env,
         &tag_group,
+        &conf.tag_registry,
     )
     .await
     .context("載入腳本倉庫失敗")?;
+    if need_journal && conf.archive.auto {
+        let changes = main_util::archive_candidates(&mut repo, &conf.archive);
+        if !changes.is_empty() {
+            log::info!("依照封存策略自動封存 {} 支腳本", changes.len());
+            let ids: Vec<_> = changes.iter().map(|c| c.id).collect();
+            let changes = changes.into_iter().map(|c| (c.id, c.after)).collect();
+            repo.update_tags(changes).await?;
+            main_util::neglect_archived(&mut repo, &ids).await?;
+        }
+    }
     if no_trace {
         repo.no_trace();
     } else if humble {
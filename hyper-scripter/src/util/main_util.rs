@@ -1,10 +1,15 @@
 use super::PrepareRespond;
-use crate::args::{HistoryDisplay, Subs};
-use crate::color::Stylize;
-use crate::config::Config;
+use crate::args::{
+    find_subcmd, History as HistoryArgs, PreviousMode, Subs, Tags as TagsArgs, Types as TypesArgs,
+    TypesCmd,
+};
+use crate::color::{Color, Stylize};
+use crate::config::{ArchivePolicy, Config, ConfigChange, EnvProfile};
 use crate::env_pair::EnvPair;
-use crate::error::{Contextable, Error, RedundantOpt, Result};
-use crate::extract_msg::extract_env_from_content_help_aware;
+use crate::error::{Contextable, Error, FormatCode, RedundantOpt, Result};
+use crate::extract_msg::{
+    extract_env_file_from_content, extract_env_from_content_help_aware, Message,
+};
 use crate::path;
 use crate::process_lock::{ProcessLockRead, ProcessLockWrite};
 use crate::query::{
@@ -12,9 +17,15 @@ use crate::query::{
 };
 use crate::script::{IntoScriptName, ScriptInfo, ScriptName};
 use crate::script_repo::{RepoEntry, ScriptRepo, StableRepo, Visibility};
-use crate::script_type::{iter_default_templates, ScriptFullType, ScriptType};
-use crate::tag::{Tag, TagSelector, TagSelectorGroup};
+use crate::script_type::{
+    iter_default_templates, ExecInfo, ScriptFullType, ScriptType, ScriptTypeConfig,
+};
+use crate::tag::{Tag, TagSelector, TagSelectorGroup, TagSet};
+use crate::to_display_args;
+use chrono::{NaiveDateTime, Utc};
 use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use hyper_scripter_historian::{EventData, EventRecord, EventType, RawEvent};
+use serde::{Deserialize, Serialize};
 use std::fs::{create_dir_all, read_dir};
 use std::path::{Path, PathBuf};
 use std::time::Duration;
@@ -29,6 +40,111 @@ pub struct EditTagArgs {
     pub explicit_select: bool,
 }
 
+pub struct TagChange {
+    pub id: i64,
+    pub name: String,
+    pub before: TagSet,
+    pub after: TagSet,
+}
+impl TagChange {
+    pub fn new(info: &ScriptInfo, after: TagSet) -> Self {
+        TagChange {
+            id: info.id,
+            name: info.name.key().into_owned(),
+            before: info.tags.clone(),
+            after,
+        }
+    }
+}
+
+/// 印出標籤的改動，並在使用者同意後以單一交易寫入腳本資料庫
+///
+/// 回傳值代表改動是否真的被套用
+pub async fn apply_tag_changes(
+    repo: &mut ScriptRepo,
+    mut changes: Vec<TagChange>,
+    conf_changes: &[ConfigChange],
+    dry_run: bool,
+    yes: bool,
+) -> Result<bool> {
+    if changes.is_empty() && conf_changes.is_empty() {
+        log::info!("沒有需要修改的標籤");
+        return Ok(false);
+    }
+
+    changes.sort_by(|a, b| a.name.cmp(&b.name));
+    for change in changes.iter() {
+        print!("{}:", (&change.name).stylize().bold());
+        let mut removed: Vec<_> = change.before.difference(&change.after).collect();
+        let mut added: Vec<_> = change.after.difference(&change.before).collect();
+        removed.sort();
+        added.sort();
+        for tag in removed.into_iter() {
+            print!(" {}", format!("-{}", tag).stylize().color(Color::Red));
+        }
+        for tag in added.into_iter() {
+            print!(" {}", format!("+{}", tag).stylize().color(Color::Green));
+        }
+        println!();
+    }
+    for change in conf_changes.iter() {
+        println!(
+            "{}: {} -> {}",
+            (&change.place).stylize().bold(),
+            (&change.before).stylize().color(Color::Red),
+            (&change.after).stylize().color(Color::Green)
+        );
+    }
+
+    if dry_run {
+        log::info!("僅顯示改動，不實際寫入");
+        return Ok(false);
+    }
+    if !yes && !super::prompt("Apply these changes?", false)? {
+        log::info!("使用者取消修改標籤");
+        return Ok(false);
+    }
+
+    let changes = changes.into_iter().map(|c| (c.id, c.after)).collect();
+    repo.update_tags(changes).await?;
+    Ok(true)
+}
+
+/// 找出依照封存策略該被封存的腳本
+pub fn archive_candidates(repo: &mut ScriptRepo, policy: &ArchivePolicy) -> Vec<TagChange> {
+    let conf = Config::get();
+    let bound = Utc::now().naive_utc() - chrono::Duration::days(policy.days.into());
+    repo.iter_mut(Visibility::All)
+        .filter_map(|entry| {
+            // 從未執行過的腳本以創建時間計算
+            let last_exec = match &entry.exec_time {
+                Some(time) => **time,
+                None => *entry.created_time,
+            };
+            if entry.tags.contains(&policy.tag) || last_exec > bound {
+                return None;
+            }
+            let tags = conf.tag_registry.expand(&entry.tags);
+            if !policy.select.select(&tags, &entry.ty).is_true() {
+                return None;
+            }
+            let mut after = entry.tags.clone();
+            after.insert(policy.tag.clone());
+            Some(TagChange::new(&entry, after))
+        })
+        .collect()
+}
+/// 將被封存的腳本標記為忽略，使其不再出現在近期腳本中
+pub async fn neglect_archived(repo: &mut ScriptRepo, ids: &[i64]) -> Result {
+    for id in ids.iter() {
+        if let Some(entry) = repo.get_mut_by_id(*id) {
+            log::debug!("封存腳本 {:?}", entry.name);
+            entry.get_env().handle_neglect(*id).await?;
+        }
+    }
+    Ok(())
+}
+
 pub async fn mv(
     entry: &mut RepoEntry<'_>,
     new_name: Option<ScriptName>,
@@ -51,6 +167,9 @@ pub async fn mv(
         }
     }
 
+    let og_name = entry.name.key().into_owned();
+    let og_ty = entry.ty.clone();
+    let og_tags = entry.tags.clone();
     entry
         .update(|info| {
             if let Some(ty) = ty {
@@ -65,6 +184,135 @@ pub async fn mv(
             info.write();
         })
         .await?;
+
+    let env = entry.get_env();
+    let name = entry.name.key();
+    if name != og_name {
+        let data = EventData::Rename {
+            from: &og_name,
+            to: &name,
+        };
+        env.record_structural(entry.id, data).await?;
+    }
+    if entry.ty != og_ty {
+        let data = EventData::TypeChange {
+            from: og_ty.as_ref(),
+            to: entry.ty.as_ref(),
+        };
+        env.record_structural(entry.id, data).await?;
+    }
+    env.handle_retag(entry.id, &name, &og_tags, &entry.tags)
+        .await?;
+    Ok(())
+}
+
+/// 新增類型。有 `from` 時繼承該類型並複製其模版；沒有 `cmd` 也沒有 `from` 的類型以 shebang 執行
+pub fn add_type(
+    conf: &mut Config,
+    name: ScriptType,
+    ext: Option<String>,
+    cmd: Option<String>,
+    color: Option<String>,
+    from: Option<ScriptType>,
+) -> Result {
+    if conf.types.contains_key(&name) {
+        log::error!("類型 {} 已存在", name);
+        return Err(Error::TypeExist(name.to_string()));
+    }
+    let exec_info = match cmd {
+        Some(cmd) => {
+            let mut args = shlex::split(&cmd)
+                .filter(|args| !args.is_empty())
+                .ok_or_else(|| Error::msg(format!("Invalid command: {}", cmd)))?
+                .into_iter();
+            let cmd = args.next().unwrap();
+            let mut args: Vec<_> = args.collect();
+            if args.is_empty() {
+                args.push("{{path}}".to_owned());
+            }
+            Some(ExecInfo::new(cmd, args))
+        }
+        None => None,
+    };
+    let default_color = if from.is_some() { "" } else { "white" };
+    let color = color.unwrap_or_else(|| default_color.to_owned());
+    let mut ty_conf = ScriptTypeConfig::new(exec_info, ext, color);
+
+    if let Some(from) = from {
+        conf.get_script_conf(&from)?; // 確認類型存在與否
+        let parent_tmpl = ScriptFullType {
+            ty: from.clone(),
+            sub: None,
+        };
+        let new_tmpl = ScriptFullType {
+            ty: name.clone(),
+            sub: None,
+        };
+        let tmpl_path = path::get_template_path(&new_tmpl)?;
+        if tmpl_path.exists() {
+            log::warn!("{:?} 已有模版，不從 {} 複製", tmpl_path, from);
+        } else {
+            let tmpl = super::get_or_create_template(&parent_tmpl, false, false)?;
+            super::write_file(&tmpl_path, &tmpl)?;
+        }
+        ty_conf.extends = Some(from);
+    }
+    conf.types.insert(name, ty_conf);
+    Ok(())
+}
+
+/// 刪除類型。若仍有腳本使用之，需指定 `migrate_to` 將它們改為其它類型
+pub async fn remove_type(
+    repo: &mut ScriptRepo,
+    conf: &mut Config,
+    name: &ScriptType,
+    migrate_to: Option<ScriptType>,
+) -> Result {
+    if !conf.types.contains_key(name) {
+        return Err(Error::UnknownType(name.to_string()));
+    }
+    let mut children: Vec<_> = conf.iter_children(name).map(|t| t.to_string()).collect();
+    if !children.is_empty() {
+        children.sort();
+        log::error!("類型 {} 仍被繼承：{:?}", name, children);
+        return Err(Error::TypeExtended(name.to_string(), children));
+    }
+    if let Some(migrate_to) = &migrate_to {
+        if migrate_to == name {
+            return Err(RedundantOpt::Type.into());
+        }
+        conf.get_script_conf(migrate_to)?; // 確認類型存在與否
+    }
+
+    let mut scripts: Vec<_> = repo
+        .iter_mut(Visibility::All)
+        .filter(|e| &e.ty == name)
+        .map(|e| (e.id, e.name.key().to_string()))
+        .collect();
+    if !scripts.is_empty() {
+        let Some(migrate_to) = migrate_to else {
+            scripts.sort_by(|a, b| a.1.cmp(&b.1));
+            let names: Vec<_> = scripts.into_iter().map(|(_, name)| name).collect();
+            log::error!("類型 {} 仍有腳本在使用：{:?}", name, names);
+            return Err(Error::TypeInUse(name.to_string(), names));
+        };
+        // NOTE: 先確認所有腳本都搬得過去，以免搬到一半才失敗，留下一半已改類型的腳本
+        for (id, _) in scripts.iter() {
+            let entry = repo.get_mut_by_id(*id).unwrap();
+            let og_path = path::open_script(&entry.name, &entry.ty, Some(true))?;
+            let new_path = path::open_script(&entry.name, &migrate_to, None)?;
+            if new_path != og_path && new_path.exists() {
+                log::error!("{:?} 改類型後會撞到既存的 {:?}", entry.name, new_path);
+                return Err(Error::PathExist(new_path).context("移動成既存腳本"));
+            }
+        }
+        for (id, _) in scripts.into_iter() {
+            let mut entry = repo.get_mut_by_id(id).unwrap();
+            log::info!("將 {:?} 的類型改為 {}", entry.name, migrate_to);
+            mv(&mut entry, None, Some(migrate_to.clone()), None).await?;
+        }
+    }
+    conf.types.remove(name);
     Ok(())
 }
 
@@ -172,12 +420,13 @@ pub struct EditResult<'a> {
 pub struct CreateResult {
     pub ty: ScriptFullType,
     pub tags: Vec<Tag>,
+    pub tag_expiry: HashMap<Tag, NaiveDateTime>,
     pub to_create: HashMap<ScriptName, PathBuf>,
 }
 impl CreateResult {
     pub fn new(
         ty: ScriptFullType,
-        tags: Vec<Tag>,
+        tags: TagSelector,
         anonymous_cnt: u32,
         named: HashMap<ScriptName, PathBuf>,
     ) -> Result<CreateResult> {
@@ -198,9 +447,12 @@ impl CreateResult {
             let path = path::open_script(&name, &ty.ty, None)?; // NOTE: new_anonymous_name 的邏輯已足以確保不會產生衝突的檔案，不檢查了！
             to_create.insert(name, path);
         }
+        let mut tag_expiry = HashMap::default();
+        tags.fill_expiry_map(&mut tag_expiry);
         Ok(CreateResult {
             ty,
-            tags,
+            tags: tags.into_allowed_iter().collect(),
+            tag_expiry,
             to_create,
         })
     }
@@ -236,7 +488,7 @@ pub async fn edit_or_create(
     if edit_query_handler.has_new_script() {
         let create_result = CreateResult::new(
             edit_query_handler.ty.unwrap(),
-            tags.content.into_allowed_iter().collect(),
+            tags.content,
             edit_query_handler.anonymous_cnt,
             edit_query_handler.named,
         )?;
@@ -246,32 +498,82 @@ pub async fn edit_or_create(
     }
 }
 
-async fn run(
+const SECRET_ENV_PATTERNS: &[&str] = &[
+    "SECRET",
+    "TOKEN",
+    "PASSWORD",
+    "PASSWD",
+    "CREDENTIAL",
+    "API_KEY",
+    "PRIVATE_KEY",
+    "AUTH",
+];
+
+fn is_secret_env(key: &str) -> bool {
+    let key = key.to_ascii_uppercase();
+    SECRET_ENV_PATTERNS.iter().any(|p| key.contains(p))
+}
+
+fn display_cmd(cmd: &AsyncCommand) -> String {
+    let cmd = cmd.as_std();
+    let argv: Vec<_> = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|s| crate::to_display_args(&s.to_string_lossy()).into_owned())
+        .collect();
+    argv.join(" ")
+}
+
+/// 印出實際會執行的命令、工作目錄，以及和當前環境相比有變動的環境變數
+fn print_dry_run(pre_cmd: &AsyncCommand, main_cmd: &AsyncCommand) -> Result {
+    let cwd = path::normalize_path(".")?;
+    println!("pre-run: {}", display_cmd(pre_cmd));
+    println!("command: {}", display_cmd(main_cmd));
+    println!("cwd: {}", cwd.to_string_lossy());
+
+    let mut envs: Vec<_> = main_cmd
+        .as_std()
+        .get_envs()
+        .filter_map(|(k, v)| Some((k, v?)))
+        .collect();
+    envs.sort_by_key(|(k, _)| *k);
+    println!("env:");
+    for (key, val) in envs.into_iter() {
+        let old = std::env::var_os(key);
+        let sign = match old {
+            Some(old) if old == val => continue,
+            Some(_) => '~',
+            None => '+',
+        };
+        let key = key.to_string_lossy();
+        if is_secret_env(&key) {
+            println!("  {} {}=<redacted>", sign, key);
+        } else {
+            let val = val.to_string_lossy();
+            println!("  {} {}={}", sign, key, crate::to_display_args(&val));
+        }
+    }
+    Ok(())
+}
+
+/// 建立預腳本及主程式的命令，兩者共用同一組環境變數
+fn build_cmds(
     script_path: &Path,
-    info: &mut RepoEntry<'_>,
+    ty: &ScriptType,
     remaining: &[String],
-    mut hs_tmpl_val: super::TmplVal<'_>,
+    hs_tmpl_val: &super::TmplVal<'_>,
     remaining_envs: &[EnvPair],
-    caution: bool,
-    repeat: u64,
-    res: &mut Vec<Error>,
-) -> Result {
-    let env_record = serde_json::to_string(&remaining_envs)?;
-    let here = path::normalize_path(".").ok();
-    let run_id = info
-        .update(|info| info.exec(remaining, env_record, here, false))
-        .await?;
-    hs_tmpl_val.run_id = Some(run_id);
-
-    let mut lock = ProcessLockWrite::new(run_id, info.id, hs_tmpl_val.name.unwrap(), remaining)?;
-    let guard = lock.try_write_info()?;
-
+    file_envs: &[EnvPair],
+    profile: Option<(&str, &EnvProfile)>,
+) -> Result<(AsyncCommand, AsyncCommand)> {
     let conf = Config::get();
-    let ty = &info.ty;
 
     let script_conf = conf.get_script_conf(ty)?;
-    let env = conf.gen_env(&hs_tmpl_val, true)?;
-    let ty_env = script_conf.gen_env(&hs_tmpl_val)?;
+    let env = conf.gen_env(hs_tmpl_val, true)?;
+    let ty_env = script_conf.gen_env(hs_tmpl_val)?;
+    let profile_env = match profile {
+        Some((_, profile)) => profile.gen_env(hs_tmpl_val)?,
+        None => vec![],
+    };
 
     let pre_run_script = prepare_pre_run(None)?;
     let (cmd, shebang) = super::shebang_handle::handle(&pre_run_script)?;
@@ -284,6 +586,8 @@ async fn run(
     let set_cmd_envs = |cmd: &mut AsyncCommand| {
         cmd.envs(ty_env.iter().map(|(a, b)| (a, b)));
         cmd.envs(env.iter().map(|(a, b)| (a, b)));
+        cmd.envs(profile_env.iter().map(|(a, b)| (a, b)));
+        cmd.envs(file_envs.iter().map(|p| (&p.key, &p.val)));
         cmd.envs(remaining_envs.iter().map(|p| (&p.key, &p.val)));
     };
 
@@ -295,13 +599,13 @@ async fn run(
     let (cmd, args) = match script_conf.exec_info.as_ref() {
         None => {
             // TODO: try to avoid this file read? It's done in the caller function
-            tmp = super::shebang_handle::handle(&script_path)?;
+            tmp = super::shebang_handle::handle(script_path)?;
             tmp.1.push(script_path.to_string_lossy().into_owned());
             (&tmp.0, tmp.1)
         }
         Some(exec_info) => {
             let cmd = &exec_info.cmd;
-            let args = exec_info.args(&hs_tmpl_val)?;
+            let args = exec_info.args(hs_tmpl_val)?;
             (cmd, args)
         }
     };
@@ -310,16 +614,88 @@ async fn run(
         .iter()
         .map(|s| s.as_str())
         .chain(remaining.iter().map(|s| s.as_str()));
-    let mut main_cmd = super::async_create_cmd(&cmd, full_args);
+    let mut main_cmd = super::async_create_cmd(cmd, full_args);
     set_cmd_envs(&mut main_cmd);
     // end prepare main cmd
 
+    Ok((pre_cmd, main_cmd))
+}
+
+/// 一次 `hs run` 的選項
+#[derive(Debug)]
+pub struct RunOptions {
+    pub repeat: u64,
+    pub dummy: bool,
+    pub dry_run: bool,
+    pub previous: Option<PreviousMode>,
+    pub error_no_previous: bool,
+    pub caution: Option<bool>,
+    pub dir: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub env_files: Vec<PathBuf>,
+}
+
+/// 準備好要實際執行的內容
+struct RunPlan<'a> {
+    remaining: &'a [String],
+    remaining_envs: &'a [EnvPair],
+    /// 從環境檔讀出的變數，只用於執行，不會被記錄
+    file_envs: &'a [EnvPair],
+    env_files: Option<&'a str>,
+    profile: Option<(&'a str, &'a EnvProfile)>,
+    caution: bool,
+    repeat: u64,
+}
+
+async fn run(
+    script_path: &Path,
+    info: &mut RepoEntry<'_>,
+    mut hs_tmpl_val: super::TmplVal<'_>,
+    plan: RunPlan<'_>,
+    res: &mut Vec<Error>,
+) -> Result {
+    let RunPlan {
+        remaining,
+        remaining_envs,
+        file_envs,
+        env_files,
+        profile,
+        caution,
+        repeat,
+    } = plan;
+    let env_record = serde_json::to_string(&remaining_envs)?;
+    let here = path::normalize_path(".").ok();
+    let profile_name = profile.map(|(name, _)| name.to_owned());
+    let env_files = env_files.map(str::to_owned);
+    let run_id = info
+        .update(|info| info.exec(remaining, env_record, env_files, profile_name, here, false))
+        .await?;
+    hs_tmpl_val.run_id = Some(run_id);
+
+    let mut lock = ProcessLockWrite::new(run_id, info.id, hs_tmpl_val.name.unwrap(), remaining)?;
+    let guard = lock.try_write_info()?;
+
+    let (mut pre_cmd, mut main_cmd) = build_cmds(
+        script_path,
+        &info.ty,
+        remaining,
+        &hs_tmpl_val,
+        remaining_envs,
+        file_envs,
+        profile,
+    )?;
+
     let caution_msg = {
         let ty = super::get_display_type(&info.ty);
-        format!(
-            "{} requires extra caution. Sure to run?",
-            info.name.key().stylize().color(ty.color()).bold()
-        )
+        let name = info.name.key();
+        let name = name.stylize().color(ty.color()).bold().to_string();
+        match profile {
+            Some((profile, _)) => format!(
+                "{} (profile `{}`) requires extra caution. Sure to run?",
+                name, profile
+            ),
+            None => format!("{} requires extra caution. Sure to run?", name),
+        }
     };
     let info_mutex = Mutex::new(info);
     let run_future = async {
@@ -383,71 +759,107 @@ async fn run(
     Ok(())
 }
 pub async fn run_n_times(
-    repeat: u64,
-    dummy: bool,
     entry: &mut RepoEntry<'_>,
     mut args: Vec<String>,
+    opts: RunOptions,
     res: &mut Vec<Error>,
-    previous: Option<HistoryDisplay>,
-    error_no_previous: bool,
-    caution: Option<bool>,
-    dir: Option<PathBuf>,
 ) -> Result {
+    let RunOptions {
+        repeat,
+        dummy,
+        dry_run,
+        previous,
+        error_no_previous,
+        caution,
+        dir,
+        mut profile,
+        mut env_files,
+    } = opts;
     log::info!("執行 {:?}", entry.name);
     super::hijack_ctrlc_once();
 
     let mut env_vec = vec![];
     if let Some(previous) = previous {
         let historian = &entry.get_env().historian;
-        match historian.previous_args(entry.id, dir.as_deref()).await? {
+        match historian
+            .previous_args(entry.id, dir.as_deref(), previous.only_success())
+            .await?
+        {
             None if error_no_previous => {
                 return Err(Error::NoPreviousArgs);
             }
             None => log::warn!("無前一次參數，當作空的"),
-            Some((arg_str, envs_str)) => {
-                log::debug!("撈到前一次呼叫的參數 {} 及環境 {}", arg_str, envs_str);
+            Some(prev) => {
+                log::debug!("撈到前一次呼叫的參數 {} 及環境 {}", prev.args, prev.envs);
                 if previous.show_args() {
-                    let mut prev_arg_vec: Vec<String> = serde_json::from_str(&arg_str)
-                        .context(format!("反序列失敗 {}", arg_str))?;
+                    let mut prev_arg_vec: Vec<String> = serde_json::from_str(&prev.args)
+                        .context(format!("反序列失敗 {}", prev.args))?;
                     prev_arg_vec.extend(args.into_iter());
                     args = prev_arg_vec;
                 }
                 if previous.show_env() {
-                    env_vec = serde_json::from_str(&envs_str)
-                        .context(format!("反序列失敗 {}", envs_str))?;
+                    env_vec = serde_json::from_str(&prev.envs)
+                        .context(format!("反序列失敗 {}", prev.envs))?;
+                    if profile.is_none() {
+                        log::debug!("沿用前一次的環境組合 {:?}", prev.profile);
+                        profile = prev.profile;
+                    }
+                    if let Some(files) = prev.env_files.filter(|_| env_files.is_empty()) {
+                        log::debug!("沿用前一次的環境檔 {}，重新讀取其內容", files);
+                        env_files = serde_json::from_str(&files)
+                            .context(format!("反序列失敗 {}", files))?;
+                    }
                 }
             }
         }
     }
 
     let script_path = path::open_script(&entry.name, &entry.ty, Some(true))?;
-    let content = super::read_file_lines(&script_path)?;
+    let content: Vec<_> = super::read_file_lines(&script_path)?.collect();
 
+    let conf = Config::get();
+    let profile = conf.get_env_profile(profile.as_deref(), &entry.tags, &entry.ty)?;
+    if let Some((name, _)) = profile {
+        log::info!("使用環境組合 {}", name);
+    }
     let caution = caution.unwrap_or_else(|| {
-        Config::get()
-            .caution_tags
-            .select(&entry.tags, &entry.ty)
-            .is_true()
+        if profile.is_some_and(|(_, p)| p.dangerous) {
+            return true;
+        }
+        let tags = conf.tag_registry.expand(&entry.tags);
+        conf.caution_tags.select(&tags, &entry.ty).is_true()
     });
 
     let mut hs_env_desc = vec![];
-    for (need_save, line) in extract_env_from_content_help_aware(content) {
+    for (need_save, line) in extract_env_from_content_help_aware(content.iter().cloned()) {
         if need_save {
             EnvPair::process_line(line.as_ref(), &mut env_vec, |e| std::env::var(e).ok());
         }
         hs_env_desc.push(line);
     }
-    EnvPair::sort(&mut env_vec);
-
-    if dummy {
-        log::info!("--dummy 不用真的執行，提早退出");
-        let env_record = serde_json::to_string(&env_vec)?;
-        let here = path::normalize_path(".").ok();
-        entry
-            .update(|info| info.exec(&args, env_record, here, true))
-            .await?;
-        return Ok(());
+    // NOTE: 環境檔只記錄路徑，不記錄內容，以免把密鑰存進歷史
+    let env_files = env_files
+        .iter()
+        .map(|p| resolve_env_file(p))
+        .collect::<Result<Vec<_>>>()?;
+    let env_files_record = if env_files.is_empty() {
+        None
+    } else {
+        Some(serde_json::to_string(&env_files)?)
+    };
+    // NOTE: 已存在的環境變數較強，故後面的檔案要先載入
+    // 腳本建立後不會保留出生地，故宣告的相對路徑一律以當前目錄為準，
+    // 要綁定出生地得在模版中寫 `{{birthplace}}`，建立時即展開成絕對路徑
+    let declared_files: Vec<_> = extract_env_file_from_content(content.iter().cloned())
+        .map(|m| (PathBuf::from(m.as_ref().trim()), false))
+        .collect();
+    let explicit_files = env_files.into_iter().map(|p| (p, true));
+    let mut file_envs = vec![];
+    for (file, explicit) in explicit_files.rev().chain(declared_files.into_iter().rev()) {
+        load_env_file(&file, explicit, &env_vec, &mut file_envs, &mut hs_env_desc)?;
     }
+    EnvPair::sort(&mut env_vec);
+    EnvPair::sort(&mut file_envs);
 
     // Start packing hs tmpl val
     // SAFETY: 底下所有對 `entry` 的借用，都不會被更後面的 `entry.update` 影響
@@ -462,18 +874,100 @@ pub async fn run_n_times(
     hs_tmpl_val.name = Some(hs_name);
     // End packing hs tmpl val
 
-    run(
-        &script_path,
-        entry,
-        &args,
-        hs_tmpl_val,
-        &env_vec,
+    if dry_run {
+        let (pre_cmd, main_cmd) = build_cmds(
+            &script_path,
+            &entry.ty,
+            &args,
+            &hs_tmpl_val,
+            &env_vec,
+            &file_envs,
+            profile,
+        )?;
+        print_dry_run(&pre_cmd, &main_cmd)?;
+        if !dummy {
+            log::info!("--dry-run 不用真的執行，提早退出");
+            return Ok(());
+        }
+    }
+    if dummy {
+        log::info!("--dummy 不用真的執行，提早退出");
+        let env_record = serde_json::to_string(&env_vec)?;
+        let here = path::normalize_path(".").ok();
+        let profile_name = profile.map(|(name, _)| name.to_owned());
+        entry
+            .update(|info| {
+                info.exec(
+                    &args,
+                    env_record,
+                    env_files_record,
+                    profile_name,
+                    here,
+                    true,
+                )
+            })
+            .await?;
+        return Ok(());
+    }
+
+    let plan = RunPlan {
+        remaining: &args,
+        remaining_envs: &env_vec,
+        file_envs: &file_envs,
+        env_files: env_files_record.as_deref(),
+        profile,
         caution,
         repeat,
-        res,
-    )
-    .await?;
+    };
+    run(&script_path, entry, hs_tmpl_val, plan, res).await?;
+
+    Ok(())
+}
+
+fn resolve_env_file(file: &Path) -> Result<PathBuf> {
+    if let Ok(rest) = file.strip_prefix("~") {
+        if let Some(home) = dirs::home_dir() {
+            return Ok(home.join(rest));
+        }
+    }
+    path::normalize_path(file)
+}
 
+/// 載入 dotenv 檔至 `file_envs`，已存在於 `env_vec`、`file_envs` 或當前環境中的變數不會被覆蓋
+fn load_env_file(
+    file: &Path,
+    explicit: bool,
+    env_vec: &[EnvPair],
+    file_envs: &mut Vec<EnvPair>,
+    env_desc: &mut Vec<Message>,
+) -> Result {
+    let full_path = resolve_env_file(file)?;
+    if !explicit && !full_path.exists() {
+        log::warn!("找不到腳本宣告的環境檔 {:?}，跳過", full_path);
+        return Ok(());
+    }
+    log::info!("載入環境檔 {:?}", full_path);
+    let content = super::read_file(&full_path)?;
+    let pairs =
+        EnvPair::from_dotenv(&content).context(format!("解析環境檔失敗 {:?}", full_path))?;
+    for pair in pairs.into_iter() {
+        if env_vec
+            .iter()
+            .chain(file_envs.iter())
+            .any(|p| p.key == pair.key)
+            || std::env::var_os(&pair.key).is_some()
+        {
+            log::debug!("環境變數 {} 已存在，忽略環境檔中的值", pair.key);
+            continue;
+        }
+        if !env_desc
+            .iter()
+            .any(|d| d.as_ref().split_whitespace().next() == Some(&pair.key))
+        {
+            env_desc.push(format!("{} from {}", pair.key, file.to_string_lossy()).into());
+        }
+        file_envs.push(pair);
+    }
     Ok(())
 }
 
@@ -512,7 +1006,8 @@ pub async fn load_utils(
         )
         .build();
         let hide = if let Some(selector) = selector {
-            !selector.select(&script.tags, &script.ty)
+            let tags = Config::get().tag_registry.expand(&script.tags);
+            !selector.select(&tags, &script.ty)
         } else {
             false
         };
@@ -556,8 +1051,19 @@ pub fn need_write(arg: &Subs) -> bool {
     use Subs::*;
     match arg {
         Edit { .. } => true,
+        Archive { .. } => true,
+        Tags {
+            subcmd: Some(TagsArgs::Add { .. } | TagsArgs::Remove { .. } | TagsArgs::Rename { .. }),
+        } => true,
         CP { .. } => true,
         RM { .. } => true,
+        History {
+            subcmd: HistoryArgs::ToScript { .. },
+        } => true,
+        Types(TypesArgs {
+            subcmd: Some(TypesCmd::RM { migrate_to, .. }),
+            ..
+        }) => migrate_to.is_some(),
         LoadUtils { .. } => true,
         MV {
             ty,
@@ -618,6 +1124,17 @@ fn check_path_collision(p: &Path, script_repo: &mut StableRepo) -> Result {
     Ok(())
 }
 
+/// 以返回碼描述一次執行的結果，沒有返回碼時依是否仍在執行區分
+pub fn exec_status(code: Option<i32>, running: bool) -> String {
+    match code {
+        Some(0) => "ok".to_owned(),
+        Some(130) => "interrupted".to_owned(),
+        Some(code) => format!("failed {}", code),
+        None if running => "running".to_owned(),
+        None => "interrupted".to_owned(),
+    }
+}
+
 pub fn get_all_active_process_locks() -> Result<Vec<ProcessLockRead>> {
     let dir_path = path::get_process_lock_dir()?;
     let dir = super::handle_fs_res(&[&dir_path], read_dir(&dir_path))?;
@@ -657,26 +1174,232 @@ pub fn get_all_active_process_locks() -> Result<Vec<ProcessLockRead>> {
     Ok(ret)
 }
 
-pub fn known_tags_iter<'a>(repo: &'a mut ScriptRepo) -> impl Iterator<Item = &'a Tag> {
-    use std::collections::hash_map::Entry::*;
+#[derive(Debug, Default)]
+pub struct TagStat {
+    pub script_count: usize,
+    /// 被目前的選擇器篩掉的腳本數
+    pub hidden_count: usize,
+    pub last_major_time: NaiveDateTime,
+    pub last_exec_time: Option<NaiveDateTime>,
+}
 
-    let mut map: HashMap<&Tag, _> = Default::default();
-    for script in repo.iter_mut(Visibility::All) {
-        let script = script.into_inner();
+/// 統計每個標籤的使用狀況，依最後使用時間由新到舊排序
+pub fn known_tags_stat(repo: &ScriptRepo) -> Vec<(&Tag, TagStat)> {
+    let mut map: HashMap<&Tag, TagStat> = Default::default();
+    for (script, hidden) in repo.iter_with_select_hidden() {
         let date = script.last_major_time();
+        let exec_time = script.exec_time.as_ref().map(|t| **t);
         for tag in script.tags.iter() {
-            match map.entry(tag) {
-                Occupied(entry) => {
-                    let date_mut = entry.into_mut();
-                    *date_mut = std::cmp::max(date, *date_mut);
-                }
-                Vacant(entry) => {
-                    entry.insert(date);
-                }
+            let stat = map.entry(tag).or_default();
+            stat.script_count += 1;
+            if hidden {
+                stat.hidden_count += 1;
             }
+            stat.last_major_time = std::cmp::max(date, stat.last_major_time);
+            stat.last_exec_time = std::cmp::max(exec_time, stat.last_exec_time);
+        }
+    }
+    let mut v: Vec<_> = map.into_iter().collect();
+    v.sort_by_key(|(_, stat)| std::cmp::Reverse(stat.last_major_time));
+    v
+}
+
+pub fn known_tags_iter<'a>(repo: &'a mut ScriptRepo) -> impl Iterator<Item = &'a Tag> {
+    known_tags_stat(repo).into_iter().map(|(tag, _)| tag)
+}
+
+/// 找出設定檔中（選擇器、警示標籤）有提及，卻沒有任何腳本帶著的標籤
+///
+/// 腳本的標籤會先經過標籤登記的隱含展開，且父標籤會選中其子孫標籤
+pub fn orphan_config_tags<'a>(repo: &ScriptRepo, conf: &'a Config) -> Vec<(&'a Tag, String)> {
+    let mut all_tags: TagSet = Default::default();
+    for (script, _) in repo.iter_with_select_hidden() {
+        all_tags.extend(conf.tag_registry.expand(&script.tags).iter().cloned());
+    }
+    let is_orphan = |tag: &Tag| !all_tags.iter().any(|t| t.is_under(tag));
+
+    let mut ret = vec![];
+    for selector in conf.tag_selectors.iter() {
+        for tag in selector.content.iter_tags().filter(|t| is_orphan(t)) {
+            ret.push((tag, format!("tag selector {}", selector.name)));
+        }
+    }
+    for tag in conf.main_tag_selector.iter_tags().filter(|t| is_orphan(t)) {
+        ret.push((tag, "main tag selector".to_owned()));
+    }
+    for tag in conf.caution_tags.iter_tags().filter(|t| is_orphan(t)) {
+        ret.push((tag, "caution tags".to_owned()));
+    }
+    ret
+}
+
+/// 找出所有標籤都只是由命名空間而來的腳本，例如帶著 `util` 標籤的 `util/git`
+pub fn namespace_only_scripts(repo: &ScriptRepo) -> Vec<&ScriptInfo> {
+    let mut v: Vec<_> = repo
+        .iter_with_select_hidden()
+        .map(|(script, _)| script)
+        .filter(|script| {
+            let namespaces = script.name.namespaces();
+            if namespaces.is_empty() || script.tags.is_empty() {
+                return false;
+            }
+            script.tags.iter().all(|tag| {
+                let mut prefix = String::new();
+                namespaces.iter().any(|ns| {
+                    if !prefix.is_empty() {
+                        prefix.push('/');
+                    }
+                    prefix.push_str(ns);
+                    tag.as_ref() == *ns || tag.as_ref() == prefix
+                })
+            })
+        })
+        .collect();
+    v.sort_by_key(|script| script.name.key().into_owned());
+    v
+}
+
+/// 檢查別名是否遮蔽了同名的子命令或腳本。展開成同名子命令的別名（如 `ls = ["ls", "-l"]`）是刻意的，不算遮蔽
+pub fn alias_shadowing(repo: &ScriptRepo, name: &str, after: &[String]) -> Vec<String> {
+    let mut ret = vec![];
+    let wraps_itself = after.first().map(String::as_str) == Some(name);
+    if let Some(subcmd) = find_subcmd(name).filter(|_| !wraps_itself) {
+        ret.push(format!("alias `{}` shadows subcommand `{}`", name, subcmd));
+    }
+    if repo.iter().any(|info| info.name.key() == name) {
+        ret.push(format!("alias `{}` shadows script `{}`", name, name));
+    }
+    ret
+}
+
+/// `hs history export` 輸出的一行，附上腳本名稱以便在不同的家目錄間搬移
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HistoryLine {
+    pub name: String,
+    /// 複製事件的來源在此亦以名稱表示
+    #[serde(flatten)]
+    pub event: RawEvent,
+}
+
+/// 將事件以 JSON Lines 寫出，回傳寫出的行數
+pub fn export_history(
+    events: Vec<RawEvent>,
+    names: &HashMap<i64, String>,
+    w: &mut impl std::io::Write,
+) -> Result<usize> {
+    let mut count = 0;
+    for mut event in events.into_iter() {
+        let Some(name) = names.get(&event.script_id) else {
+            continue;
+        };
+        if event.ty == EventType::Copy {
+            let from = event.content.as_deref().and_then(|id| id.parse().ok());
+            if let Some(from) = from.and_then(|id| names.get(&id)) {
+                event.content = Some(from.clone());
+            }
+        }
+        let line = HistoryLine {
+            name: name.clone(),
+            event: RawEvent {
+                script_id: 0,
+                ..event
+            },
+        };
+        serde_json::to_writer(&mut *w, &line)?;
+        writeln!(w)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// 讀入 JSON Lines 並將腳本名稱換成本地的 id，本地不存在的腳本其事件會被略過
+/// 回傳可匯入的事件及被略過的腳本名稱
+pub fn parse_history_lines(
+    r: impl std::io::BufRead,
+    ids: &HashMap<String, i64>,
+) -> Result<(Vec<RawEvent>, Vec<String>)> {
+    let mut events = vec![];
+    let mut unknown = vec![];
+    for (i, line) in r.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let to_err =
+            |e: serde_json::Error| FormatCode::HistoryLine.to_err(format!("line {}: {}", i + 1, e));
+        let value: serde_json::Value = serde_json::from_str(&line).map_err(to_err)?;
+        if let Some(code) = value.get("type").and_then(|ty| ty.as_i64()) {
+            let ty = i8::try_from(code).ok().and_then(EventType::from_code);
+            if ty.is_none() {
+                log::warn!("第 {} 行有未知的事件類型 {}", i + 1, code);
+                continue;
+            }
+        }
+        let HistoryLine { name, mut event } = serde_json::from_value(value).map_err(to_err)?;
+        let Some(script_id) = ids.get(&name) else {
+            if !unknown.contains(&name) {
+                unknown.push(name);
+            }
+            continue;
+        };
+        event.script_id = *script_id;
+        if event.ty == EventType::Copy {
+            if let Some(from) = event.content.as_ref().and_then(|name| ids.get(name)) {
+                event.content = Some(from.to_string());
+            }
+        }
+        events.push(event);
+    }
+    Ok((events, unknown))
+}
+
+/// 將執行紀錄轉成重播用的 shell 指令，`records` 須由舊到新排序
+///
+/// 工作目錄改變時先 `cd` 過去，返回碼非零的執行會加上註解，找不到名字的腳本會被略過
+/// 環境變數只留下名字，值須由重播時的環境提供，以免把密碼之類的值寫進腳本；環境檔則照舊以路徑帶入
+/// 在另一個被重播的執行中被呼叫的執行，重播外層時自然會再跑一次，故略過
+pub fn replay_lines(records: &[EventRecord], names: &HashMap<i64, String>) -> Vec<String> {
+    let mut lines = vec![];
+    let mut last_dir = None;
+    let mut replayed = HashSet::default();
+    for record in records.iter() {
+        if let Some(parent) = record.parent_id.filter(|id| replayed.contains(id)) {
+            log::debug!("略過在執行 {} 中被呼叫的執行 {}", parent, record.id);
+            replayed.insert(record.id);
+            continue;
+        }
+        let Some(name) = names.get(&record.script_id) else {
+            log::warn!("略過不存在的腳本 {} 的執行紀錄", record.script_id);
+            continue;
+        };
+        replayed.insert(record.id);
+        if let Some(dir) = &record.dir {
+            if last_dir != Some(dir) {
+                lines.push(format!("cd {}", to_display_args(&dir.to_string_lossy())));
+                last_dir = Some(dir);
+            }
+        }
+
+        let mut line = String::new();
+        for env in record.envs.iter() {
+            line += &format!("{}=\"${{{}:?}}\" ", env.key, env.key);
+        }
+        line += "\"$HS_EXE\" -H \"$HS_HOME\" run ";
+        if let Some(profile) = &record.profile {
+            line += &format!("--profile {} ", to_display_args(profile));
+        }
+        for file in record.env_files.iter() {
+            line += &format!("--env-file {} ", to_display_args(&file.to_string_lossy()));
+        }
+        line += &format!("={}!", to_display_args(name));
+        for arg in record.args.iter() {
+            line += " ";
+            line += &to_display_args(arg);
+        }
+        if let Some(code) = record.code.filter(|c| *c != 0) {
+            line += &format!(" # exited with {}", code);
         }
+        lines.push(line);
     }
-    let mut v: Vec<_> = map.into_iter().map(|(k, v)| (k, v)).collect();
-    v.sort_by_key(|(_, v)| std::cmp::Reverse(*v));
-    v.into_iter().map(|(k, _)| k)
+    lines
 }
@@ -17,9 +17,11 @@ use std::path::{Path, PathBuf};
 use std::process::Command;
 use tokio::process::Command as AsyncCommand;
 
+pub mod config_edit;
 pub mod holder;
 pub mod main_util;
 pub mod shebang_handle;
+pub mod template_lib;
 pub mod writable;
 
 pub mod init_repo;
@@ -276,10 +278,11 @@ pub enum PrepareRespond {
     New { create_time: DateTime<Utc> },
     Old { last_hash: i64 },
 }
+/// `template` 附帶模版變數的值，見 [`crate::template_var`]
 pub fn prepare_script<T: AsRef<str>>(
     path: &Path,
     script: &ScriptInfo,
-    template: Option<String>,
+    template: Option<(String, &serde_json::Map<String, serde_json::Value>)>,
     content: &[T],
 ) -> Result<PrepareRespond> {
     log::info!("開始準備 {} 腳本內容……", script.name);
@@ -292,15 +295,18 @@ pub fn prepare_script<T: AsRef<str>>(
         let mut file = handle_fs_res(&[path], File::create(&path))?;
 
         let content = content.iter().map(|s| s.as_ref().split('\n')).flatten();
-        if let Some(template) = template {
+        if let Some((template, vars)) = template {
             let content: Vec<_> = content.collect();
-            let info = json!({
+            let mut info = json!({
                 "birthplace_in_home": birthplace_rel.is_some(),
                 "birthplace_rel": birthplace_rel,
                 "birthplace": birthplace,
                 "name": script.name.key().to_owned(),
                 "content": content,
             });
+            if let Some(info) = info.as_object_mut() {
+                info.extend(vars.clone());
+            }
             log::debug!("編輯模版資訊：{:?}", info);
             write_prepare_script(file, &path, &template, &info)?;
         } else {
@@ -1,4 +1,6 @@
+use crate::config::Config;
 use crate::error::{Error, Result};
+use crate::script_type::ScriptType;
 use shlex::Shlex;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
@@ -20,3 +22,48 @@ pub fn handle(p: &Path) -> Result<(String, Vec<String>)> {
     }
     Err(Error::PermissionDenied(vec![p.to_path_buf()]))
 }
+
+/// 取出 shebang 指定的直譯器名稱，`#!/usr/bin/env X` 的形式會取 `X`
+pub fn interpreter(p: &Path) -> Result<Option<String>> {
+    let (cmd, args) = match handle(p) {
+        Ok(t) => t,
+        Err(Error::PermissionDenied(_)) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let basename = |s: &str| s.rsplit('/').next().unwrap_or(s).to_owned();
+    let cmd = basename(&cmd);
+    if cmd != "env" {
+        return Ok(Some(cmd));
+    }
+    Ok(args
+        .iter()
+        .find(|a| !a.starts_with('-'))
+        .map(|a| basename(a)))
+}
+
+/// 依 shebang 猜測腳本類型：優先找與直譯器同名的類型，其次找執行指令為該直譯器的類型中名字最短者
+pub fn detect_type(p: &Path) -> Result<Option<ScriptType>> {
+    let Some(interpreter) = interpreter(p)? else {
+        return Ok(None);
+    };
+    log::debug!("{:?} 的直譯器為 {}", p, interpreter);
+    let conf = Config::get();
+    if let Some((ty, _)) = conf.types.iter().find(|(ty, _)| ty.as_ref() == interpreter) {
+        return Ok(Some(ty.clone()));
+    }
+    let mut candidates = vec![];
+    for ty in conf.types.keys() {
+        let ty_conf = conf.get_script_conf(ty)?;
+        let Some(exec_info) = &ty_conf.exec_info else {
+            continue;
+        };
+        let cmd = exec_info.cmd.rsplit('/').next().unwrap_or(&exec_info.cmd);
+        if cmd == interpreter {
+            candidates.push(ty);
+        }
+    }
+    let ty = candidates
+        .into_iter()
+        .min_by_key(|ty: &&ScriptType| (ty.as_ref().len(), ty.to_string()));
+    Ok(ty.cloned())
+}
@@ -0,0 +1,169 @@
+use super::{get_types, read_file, write_file};
+use crate::config::Config;
+use crate::error::{DisplayError, Error, Result};
+use crate::path::{self, HBS_EXT, TEMPLATE};
+use crate::script_type::{get_default_template, iter_default_templates, ScriptFullType};
+use std::path::{Path, PathBuf};
+
+#[derive(Display, Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TemplateStatus {
+    /// 與內建模版相同，或還沒有寫出檔案
+    #[display(fmt = "default")]
+    Default,
+    /// 與內建模版不同
+    #[display(fmt = "modified")]
+    Modified,
+    /// 沒有對應的內建模版
+    #[display(fmt = "custom")]
+    Custom,
+}
+
+/// 類型本身一定有內建模版（未知的類型用通用模版），子模版則只有少數幾個
+pub fn builtin_template(ty: &ScriptFullType) -> Option<&'static str> {
+    if ty.sub.is_none() {
+        return Some(get_default_template(ty));
+    }
+    iter_default_templates()
+        .find(|(t, _)| t == ty)
+        .map(|(_, tmpl)| tmpl)
+}
+
+fn read_template(ty: &ScriptFullType) -> Result<Option<String>> {
+    let p = path::get_template_path(ty)?;
+    if p.exists() {
+        Ok(Some(read_file(&p)?))
+    } else {
+        Ok(None)
+    }
+}
+
+pub fn status(ty: &ScriptFullType) -> Result<TemplateStatus> {
+    let content = read_template(ty)?;
+    Ok(match (builtin_template(ty), content) {
+        (None, _) => TemplateStatus::Custom,
+        (Some(_), None) => TemplateStatus::Default,
+        (Some(builtin), Some(content)) if builtin == content => TemplateStatus::Default,
+        (Some(_), Some(_)) => TemplateStatus::Modified,
+    })
+}
+
+/// 列出所有模版。被刪掉的子模版即使有內建版本也不列出，可用 `reset` 找回
+pub fn list() -> Result<Vec<(ScriptFullType, TemplateStatus)>> {
+    let mut types = get_types(true)?;
+    types.sort_by_key(|ty| ty.to_string());
+    let mut ret = Vec::with_capacity(types.len());
+    for ty in types.into_iter() {
+        let status = status(&ty)?;
+        ret.push((ty, status));
+    }
+    Ok(ret)
+}
+
+/// 回傳與內建模版的 unified diff，沒有差異時為空字串
+pub fn diff_default(ty: &ScriptFullType) -> Result<String> {
+    let builtin = builtin_template(ty).ok_or_else(|| Error::NoBuiltinTemplate(ty.to_string()))?;
+    let Some(content) = read_template(ty)? else {
+        return Ok(String::new());
+    };
+    let p = path::get_template_path(ty)?;
+    let diff = similar::TextDiff::from_lines(builtin, &content);
+    let diff = diff
+        .unified_diff()
+        .header(&format!("{} (built-in)", ty), &p.to_string_lossy())
+        .to_string();
+    Ok(diff)
+}
+
+pub fn reset(ty: &ScriptFullType) -> Result {
+    let builtin = builtin_template(ty).ok_or_else(|| Error::NoBuiltinTemplate(ty.to_string()))?;
+    let p = path::get_template_path(ty)?;
+    log::info!("將模版 {} 重置為內建模版", ty);
+    write_file(&p, builtin)
+}
+
+fn collect_dir(dir: &Path) -> Result<Vec<(ScriptFullType, PathBuf)>> {
+    fn parse_file(p: &Path) -> Option<String> {
+        let name = p.file_name()?.to_str()?;
+        name.strip_suffix(HBS_EXT).map(|s| s.to_owned())
+    }
+    // NOTE: 給的是另一個家目錄的話，改用其中的模版資料夾
+    let home_tmpl_dir = dir.join(TEMPLATE);
+    let dir = if home_tmpl_dir.is_dir() {
+        home_tmpl_dir
+    } else {
+        dir.to_owned()
+    };
+
+    let mut ret = vec![];
+    for entry in super::handle_fs_res(&[&dir], std::fs::read_dir(&dir))? {
+        let p = entry?.path();
+        if p.is_dir() {
+            let Some(ty) = p.file_name().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            for sub_entry in super::handle_fs_res(&[&p], std::fs::read_dir(&p))? {
+                let sub_p = sub_entry?.path();
+                match parse_file(&sub_p)
+                    .map(|sub| format!("{}/{}", ty, sub).parse::<ScriptFullType>())
+                {
+                    Some(Ok(full_ty)) => ret.push((full_ty, sub_p)),
+                    _ => log::warn!("略過非模版檔案 {:?}", sub_p),
+                }
+            }
+        } else {
+            match parse_file(&p).map(|ty| ty.parse::<ScriptFullType>()) {
+                Some(Ok(full_ty)) => ret.push((full_ty, p)),
+                _ => log::warn!("略過非模版檔案 {:?}", p),
+            }
+        }
+    }
+    ret.sort_by_key(|(ty, _)| ty.to_string());
+    Ok(ret)
+}
+
+/// 匯入單一模版檔案或整個模版資料夾，回傳實際匯入的模版。
+/// 匯入單一檔案時遇到問題直接報錯；匯入資料夾時則略過有問題的模版
+pub fn import(p: &Path, as_ty: Option<ScriptFullType>, force: bool) -> Result<Vec<ScriptFullType>> {
+    let is_dir = p.is_dir();
+    let sources = if is_dir {
+        if as_ty.is_some() {
+            log::warn!("匯入資料夾時忽略 --as");
+        }
+        collect_dir(p)?
+    } else {
+        let ty = match as_ty {
+            Some(ty) => ty,
+            None => {
+                let name = p.file_name().and_then(|s| s.to_str()).unwrap_or_default();
+                let name = name.strip_suffix(HBS_EXT).unwrap_or(name);
+                name.parse().map_err(DisplayError::into_err)?
+            }
+        };
+        vec![(ty, p.to_owned())]
+    };
+
+    let mut imported = vec![];
+    for (ty, src) in sources.into_iter() {
+        let res = (|| -> Result<bool> {
+            Config::get().get_script_conf(&ty.ty)?; // 確認類型存在與否
+            let content = read_file(&src)?;
+            let target = path::get_template_path(&ty)?;
+            if target.exists() && !force {
+                if read_file(&target)? == content {
+                    log::info!("模版 {} 內容相同，不須匯入", ty);
+                    return Ok(false);
+                }
+                return Err(Error::PathExist(target));
+            }
+            write_file(&target, &content)?;
+            Ok(true)
+        })();
+        match res {
+            Ok(true) => imported.push(ty),
+            Ok(false) => (),
+            Err(err) if is_dir => log::warn!("略過模版 {}：{:?}", ty, err),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(imported)
+}
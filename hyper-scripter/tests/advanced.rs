@@ -245,6 +245,66 @@ fn test_shell_alias() {
     ); // run the script without hs, should not have env variables
 }
 
+#[test]
+fn test_alias_placeholder() {
+    let _g = setup();
+
+    run!("e -T sh deploy/api | echo $@").unwrap();
+    run!("alias -d 'Deploy a service' dep run '=deploy/$1!' --env '${{2:-staging}}'").unwrap();
+    assert_eq!("--env staging", run!("dep api").unwrap());
+    assert_eq!("--env prod x", run!("dep api prod x").unwrap());
+    run!(allow_other_error: true, "dep").expect_err("missing $1");
+
+    run!("alias dep -d 'Deploy it'").unwrap();
+    assert_eq!(
+        "dep\trun =deploy/$1! --env ${2:-staging}\t# Deploy it",
+        run!("alias dep").unwrap()
+    );
+    run!("alias nope -d 'no such alias'").expect_err("alias doesn't exist");
+
+    run!("alias swap !echo $2 $1").unwrap();
+    assert_eq!("b a c", run!("swap a b c").unwrap());
+    assert_eq!("b a*b", run!(dir: "/", "swap 'a*b' b").unwrap());
+}
+
+#[test]
+fn test_alias_recursion() {
+    let _g = setup();
+
+    run!("e -T sh echo-args | echo $@").unwrap();
+    run!("alias ea echo-args").unwrap();
+    run!("alias ea2 ea a").unwrap();
+    run!("alias ea3 ea2 '$1' b").unwrap();
+    assert_eq!("a x b", run!("ea3 x").unwrap());
+
+    run!("alias ea ea3").unwrap();
+    run!(allow_other_error: true, "ea3 x").expect_err("alias loop");
+
+    // 展開成自己不算循環
+    run!("alias ea ea2").unwrap();
+    run!("alias ea2 ea2").unwrap();
+    run!("ea2 x").expect_err("ea2 is not a subcommand nor a script");
+    run!("alias ea2 echo-args -").unwrap();
+    assert_eq!("- x", run!("ea x").unwrap());
+
+    let explained = run!("alias --explain 'ea x'").unwrap();
+    let mut lines = explained.lines();
+    assert_eq!(Some("ea\t=> ea2 x"), lines.next());
+    assert_eq!(Some("ea2\t=> echo-args - x"), lines.next());
+    assert!(lines
+        .next()
+        .unwrap()
+        .contains(r#""script_query":"echo-args""#));
+    assert_eq!(None, lines.next());
+
+    run!("alias echo-args ls").unwrap();
+    let explained = run!("alias --explain echo-args").unwrap();
+    assert!(explained.contains("# alias `echo-args` shadows script `echo-args`"));
+    run!("alias cat ls").unwrap();
+    let explained = run!("alias --explain cat").unwrap();
+    assert!(explained.contains("# alias `cat` shadows subcommand `cat`"));
+}
+
 #[test]
 fn test_special_anonymous_query() {
     let _g = setup();
@@ -349,6 +409,119 @@ fn test_prev_option() {
     assert_eq!(run!("run --no-trace -p=args").unwrap(), "arg:");
 }
 
+#[test]
+fn test_env_profile() {
+    let _g = setup();
+    run!("e --no-template -t deploy ship | echo $1:$TARGET:$REGION").unwrap();
+    run!("e --no-template other | echo $TARGET").unwrap();
+
+    run!("config set env.REGION us").unwrap();
+    run!("config set env_profiles.stg.env.TARGET stg").unwrap();
+    run!("config set env_profiles.stg.tags deploy").unwrap();
+    run!("config set env_profiles.prod.env.TARGET prd").unwrap();
+    run!("config set env_profiles.prod.env.REGION '{{{{name}}}}-eu'").unwrap();
+    run!("config set env_profiles.prod.dangerous true").unwrap();
+
+    // 綁定到標籤的預設組合
+    assert_eq!("a:stg:us", run!("ship a").unwrap());
+    assert_eq!("", run!("other").unwrap());
+    assert_eq!(
+        "prd",
+        run!("run --profile prod --no-caution other").unwrap()
+    );
+    run!("run --profile no-such ship").expect_err("不存在的組合");
+
+    assert_eq!(
+        "b:prd:ship-eu",
+        run!("run --profile prod --no-caution ship b").unwrap()
+    );
+
+    // --previous 沿用上次的組合，明確指定的組合優先
+    assert_eq!("b:prd:ship-eu", run!("run --no-caution -p ship").unwrap());
+    assert_eq!("b:stg:us", run!("run --profile stg -p ship").unwrap());
+    assert_eq!("b:stg:us", run!("run -p ship").unwrap());
+    assert_eq!("b:stg:us", run!("run -p=args ship").unwrap());
+}
+
+#[test]
+fn test_env_file() {
+    let _g = setup();
+    let home = get_home();
+    write_file(
+        &home.join(".env"),
+        "export A=file_a\nB='b # b' # comment\nC=c1\n",
+    )
+    .unwrap();
+    write_file(&home.join("other.env"), "C=c2\n").unwrap();
+    run!(
+        "e --no-template env-file | # [HS_ENV_FILE]: ./.env
+# [HS_ENV]: A from process
+echo $A:$B:$C"
+    )
+    .unwrap();
+
+    assert_eq!("file_a:b # b:c1", run!(dir: home, "env-file").unwrap());
+    // 行程的環境變數優先
+    let custom_env = vec![("A".to_owned(), "proc".to_owned())];
+    assert_eq!(
+        "proc:b # b:c1",
+        run!(dir: home, custom_env: custom_env, "env-file").unwrap()
+    );
+    // 明確指定的檔案優先於腳本宣告的檔案
+    assert_eq!(
+        "file_a:b # b:c2",
+        run!(dir: home, "run --env-file other.env env-file").unwrap()
+    );
+    // 歷史只記錄環境檔的路徑，重播時重新讀取
+    let hist = run!("history export env-file").unwrap();
+    assert!(hist.contains("other.env"), "{}", hist);
+    assert!(!hist.contains("file_a") && !hist.contains("c2"), "{}", hist);
+    write_file(&home.join("other.env"), "C=c3\n").unwrap();
+    assert_eq!(
+        "file_a:b # b:c3",
+        run!(dir: home, "run -p env-file").unwrap()
+    );
+    assert_eq!("::c3", run!("run -p env-file").unwrap());
+    // 宣告的檔案不存在時略過，明確指定的則報錯
+    assert_eq!("::", run!("env-file").unwrap());
+    run!("run --env-file no-such.env env-file").expect_err("找不到環境檔");
+
+    // 以模版的 birthplace 綁定出生地的環境檔，在別處執行也讀得到
+    run!("types add bp --cmd bash").unwrap();
+    write_file(
+        &home.join(".hs_templates/bp.hbs"),
+        "# [HS_ENV_FILE]: {{birthplace}}/.env\necho $A\n",
+    )
+    .unwrap();
+    let custom_env = vec![("PWD".to_owned(), home.to_string_lossy().into_owned())];
+    run!(dir: home, custom_env: custom_env, "e -T bp born-here --fast").unwrap();
+    assert_eq!("file_a", run!("born-here").unwrap());
+}
+
+#[test]
+fn test_dry_run() {
+    let _g = setup();
+    run!("e --no-template dry | echo ran $1").unwrap();
+    run!("config set env.API_TOKEN s3cret").unwrap();
+    run!("config set env.MY_VAR visible").unwrap();
+
+    let out = run!("run --dry-run dry 'a b'").unwrap();
+    assert!(!out.contains("ran"), "不該真的執行：{}", out);
+    let lines: Vec<_> = out.lines().map(|l| l.trim()).collect();
+    assert!(lines[0].starts_with("pre-run: "));
+    assert!(lines[1].starts_with("command: "));
+    assert!(lines[1].ends_with("dry.sh 'a b'"));
+    assert!(lines.contains(&"+ MY_VAR=visible"));
+    assert!(lines.contains(&"+ NAME=dry"));
+    assert!(lines.contains(&"+ API_TOKEN=<redacted>"));
+    assert!(!out.contains("s3cret"));
+    assert_eq!("", run!("history show dry").unwrap(), "不該留下紀錄");
+
+    let out = run!("run --explain --dummy dry b").unwrap();
+    assert!(!out.contains("ran"), "不該真的執行：{}", out);
+    assert_eq!("b", run!("history show dry").unwrap());
+}
+
 #[test]
 fn test_script_name_conflict_dir_name() {
     let _g = setup();
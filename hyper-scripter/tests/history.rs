@@ -730,3 +730,424 @@ fn test_multi_history() {
         h.ls();
     }
 }
+
+#[test]
+fn test_history_search() {
+    let _g = setup();
+    run!("e deploy | [ \"$3\" != bad ]").unwrap();
+    run!("e -t hide secret | echo $@").unwrap();
+    let (dir, _) = init_dir("search");
+
+    run!("deploy --region eu-west-3 ok").unwrap();
+    run!(dir: &dir, "deploy --region us-east-1 bad").expect_err("應該執行失敗");
+    run!("run --dummy -a secret --region eu-west-3 hidden").unwrap();
+    run!("run --dummy deploy x").unwrap();
+
+    let search = |args: &str| -> Vec<(String, String, String)> {
+        let out = run!("history search {}", args).unwrap();
+        out.lines()
+            .map(|l| {
+                let v: Vec<_> = l.split('\t').collect();
+                assert_eq!(v.len(), 5, "格式不符 {}", l);
+                (v[0].to_owned(), v[3].to_owned(), v[4].to_owned())
+            })
+            .collect()
+    };
+    let t =
+        |name: &str, args: &str, code: &str| (name.to_owned(), args.to_owned(), code.to_owned());
+
+    assert_eq!(
+        search("-- '--region eu-west-3'"),
+        vec![t("deploy", "--region eu-west-3 ok", "0")]
+    );
+    assert_eq!(
+        search("-a -- '--region eu-west-3'"),
+        vec![
+            t("secret", "--region eu-west-3 hidden", "-"),
+            t("deploy", "--region eu-west-3 ok", "0")
+        ]
+    );
+    // 過短的字串無法走全文檢索，但仍要找得到
+    assert_eq!(search("x"), vec![t("deploy", "x", "-")]);
+    assert_eq!(
+        search("'west-3 ok'"),
+        vec![t("deploy", "--region eu-west-3 ok", "0")]
+    );
+    assert_eq!(search("'3 hidden'"), vec![]);
+    // 區分大小寫，且比對後才截斷
+    assert_eq!(search("REGION"), vec![]);
+    assert_eq!(
+        search("--limit 1 region"),
+        vec![t("deploy", "--region us-east-1 bad", "1")]
+    );
+
+    assert_eq!(
+        search("--failed"),
+        vec![t("deploy", "--region us-east-1 bad", "1")]
+    );
+    assert_eq!(
+        search(&format!("--dir {} region", dir)),
+        vec![t("deploy", "--region us-east-1 bad", "1")]
+    );
+    assert_eq!(search("--limit 1").len(), 1);
+    assert_eq!(search("--since 1h").len(), 3);
+    assert_eq!(search("--since 2000-01-01").len(), 3);
+    run!("history search --since yesterday").expect_err("時間格式錯誤");
+}
+
+#[test]
+fn test_history_status() {
+    let _g = setup();
+    run!("e check | [ \"$1\" != bad ]").unwrap();
+
+    run!("check good").unwrap();
+    run!("check bad").expect_err("應該執行失敗");
+
+    let recorded = run!("history show check --with-status").unwrap();
+    assert_list(&recorded, &["bad\tfailed 1", "good\tok"]);
+    let recorded = run!("history show check --failed").unwrap();
+    assert_list(&recorded, &["bad"]);
+    let recorded = run!("history show check --succeeded").unwrap();
+    assert_list(&recorded, &["good"]);
+    run!("history show check --failed --succeeded").expect_err("互斥的選項");
+
+    // 一般的 --previous 會重播失敗的參數，last-success 則略過它
+    run!("run -p check").expect_err("重播了失敗的參數");
+    run!("run -p=last-success check").unwrap();
+    let recorded = run!("history show check --with-status --limit 1").unwrap();
+    assert_list(&recorded, &["good\tok"]);
+
+    // 沒有返回碼的執行視為被中斷
+    run!("run --dummy check dummy").unwrap();
+    let recorded = run!("history show check --with-status --limit 1").unwrap();
+    assert_list(&recorded, &["dummy\tinterrupted"]);
+}
+
+#[test]
+fn test_history_log() {
+    let _g = setup();
+    run!("e log-a | echo $1").unwrap();
+    run!("e log-b | exit 3").unwrap();
+    run!("log-a x").unwrap();
+    run!(allow_other_error: true, "log-b").expect_err("應該執行失敗");
+
+    let log = |args: &str| -> Vec<String> {
+        let out = run!("history log {}", args).unwrap();
+        out.lines()
+            .map(|l| {
+                let v: Vec<_> = l.split('\t').collect();
+                if v.len() == 1 {
+                    return v[0].to_owned();
+                }
+                assert_eq!(v.len(), 4, "格式不符 {}", l);
+                format!("{} {} {}", v[1], v[2], v[3]).trim().to_owned()
+            })
+            .collect()
+    };
+
+    let events = log("");
+    let tail = &events[events.len() - 4..];
+    assert_eq!(
+        tail,
+        &[
+            "exec log-a x",
+            "exit log-a ok",
+            "exec log-b",
+            "exit log-b failed 3"
+        ]
+    );
+    assert!(events.contains(&"edit log-a".to_owned()));
+    assert!(events.contains(&"edit log-b".to_owned()));
+
+    assert_eq!(log("--limit 2"), &["exec log-b", "exit log-b failed 3"]);
+    assert_eq!(
+        log("--limit 2 --offset 2"),
+        &["exec log-a x", "exit log-a ok"]
+    );
+    assert_eq!(log("--until 2000-01-01"), Vec::<String>::new());
+    assert_eq!(log("--since 1h").len(), events.len());
+
+    let by_day = log("--by-day --limit 1");
+    assert_eq!(by_day.len(), 2);
+    assert!(by_day[0].starts_with("-- "));
+
+    run!("history neglect log-b").unwrap();
+    assert_eq!(log("--limit 1"), &["exit log-a ok"], "被忽視的腳本不該出現");
+    let out = run!("-a history log --limit 1").unwrap();
+    assert!(out.ends_with("neglect\tlog-b\t"), "{}", out);
+}
+
+#[test]
+fn test_structural_events() {
+    let _g = setup();
+    run!("e st-a | echo $1").unwrap();
+    run!("st-a x").unwrap();
+
+    let log = |args: &str| -> Vec<String> {
+        let out = run!("history log --limit 100 {}", args).unwrap();
+        out.lines()
+            .filter_map(|l| {
+                let v: Vec<_> = l.split('\t').collect();
+                assert_eq!(v.len(), 4, "格式不符 {}", l);
+                if matches!(v[1], "read" | "edit") {
+                    return None;
+                }
+                Some(format!("{} {} {}", v[1], v[2], v[3]).trim().to_owned())
+            })
+            .collect()
+    };
+
+    run!("mv st-a st-b").unwrap();
+    run!("mv st-b -t +x,y").unwrap();
+    run!("mv st-b -T js").unwrap();
+    run!("cp st-b st-c").unwrap();
+    run!("rm st-b").unwrap();
+    run!("-s remove mv st-b -t +^remove").unwrap();
+    assert_eq!(
+        log(""),
+        &[
+            "exec st-b x",
+            "exit st-b ok",
+            "rename st-b st-a -> st-b",
+            "retag st-b +x +y",
+            "type st-b sh -> js",
+            "copy st-c from st-b",
+            "delete st-b",
+            "restore st-b",
+        ]
+    );
+
+    run!("rm --purge --keep-history st-b").unwrap();
+    run!("rm --purge st-c").unwrap();
+    assert_eq!(log(""), Vec::<String>::new(), "刪掉的腳本不該出現");
+    let purged = log("--purged");
+    assert_eq!(purged.first().unwrap(), "exec st-b (purged) x");
+    assert_eq!(purged.last().unwrap(), "delete st-b (purged)");
+    assert!(
+        purged.iter().all(|l| !l.contains("st-c")),
+        "沒有保留歷史的腳本不該出現"
+    );
+
+    run!("history tidy").unwrap();
+    assert_eq!(log("--purged").last().unwrap(), "delete st-b (purged)");
+}
+
+#[test]
+fn test_history_tidy_retention() {
+    let _g = setup();
+    let execs = |name: &str| -> Vec<String> {
+        let out = run!("history log --limit 100").unwrap();
+        out.lines()
+            .filter_map(|l| {
+                let v: Vec<_> = l.split('\t').collect();
+                (v[1] == "exec" && v[2] == name).then(|| v[3].to_owned())
+            })
+            .collect()
+    };
+    let runs = ["1", "1", "1", "2", "2", "1", "3"];
+
+    run!("e tidy-a | echo $1").unwrap();
+    for arg in runs {
+        run!("tidy-a {}", arg).unwrap();
+    }
+    let out = run!("history tidy --dry-run").unwrap();
+    assert!(out.starts_with("would remove 13 events"), "{}", out);
+    assert_eq!(execs("tidy-a").len(), 7, "dry run 不該刪除任何東西");
+
+    // 預設只保留每組參數最新的一筆
+    run!("history tidy").unwrap();
+    assert_eq!(execs("tidy-a"), &["2", "1", "3"]);
+
+    run!("config set history_retention.collapse_repeats true").unwrap();
+    run!("e tidy-b | echo $1").unwrap();
+    for arg in runs {
+        run!("tidy-b {}", arg).unwrap();
+    }
+    let out = run!("history tidy --dry-run").unwrap();
+    assert!(out.starts_with("would remove 6 events"), "{}", out);
+    run!("history tidy").unwrap();
+    assert_eq!(execs("tidy-b"), &["1 (x3)", "2 (x2)", "1", "3"]);
+    assert_eq!(execs("tidy-a"), &["2", "1", "3"]);
+    assert_eq!(run!("history show tidy-b").unwrap(), "3\n1\n2");
+
+    run!("config set history_retention.keep_execs 2").unwrap();
+    let out = run!("history tidy").unwrap();
+    assert!(out.starts_with("removed 5 events"), "{}", out);
+    assert_eq!(execs("tidy-b"), &["1", "3"]);
+    assert_eq!(execs("tidy-a"), &["1", "3"]);
+    let out = run!("history tidy --dry-run").unwrap();
+    assert!(out.starts_with("would remove 0 events"), "{}", out);
+}
+
+#[test]
+fn test_history_export_import() {
+    let _g = setup();
+    let other = get_home().with_file_name("history-export-import-other");
+    let _ = std::fs::remove_dir_all(&other);
+    let file = other.with_extension("jsonl");
+
+    run!("e ex-a | echo $1").unwrap();
+    run!("e ex-gone | echo gone").unwrap();
+    run!("ex-a 1").unwrap();
+    run!("ex-a 2").unwrap();
+    run!("ex-gone").unwrap();
+    let exported = run!("history export ex-*").unwrap();
+    assert!(!exported.contains("\"script_id\""), "應以名稱取代 id");
+    std::fs::write(&file, exported).unwrap();
+    let exported = run!("history export --since 2000-01-01 ex-a").unwrap();
+    assert!(!exported.contains("ex-gone"));
+
+    run!(silent: true, home: &other, "ls").unwrap(); // create the home
+    let conf = ".config.toml";
+    std::fs::copy(get_home().join(conf), other.join(conf)).unwrap(); // 避免編輯器堵住
+    run!(home: &other, "e ex-a | echo $1").unwrap();
+    run!(home: &other, "ex-a 3").unwrap();
+    let out = run!(home: &other, "history import --dedupe {}", file.display()).unwrap();
+    assert!(out.starts_with("imported "), "{}", out);
+    assert!(
+        out.ends_with("skipped events of unknown scripts: ex-gone"),
+        "{}",
+        out
+    );
+    assert_eq!(run!(home: &other, "history show ex-a").unwrap(), "3\n2\n1");
+    let log = run!(home: &other, "history log").unwrap();
+    assert!(log.contains("exit\tex-a\tok"), "{}", log);
+
+    let out = run!(home: &other, "history import --dedupe {}", file.display()).unwrap();
+    assert!(out.starts_with("imported 0 events"), "{}", out);
+    assert_eq!(run!(home: &other, "history show ex-a").unwrap(), "3\n2\n1");
+
+    run!(home: &other, "history rm ex-a -- 1").unwrap();
+    assert_eq!(
+        run!(home: &other, "run -p ex-a").unwrap(),
+        "2",
+        "匯入的事件應能正常使用"
+    );
+}
+
+#[test]
+fn test_history_undo() {
+    let _g = setup();
+    run!("e undo-a | echo $1").unwrap();
+    for arg in ["1", "2", "3"] {
+        run!("undo-a {}", arg).unwrap();
+    }
+    assert_eq!(run!("history undo --list").unwrap(), "");
+
+    run!("history rm undo-a -- 1..").unwrap();
+    assert_eq!(run!("history show undo-a").unwrap(), "");
+    run!("history undo").unwrap();
+    assert_eq!(run!("history show undo-a").unwrap(), "3\n2\n1");
+    assert_eq!(run!("run -p undo-a").unwrap(), "3", "最新時間應該被復原");
+
+    run!("history rm undo-a -- 1").unwrap();
+    run!("history rm undo-a -- 1").unwrap();
+    assert_eq!(run!("history show undo-a").unwrap(), "1");
+    let list = run!("history undo --list").unwrap();
+    assert_eq!(list.lines().count(), 2, "{}", list);
+    assert!(list.lines().all(|l| l.contains("\trm\t")), "{}", list);
+    run!("history undo 2").unwrap();
+    assert_eq!(run!("history show undo-a").unwrap(), "3\n2\n1");
+
+    assert_eq!(run!("history undo --list").unwrap(), "");
+
+    run!("e undo-b | $HS_EXE -H $HS_HOME history amend $HS_RUN_ID amended").unwrap();
+    run!("undo-b orig").unwrap();
+    assert_eq!(run!("history show undo-b").unwrap(), "amended");
+    let list = run!("history undo --list").unwrap();
+    assert!(list.contains("\tamend\t1 events\t"), "{}", list);
+    run!("history undo").unwrap();
+    assert_eq!(run!("history show undo-b").unwrap(), "orig");
+}
+
+#[test]
+fn test_jsonl_backend() {
+    let _g = setup();
+    run!("config set history_backend jsonl").unwrap();
+    run!("e jl-a | echo $1").unwrap();
+    run!("e jl-b | $HS_EXE -H $HS_HOME history amend $HS_RUN_ID amended").unwrap();
+    for arg in ["1", "2", "2"] {
+        run!("jl-a {}", arg).unwrap();
+    }
+    run!("jl-b orig").unwrap(); // 子程序修改了檔案，父程序之後寫入時須先讀進來
+    assert_eq!(run!("history show jl-a").unwrap(), "2\n1");
+    assert_eq!(run!("history show jl-b").unwrap(), "amended");
+    assert_eq!(run!("run -p").unwrap(), "", "最新的執行應為 jl-b");
+
+    let file = get_home().join(".script_history.jsonl");
+    let lines = std::fs::read_to_string(&file).unwrap();
+    assert!(
+        lines.lines().all(|l| l.starts_with("{\"op\":")),
+        "{}",
+        lines
+    );
+
+    run!("history rm jl-a -- 1").unwrap();
+    assert_eq!(run!("history show jl-a").unwrap(), "1");
+    run!("history undo").unwrap();
+    assert_eq!(run!("history show jl-a").unwrap(), "2\n1");
+
+    run!("config set history_retention.collapse_repeats true").unwrap();
+    let out = run!("history tidy").unwrap();
+    assert!(out.starts_with("removed 4 events"), "{}", out); // 兩支腳本各有一次重覆執行
+    let compacted = std::fs::read_to_string(&file).unwrap();
+    assert!(compacted.len() < lines.len(), "{}", compacted);
+    assert_eq!(run!("history show jl-a").unwrap(), "2\n1");
+    assert_eq!(
+        run!("history log --limit 1").unwrap().split('\t').nth(3),
+        Some("ok")
+    );
+}
+
+#[test]
+fn test_history_to_script() {
+    let _g = setup();
+    let (dir_a, _) = init_dir("to-script-a");
+    let (dir_b, _) = init_dir("to-script-b");
+    run!("e ts-a | echo a $@").unwrap();
+    run!(
+        "e --no-template ts-b | 
+        # [HS_ENV]: TS_ENV
+        echo b $TS_ENV $(basename $(pwd))
+        exit 1
+        "
+    )
+    .unwrap();
+    run!("e ts-c | echo c").unwrap();
+    run!("e ts-nested | \"$HS_EXE\" -H \"$HS_HOME\" =ts-c!").unwrap();
+    run!(dir: &dir_a, "ts-a 'x y' 1").unwrap();
+    run!(dir: &dir_a, "ts-nested").unwrap();
+    let env = || vec![("TS_ENV".to_owned(), "it's".to_owned())];
+    run!(dir: &dir_b, custom_env: env(), "ts-b").expect_err("ts-b 應該失敗");
+    run!(dir: &dir_a, "--humble ts-c").unwrap();
+
+    run!("history to-script --since 1h -t +runbook ts-run").unwrap();
+    let content = run!("cat ts-run").unwrap();
+    let lines: Vec<_> = content.lines().rev().take(5).collect();
+    assert_eq!(
+        lines,
+        [
+            "TS_ENV=\"${TS_ENV:?}\" \"$HS_EXE\" -H \"$HS_HOME\" run =ts-b! # exited with 1",
+            format!("cd {}", dir_b).as_str(),
+            "\"$HS_EXE\" -H \"$HS_HOME\" run =ts-nested!",
+            "\"$HS_EXE\" -H \"$HS_HOME\" run =ts-a! 'x y' 1",
+            format!("cd {}", dir_a).as_str(),
+        ],
+        "謙卑的執行及巢狀的執行不應出現：{}",
+        content
+    );
+    assert!(!content.contains("it's"), "不應記下環境變數的值");
+    assert_ls(vec!["ts-run"], Some("runbook"), None);
+    assert_eq!(
+        run!(custom_env: env(), "ts-run")
+            .expect_err("ts-b 失敗時應停下")
+            .msg,
+        "a x y 1\nc\nb it's to-script-b"
+    );
+    run!("ts-run").expect_err("沒給環境變數時應停下");
+
+    run!("history to-script --since 1h ts-run").expect_err("不該覆蓋既有腳本");
+    run!("history to-script --since 1h --dir {} ts-run2", dir_b).unwrap();
+    assert!(run!("cat ts-run2").unwrap().ends_with("exited with 1"));
+    run!("history to-script --since 1h --until 2h ts-none").expect_err("時間內沒有執行");
+}
@@ -3,6 +3,7 @@
 mod tool;
 
 use hyper_scripter::{
+    config::{config_file, Recent},
     path::{normalize_path, HS_REDIRECT},
     util::main_util::prepare_pre_run,
 };
@@ -42,6 +43,335 @@ fn test_tags() {
     run!("tesjs").expect_err("命名空間變成標籤了？");
 }
 
+#[test]
+fn test_hierarchical_tags() {
+    let _g = setup();
+    run!("e -t team/infra/db db-script | echo \"{}\"", MSG).unwrap();
+    assert_eq!(MSG, run!("-s team db-script").unwrap());
+    assert_eq!(MSG, run!("-s team/infra db-script").unwrap());
+    run!("-s team/inf db-script").expect_err("標籤前綴不該被當成父標籤！");
+    run!("-s team/infra/db/x db-script").expect_err("子標籤不該選中父標籤！");
+    run!("e -t team/../x ?").expect_err("不合法的標籤層級");
+}
+
+#[test]
+fn test_bulk_tags() {
+    let _g = setup();
+    run!("e -t x bulk-a | echo a").unwrap();
+    run!("e -t x/y bulk-b | echo b").unwrap();
+    run!("e bulk-c | echo c").unwrap();
+    run!("tags x --name bulk-selector").unwrap();
+
+    run!("tags add --dry-run t =bulk-a =bulk-b").unwrap();
+    run!("-s t =bulk-a").expect_err("dry run 不該真的加上標籤");
+    run!("tags add -y t =bulk-a =bulk-b").unwrap();
+    assert_eq!("a", run!("-s t =bulk-a").unwrap());
+    assert_eq!("b", run!("-s t =bulk-b").unwrap());
+    run!("-s t =bulk-c").expect_err("沒被選中的腳本也被加上標籤了");
+
+    run!("tags remove -y t bulk-*").unwrap();
+    run!("-s t =bulk-a").expect_err("標籤沒被移除");
+    run!("-s t =bulk-b").expect_err("標籤沒被移除");
+
+    run!("tags rename -y x z").unwrap();
+    assert_eq!("a", run!("-s z =bulk-a").unwrap());
+    assert_eq!("b", run!("-s z/y =bulk-b").unwrap());
+    run!("-s x =bulk-b").expect_err("舊的標籤還在");
+    let conf = load_conf();
+    let selector = conf
+        .tag_selectors
+        .iter()
+        .find(|s| s.name == "bulk-selector")
+        .unwrap();
+    assert_eq!("z", selector.content.to_string());
+}
+
+#[test]
+fn test_query_preset() {
+    let _g = setup();
+    run!("e -t daily preset-a | echo a").unwrap();
+    run!("e -t daily preset-b | echo b").unwrap();
+    run!("e -t other preset-c | echo c").unwrap();
+
+    run!("query save morning -- -s daily,^remove").unwrap();
+    run!("query save pair -- =preset-a =preset-c").unwrap();
+    run!("query save nested -- @@pair").expect_err("查詢預設不該巢狀");
+    let conf = load_conf();
+    assert_eq!(conf.query_presets["pair"].args, ["=preset-a", "=preset-c"]);
+    assert!(!conf.query_presets.contains_key("nested"));
+
+    let ls = |args: &str| -> Vec<String> {
+        let out = run!("ls --grouping none --plain --format {{{{name}}}} {}", args).unwrap();
+        let mut v: Vec<_> = out.split_whitespace().map(str::to_owned).collect();
+        v.sort();
+        v
+    };
+    assert_eq!(ls("@@morning"), ["preset-a", "preset-b"]);
+    assert_eq!(ls("@@pair preset-b"), ["preset-a", "preset-b", "preset-c"]);
+    // 預設中的根參數不受當下選擇器影響
+    assert_eq!(ls("-s other @@morning"), ["preset-a", "preset-b"]);
+    run!("ls @@nothing").expect_err("不存在的預設");
+
+    // 篩選過的預設只在通過篩選的腳本中模糊搜尋
+    run!("query save fuzz-daily -- -s daily preset").unwrap();
+    assert_eq!(ls("@@fuzz-daily"), ["preset-b"]);
+    run!("query save fuzz-none -- -s daily prst-c").unwrap();
+    run!("ls @@fuzz-none").expect_err("preset-c 未通過篩選");
+
+    run!("rm @@pair").unwrap();
+    assert_eq!(ls("@@morning"), ["preset-b"]);
+    run!("query rm pair").unwrap();
+    run!("query rm pair").expect_err("預設已被刪除");
+}
+
+#[test]
+fn test_project_config() {
+    let _g = setup();
+    let project = get_home().join("project");
+    let sub_dir = project.join("sub");
+    std::fs::create_dir_all(&sub_dir).unwrap();
+    let project_conf = project.join(".hs.toml");
+    write(
+        &project_conf,
+        "[env]\nPROJECT_VAR = \"proj\"\n[alias]\nproj-ls = { after = [\"ls\", \"--plain\"] }\n",
+    )
+    .unwrap();
+
+    run!("e proj-script | echo \"var=${{PROJECT_VAR:-}}\"").unwrap();
+    assert_eq!("var=", run!("=proj-script").unwrap());
+    assert_eq!("var=proj", run!(dir: &sub_dir, "=proj-script").unwrap());
+    run!(dir: &sub_dir, "proj-ls").unwrap();
+    run!("proj-ls").expect_err("專案外不該有專案別名");
+
+    let explain = run!(dir: &sub_dir, "config --explain").unwrap();
+    let line = explain
+        .lines()
+        .find(|l| l.starts_with("env.PROJECT_VAR\t"))
+        .unwrap();
+    assert!(line.ends_with(&*project_conf.to_string_lossy()));
+
+    // 寫入設定檔時不該把專案設定層一起寫進去
+    run!(dir: &sub_dir, "alias proj-alias ls").unwrap();
+    let conf = load_conf();
+    assert!(conf.alias.contains_key("proj-alias"));
+    assert!(!conf.alias.contains_key("proj-ls"));
+    assert!(!conf.env.contains_key("PROJECT_VAR"));
+}
+
+#[test]
+fn test_config_edit() {
+    let _g = setup();
+    let file = config_file(get_home());
+    let content = std::fs::read_to_string(&file).unwrap();
+    write(&file, format!("# 我的註解\n{}", content)).unwrap();
+
+    run!("config set recent 7").unwrap();
+    assert_eq!("7", run!("config get recent").unwrap());
+    run!("config set alias.edit-alias.after '[\"edit\", \"-f\"]'").unwrap();
+    run!("config set env.EDIT_VAR 1").unwrap();
+    assert_eq!("1", run!("config get env.EDIT_VAR").unwrap());
+
+    let conf = load_conf();
+    assert_eq!(conf.recent, Recent::Days(7));
+    assert_eq!(conf.alias["edit-alias"].after, ["edit", "-f"]);
+    assert_eq!(conf.env["EDIT_VAR"], "1");
+    let content = std::fs::read_to_string(&file).unwrap();
+    assert!(content.starts_with("# 我的註解\n"), "註解不見了");
+
+    run!("config set no-such.key 1").expect_err("不存在的設定");
+    run!("config set recent abc").expect_err("型別錯誤");
+    run!("config set main_tag_selector +@no-such-type").expect_err("不存在的類型");
+    run!("config set alias.loop-a.after '[\"loop-b\"]'").unwrap();
+    run!("config set alias.loop-b.after '[\"loop-a\"]'").expect_err("循環的別名");
+    run!("config set env.BAD_TMPL '{{{{#if x}}}}'").expect_err("壞掉的模版");
+    assert_eq!(load_conf().recent, Recent::Days(7));
+
+    run!("config unset alias.edit-alias").unwrap();
+    run!("config get alias.edit-alias").expect_err("別名沒被刪掉");
+    run!("config unset recent").expect_err("必要的設定不能刪除");
+    run!("config validate").unwrap();
+
+    let mut conf = load_conf();
+    conf.main_tag_selector = "+@no-such-type".parse().unwrap();
+    conf.store().unwrap();
+    let problems = run!("config validate").expect_err("應檢查出不存在的類型");
+    assert!(problems.msg.contains("no-such-type"));
+}
+
+#[test]
+fn test_type_add_rm() {
+    let _g = setup();
+    run!("types add bash2 --from sh --ext bash").unwrap();
+    run!("types add bash2 --from sh").expect_err("重複新增類型");
+    run!("types add bad --from no-such-type").expect_err("繼承不存在的類型");
+    let conf = load_conf();
+    let bash2 = "bash2".parse().unwrap();
+    assert_eq!(conf.types[&bash2].extends, Some("sh".parse().unwrap()));
+    assert_eq!(run!("types sh").unwrap(), run!("types bash2").unwrap());
+
+    run!("e -T bash2 inherit-script | echo \"{}\"", MSG).unwrap();
+    assert_eq!(MSG, run!("=inherit-script").unwrap());
+    assert!(run!("which =inherit-script").unwrap().ends_with(".bash"));
+
+    run!("types rm sh").expect_err("被繼承的類型不能刪");
+    run!("types rm bash2").expect_err("仍有腳本使用的類型不能刪");
+    run!("types rm bash2 --migrate-to bash2").expect_err("不能遷移到自己");
+    // 任一腳本搬不過去時，所有腳本都不該被遷移
+    run!("e -T bash2 blocked | echo blocked").unwrap();
+    let blocker = get_home().join("blocked.sh");
+    write(&blocker, "echo blocker").unwrap();
+    run!("types rm bash2 --migrate-to sh").expect_err("遷移後撞到既存檔案");
+    assert!(load_conf().types.contains_key(&bash2));
+    assert!(run!("which =inherit-script").unwrap().ends_with(".bash"));
+    assert!(run!("which =blocked").unwrap().ends_with(".bash"));
+    std::fs::remove_file(&blocker).unwrap();
+    run!("types rm bash2 --migrate-to sh").unwrap();
+    assert!(!load_conf().types.contains_key(&bash2));
+    assert_eq!(MSG, run!("=inherit-script").unwrap());
+    assert!(run!("which =inherit-script").unwrap().ends_with(".sh"));
+
+    let file = get_home().join("detect-me");
+    write(&file, "#!/usr/bin/env bash\necho hi").unwrap();
+    assert_eq!(
+        "sh",
+        run!("types detect {}", file.to_string_lossy()).unwrap()
+    );
+    write(&file, "#!/usr/bin/ruby\nputs 1").unwrap();
+    assert_eq!(
+        "rb",
+        run!("types detect {}", file.to_string_lossy()).unwrap()
+    );
+    write(&file, "no shebang").unwrap();
+    run!("types detect {}", file.to_string_lossy()).expect_err("沒有 shebang");
+}
+
+#[test]
+fn test_template_vars() {
+    let _g = setup();
+    run!("types add tv --cmd bash").unwrap();
+    let tmpl = get_home().join(".hs_templates/tv.hbs");
+    write(
+        &tmpl,
+        "{{!-- hs-vars: who:enum(world,hs) count:int=2 --}}\necho {{who}} {{count}}\n",
+    )
+    .unwrap();
+
+    run!("e -T tv var-script --fast").expect_err("沒給沒有預設值的變數");
+    run!("e -T tv var-script --fast --var who=nobody").expect_err("不在選項中");
+    run!("e -T tv var-script --fast --var who=hs --var nope=1").expect_err("沒有宣告的變數");
+    run!("e -T tv var-script --fast --var count=x --var who=hs").expect_err("不是整數");
+    run!("=var-script").expect_err("失敗時不該建立腳本");
+
+    run!("e -T tv var-script --fast --var who=hs").unwrap();
+    assert_eq!("hs 2", run!("=var-script").unwrap());
+    assert_eq!("echo hs 2", run!("cat =var-script").unwrap());
+    run!("e -T tv var-script2 --fast --var who=world --var count=5").unwrap();
+    assert_eq!("world 5", run!("=var-script2").unwrap());
+}
+
+#[test]
+fn test_template_lib() {
+    let _g = setup();
+    let list = run!("template list").unwrap();
+    assert!(list.contains("sh\tdefault"), "{}", list);
+    assert!(list.contains("rb/cd\tdefault"), "{}", list);
+    run!("template show rb:cd").unwrap();
+    run!("template show sh:deploy").expect_err("不存在的具名模版");
+
+    let sh_tmpl = get_home().join(".hs_templates/sh.hbs");
+    let content = std::fs::read_to_string(&sh_tmpl).unwrap();
+    write(&sh_tmpl, format!("{}\n# customized\n", content)).unwrap();
+    assert!(run!("template list").unwrap().contains("sh\tmodified"));
+    let diff = run!("template diff-default sh").unwrap();
+    assert!(diff.contains("+# customized"), "{}", diff);
+    run!("template reset sh").unwrap();
+    assert_eq!("", run!("template diff-default sh").unwrap());
+
+    let other = get_home().join("other-templates");
+    std::fs::create_dir_all(other.join("sh")).unwrap();
+    write(other.join("sh/deploy.hbs"), "echo deploy {{name}}").unwrap();
+    write(other.join("no-such-type.hbs"), "echo ?").unwrap();
+    assert_eq!(
+        "sh/deploy",
+        run!("template import {}", other.to_string_lossy()).unwrap()
+    );
+    assert!(run!("template list").unwrap().contains("sh/deploy\tcustom"));
+    run!("template diff-default sh:deploy").expect_err("具名模版沒有內建版本");
+
+    run!("e -T sh:deploy deploy-script --fast").unwrap();
+    assert_eq!("deploy deploy-script", run!("=deploy-script").unwrap());
+
+    let single = other.join("single.hbs");
+    write(&single, "echo single").unwrap();
+    run!("template import {}", single.to_string_lossy()).expect_err("沒有叫 single 的類型");
+    run!(
+        "template import {} --as sh:deploy",
+        single.to_string_lossy()
+    )
+    .expect_err("不該覆寫既有模版");
+    run!(
+        "template import {} --as sh:deploy -f",
+        single.to_string_lossy()
+    )
+    .unwrap();
+    assert_eq!("echo single", run!("template show sh/deploy").unwrap());
+}
+
+#[test]
+fn test_expiring_tags() {
+    let _g = setup();
+    run!("e -t +wip@0m,keep exp-script | echo \"{}\"", MSG).unwrap();
+    run!("-s wip =exp-script").expect_err("過期的標籤沒被移除");
+    assert_eq!(MSG, run!("-s keep =exp-script").unwrap());
+
+    run!("mv -t +tmp@1d =exp-script").unwrap();
+    assert_eq!(MSG, run!("-s tmp =exp-script").unwrap());
+    run!("mv -t +tmp =exp-script").unwrap();
+    assert_eq!(MSG, run!("-s tmp =exp-script").unwrap());
+
+    run!("e -t ^wip@1d ?").expect_err("排除的標籤不該有存活時間");
+    run!("e -t wip@1x ?").expect_err("不合法的存活時間");
+}
+
+#[test]
+fn test_archive() {
+    let _g = setup();
+    run!("e -t pin pinned | echo pinned").unwrap();
+    run!("e idle | echo idle").unwrap();
+
+    run!("archive --days 0 --dry-run").unwrap();
+    assert_eq!("idle", run!("=idle").unwrap());
+
+    run!("archive --days 1 -y").unwrap();
+    assert_eq!("idle", run!("=idle").unwrap());
+
+    run!("archive --days 0 -y").unwrap();
+    run!("=idle").expect_err("閒置的腳本沒被封存");
+    assert_eq!("idle", run!("--timeless -s hide =idle").unwrap());
+    assert_eq!("pinned", run!("=pinned").unwrap());
+}
+
+#[test]
+fn test_tag_stat() {
+    let _g = setup();
+    run!("e -t stat-tag,hide stat/a | echo a").unwrap();
+    run!("e -t stat-tag stat/b | echo b").unwrap();
+    run!("e -t stat stat/c | echo c").unwrap();
+    run!("=stat/b").unwrap();
+    run!("tags +no-such-tag --name orphan").unwrap();
+
+    let out = run!("tags").unwrap();
+    let line = out
+        .lines()
+        .find(|l| l.trim_start().starts_with("stat-tag "))
+        .expect("沒有列出標籤統計");
+    assert!(line.contains("2 scripts (1 hidden), last run"), "{}", line);
+    let line = out.lines().find(|l| l.contains("no-such-tag")).unwrap();
+    assert!(line.ends_with("(tag selector orphan)"), "{}", line);
+    assert!(out.contains("stat/c (stat)"), "{}", out);
+    assert!(!out.contains("stat/b (stat-tag)"), "{}", out);
+}
+
 #[test]
 fn test_help() {
     let _g = setup();
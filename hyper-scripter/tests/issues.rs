@@ -109,7 +109,7 @@ use hyper_scripter::util::init_env;
 async fn init_repo() -> ScriptRepo {
     let (env, _) = init_env(true).await.unwrap();
     let group = "all,^hide".parse::<TagSelector>().unwrap().into();
-    ScriptRepo::new(Default::default(), env, &group)
+    ScriptRepo::new(Default::default(), env, &group, &Default::default())
         .await
         .unwrap()
 }